@@ -1,55 +1,258 @@
-use std::vec;
+use std::{fs, io, path::Path, vec};
 
-use bevy::{app::{App, Plugin, Startup, Update}, asset::Assets, core_pipeline::core_2d::Camera2dBundle, ecs::{component::Component, system::{Commands, Query, Res, ResMut, Resource}}, render::{mesh::Mesh, render_resource::{AsBindGroup, Buffer, ShaderRef}, renderer::{RenderDevice, RenderQueue}}, sprite::{Material2d, Material2dPlugin, MaterialMesh2dBundle}, time::{Time, Timer, TimerMode}};
+use bevy::{app::{App, AppExit, Plugin, Startup, Update}, asset::Assets, core_pipeline::core_2d::Camera2dBundle, ecs::{component::Component, system::{Commands, Query, Res, ResMut}}, render::{mesh::Mesh, render_resource::{AsBindGroup, Buffer, ShaderRef}, renderer::{RenderAdapter, RenderAdapterInfo, RenderDevice, RenderQueue}}, sprite::{Material2d, Material2dPlugin, MaterialMesh2dBundle, Mesh2dHandle}, time::Time, utils::Instant};
+use bevy_egui::{EguiPlugin, EguiSet};
 use bevy_pancam::{PanCam, PanCamPlugin};
-use biosim_core::{world::Cell, WORLD_WIDTH, WORLD_WIDTH_MULTIPLER};
+use biosim_core::{hex_grid::Grid, render::BACKGROUND_COLOR, statistics::GenerationStats, world::{get_index, Boundary, Cell, Rule, WorldCoord, WorldParams}};
 
-use crate::world::{new_random, tick};
+use crate::config::{Config, WorldSettings};
+use crate::control_panel::{block_input_under_panel, control_panel, make_room_for_panel, ControlPanel};
+use crate::inspector::{inspect, spawn_inspector_text, Inspector};
+use crate::palettes::{handle_palette_keys, Palettes};
+use crate::minimap::{jump_to_minimap, spawn_minimap, update_minimap, update_minimap_viewport};
+use crate::npy::{cells_from_states, world_shape, write_world, NpzReader};
+use crate::painting::{handle_brush_keys, paint, Brush};
+use crate::selection::{draw_selection, handle_clipboard_keys, place_pattern, select, Clipboard};
+use crate::playback::{handle_playback_keys, spawn_playback_text, update_playback_text, Playback, FAST_FORWARD_BUDGET};
+use crate::statistics::Statistics;
+use crate::svg::{export_svg, SvgStyle};
+use crate::simulator::{gpu_unsupported, Simulation};
+use crate::world::{coord_at_position, new_random, Region};
 use crate::compute_shader::BiosimComputeShader;
+use crate::rasterizer::{draw_world, mesh_size, RgbaImage, Viewport};
+use crate::recording::{Recorder, RecordingSettings};
+use crate::rle::parse_rle;
+use crate::script::Script;
+use crate::replay::{log_changes, ReplayLog, WorldChange, WorldSource};
+use crate::rpc::{handle_rpc_requests, RpcServer};
+use crate::streaming::{stream_hello, stream_world, Spectators};
 use bevy::prelude::*;
 
+/// Space between worlds running side by side, as a fraction of their size
+const WORLD_GAP: f32 = 0.05;
 
-pub struct BiosimPlugin;
+#[derive(Default)]
+pub struct BiosimPlugin {
+    pub config: Config,
+    pub recording: Option<RecordingSettings>,
+    pub script: Option<Script>,
+}
 
 impl Plugin for BiosimPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins((PanCamPlugin::default(), Material2dPlugin::<WorldMaterial>::default()))
-        .insert_resource(WorldTickTimer(Timer::from_seconds(0.5, TimerMode::Repeating)))
-        .add_systems(Startup, setup)
-        .add_systems(Update, update_world);
+        app.add_plugins((PanCamPlugin::default(), EguiPlugin, Material2dPlugin::<WorldMaterial>::default()))
+        .insert_resource(Playback::new(self.config.simulation.tick_interval, self.config.simulation.paused))
+        .insert_resource(Brush::default())
+        .insert_resource(Clipboard::default())
+        .insert_resource(Inspector::default())
+        .insert_resource(FocusedWorld::default())
+        .insert_resource(ShaderParams(self.config.world_params()))
+        .insert_resource(Palettes { palettes: self.config.palettes().unwrap_or_else(|e| panic!("Invalid config: {e}")), current: self.config.starting_palette().0 })
+        .insert_resource(ControlPanel::new(&self.config))
+        .insert_resource(self.config.clone())
+        .add_systems(Startup, (setup, spawn_playback_text, spawn_inspector_text, spawn_minimap))
+        // Keys and clicks meant for the control panel shouldn't also reach the world
+        .add_systems(PreUpdate, block_input_under_panel.after(EguiSet::BeginFrame))
+        .add_systems(Update, (focus_world, control_panel, make_room_for_panel, handle_playback_keys, handle_rpc_requests.run_if(resource_exists::<RpcServer>), log_changes, update_world, update_playback_text, inspect, exit_after_generations).chain())
+        .add_systems(Update, (handle_palette_keys, update_grid_lines, upload_params).chain())
+        .add_systems(Update, update_world_layout.after(control_panel))
+        .add_systems(Update, stream_world.after(update_world).run_if(resource_exists::<Spectators>))
+        .add_systems(Update, (jump_to_minimap, update_minimap_viewport, update_minimap).chain())
+        // Pasting has to come after painting, so the click that pastes doesn't start a stroke. Edits are logged at the
+        // generation they were made, so they have to be in before the world ticks.
+        .add_systems(Update, (handle_brush_keys, handle_clipboard_keys, select, paint, place_pattern, draw_selection).chain().before(log_changes))
+        .add_systems(Update, (take_screenshot, save_svg, save_npz));
+
+        if let Some(recording) = &self.recording {
+            let params = WorldRules::from(self.config.worlds()[0]).params(&self.config.world_params());
+            app.insert_resource(Recorder::new(recording.clone(), params));
+        }
+        if let Some(script) = &self.script {
+            app.insert_resource(script.clone());
+        }
+        if let Some(path) = &self.config.replay.log {
+            let replay_log = ReplayLog::create(path, &self.config).unwrap_or_else(|e| panic!("Couldn't create the replay log {path}: {e}"));
+            info!("Logging the run to {path}, which `--replay` can make again");
+            app.insert_resource(replay_log);
+        }
+        if let Some(port) = self.config.server.port {
+            let server = RpcServer::listen(port).unwrap_or_else(|e| panic!("Couldn't listen on port {port}: {e}"));
+            info!("Taking JSON-RPC requests on 127.0.0.1:{port}");
+            app.insert_resource(server);
+        }
+        if let Some(port) = self.config.stream.port {
+            let hello = stream_hello(self.config.world.size, self.config.worlds()[0].grid, &self.config.world_params().palette);
+            let spectators = Spectators::listen(&self.config, port, hello).unwrap_or_else(|e| panic!("Couldn't stream on port {port}: {e}"));
+            info!("Streaming the first world on 127.0.0.1:{port}");
+            app.insert_resource(spectators);
+        }
     }
 }
 
-#[derive(Resource)]
-struct WorldTickTimer(Timer);
-
-fn setup(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, mut materials: ResMut<Assets<WorldMaterial>>, render_device: Res<RenderDevice>, render_queue: Res<RenderQueue>) {
+#[allow(clippy::too_many_arguments)]
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<WorldMaterial>>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    render_adapter: Res<RenderAdapter>,
+    render_adapter_info: Res<RenderAdapterInfo>,
+    config: Res<Config>,
+    params: Res<ShaderParams>,
+    script: Option<Res<Script>>,
+    mut replay_log: Option<ResMut<ReplayLog>>,
+) {
+    // The left mouse button is left free for painting
     commands.spawn(Camera2dBundle::default())
-        .insert(PanCam::default());
+        .insert(PanCam { grab_buttons: vec![MouseButton::Right, MouseButton::Middle], ..default() });
 
-    let cells = new_random();
-    let world_component = WorldComponent(cells);
+    let gpu_unsupported = gpu_unsupported(&render_adapter, &render_adapter_info);
+    if let Some(reason) = &gpu_unsupported {
+        warn!("Simulating on the CPU, {reason}");
+    }
+    let compute = gpu_unsupported.is_none();
+    let simulation = Simulation::new(config.simulation.backend, gpu_unsupported);
 
-    let compute_shader = BiosimComputeShader::new(WORLD_WIDTH * WORLD_WIDTH, render_device.clone(), render_queue.clone());
-    compute_shader.copy_to_buffer(&world_component.0);
+    let width = config.world.size;
+    let world_meshes = WorldMeshes {
+        rect: meshes.add(Rectangle::from_size(mesh_size(Grid::Rect, width))),
+        hex: meshes.add(Rectangle::from_size(mesh_size(Grid::Hex, width))),
+    };
+    let worlds = config.worlds();
+    let widest = widest_grid(worlds.iter().map(|settings| settings.grid));
+    let starting_worlds = starting_worlds(&config, script.as_deref()).unwrap_or_else(|e| panic!("Couldn't create the worlds: {e}"));
+    for (id, (settings, (cells, ages, statistics))) in worlds.iter().zip(starting_worlds).enumerate() {
+        let generation = Generation(statistics.0.len() as u64 - 1);
+        let world_component = WorldComponent { cells, ages, changes: Vec::new() };
+        let rules = WorldRules::from(*settings);
+        if let Some(Err(e)) = replay_log.as_deref_mut().map(|log| log.write_hash(WorldId(id), generation.0, &world_component.cells, &world_component.ages)) {
+            error!("Couldn't write to the replay log, giving up on it: {e}");
+            commands.remove_resource::<ReplayLog>();
+            replay_log = None;
+        }
 
-    let world_material = WorldMaterial { buffer: compute_shader.get_cells_buffer() };
-    commands.insert_resource(compute_shader);
+        let compute_shader = BiosimComputeShader::new(rules.params(&params.0), render_device.clone(), render_queue.clone(), compute);
+        compute_shader.copy_to_buffer(&world_component.cells);
+        compute_shader.copy_ages_to_buffer(&world_component.ages);
 
-    commands.spawn(MaterialMesh2dBundle {
-        mesh: meshes.add(Rectangle::from_size(Vec2 { x: WORLD_WIDTH as f32 * WORLD_WIDTH_MULTIPLER, y: WORLD_WIDTH as f32 })).into(),
-        material: materials.add(world_material),
-        ..default()
-    }).insert(world_component);
+        let world_material = WorldMaterial { buffer: compute_shader.get_cells_buffer(), params: compute_shader.get_params_buffer(), ages: compute_shader.get_ages_buffer() };
+        commands.spawn(MaterialMesh2dBundle {
+            mesh: world_meshes.get(rules.grid).clone().into(),
+            material: materials.add(world_material),
+            transform: Transform::from_translation(world_center(widest, width, id, worlds.len()).extend(0.0)),
+            ..default()
+        }).insert((world_component, compute_shader, rules, WorldId(id), generation, statistics));
+    }
+    commands.insert_resource(simulation);
+    commands.insert_resource(world_meshes);
 } 
 
+/// Where the mesh of the world `id` of `count` goes. Worlds are laid out in rows as close to a square as they fit,
+/// starting with the first one in the middle of the screen and going right and down from there. They're spaced out by
+/// the mesh size of the `widest` grid any of them has, so none overlap.
+pub fn world_center(widest: Grid, width: usize, id: usize, count: usize) -> Vec2 {
+    let columns = (count as f32).sqrt().ceil() as usize;
+    let spacing = mesh_size(widest, width) * (1.0 + WORLD_GAP);
+    Vec2::new((id % columns) as f32 * spacing.x, -((id / columns) as f32) * spacing.y)
+}
+
+/// The grid with the widest mesh out of `grids`, see `world_center`
+fn widest_grid(grids: impl IntoIterator<Item = Grid>) -> Grid {
+    grids.into_iter().max_by(|a, b| a.width_multiplier().total_cmp(&b.width_multiplier())).unwrap_or(Grid::Rect)
+}
+
+/// A mesh for each grid, since hex worlds are wider than rect worlds of the same size
+#[derive(Resource)]
+struct WorldMeshes {
+    rect: Handle<Mesh>,
+    hex: Handle<Mesh>,
+}
+
+impl WorldMeshes {
+    fn get(&self, grid: Grid) -> &Handle<Mesh> {
+        match grid {
+            Grid::Rect => &self.rect,
+            Grid::Hex => &self.hex,
+        }
+    }
+}
+
+/// Gives worlds whose grid changed the mesh of their new grid, and lays the worlds out again in case that changed how
+/// far apart they have to be
+fn update_world_layout(world_meshes: Res<WorldMeshes>, mut worlds: Query<(&WorldId, &WorldRules, &mut Mesh2dHandle, &mut Transform)>, config: Res<Config>) {
+    let widest = widest_grid(worlds.iter().map(|(_, rules, _, _)| rules.grid));
+    let count = worlds.iter().count();
+    for (id, rules, mut mesh, mut transform) in &mut worlds {
+        // Only touch what changed, so the meshes and transforms aren't marked as changed every frame
+        let grid_mesh = world_meshes.get(rules.grid);
+        if mesh.0 != *grid_mesh {
+            mesh.0 = grid_mesh.clone();
+        }
+        let center = world_center(widest, config.world.size, id.0, count).extend(0.0);
+        if transform.translation != center {
+            transform.translation = center;
+        }
+    }
+}
+
 #[derive(Component)]
-struct WorldComponent(Vec<Cell>);
+pub struct WorldComponent {
+    pub cells: Vec<Cell>,
+    /// How many generations each cell has been in its state. Like the cells, this is only kept up to date while the
+    /// world runs on the CPU, see `Simulator`.
+    pub ages: Vec<u32>,
+    /// Edits and replacements since `log_changes` last ran, which writes them to the replay log
+    pub changes: Vec<WorldChange>,
+}
+
+/// Which of the worlds in `Config::worlds` this is
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct WorldId(pub usize);
+
+/// What sets a world apart from the others running next to it
+#[derive(Component, Clone, Copy, Debug)]
+pub struct WorldRules {
+    pub rule: Rule,
+    pub boundary: Boundary,
+    pub grid: Grid,
+}
+
+impl From<WorldSettings> for WorldRules {
+    fn from(settings: WorldSettings) -> Self {
+        WorldRules { rule: settings.rule, boundary: settings.boundary, grid: settings.grid }
+    }
+}
+
+impl WorldRules {
+    /// The params this world's shaders run with, which are `shared` by every world apart from the rules
+    pub fn params(&self, shared: &WorldParams) -> WorldParams {
+        WorldParams { rule: self.rule, boundary: self.boundary, grid: self.grid, ..*shared }
+    }
+}
+
+/// What the shaders currently draw with, which changes with the camera's zoom and the chosen palette. Changes are
+/// uploaded by `upload_params`. Every world shares these, apart from the rule, boundary and grid in its `WorldRules`.
+#[derive(Resource)]
+pub struct ShaderParams(pub WorldParams);
+
+/// How many times the world has been ticked since it was created
+#[derive(Component)]
+pub struct Generation(pub u64);
+
+/// The world closest to the middle of the screen, which the minimap, the control panel and exports of a single world
+/// work on
+#[derive(Resource, Default)]
+pub struct FocusedWorld(pub Option<Entity>);
 
 #[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
 struct WorldMaterial {
     #[storage(0, read_only, buffer)]
     buffer: Buffer,
+    #[storage(1, read_only, buffer)]
+    params: Buffer,
+    #[storage(2, read_only, buffer)]
+    ages: Buffer,
 }
 
 impl Material2d for WorldMaterial {
@@ -58,34 +261,400 @@ impl Material2d for WorldMaterial {
     }
 }
 
+/// The world under `position` in world space, along with the cell of it there
+pub fn world_at_position<'a>(worlds: impl IntoIterator<Item = (Entity, &'a Transform, &'a WorldRules)>, config: &Config, position: Vec2) -> Option<(Entity, WorldCoord)> {
+    worlds.into_iter().find_map(|(entity, transform, rules)| {
+        coord_at_position(rules.grid, config.world.size, position - transform.translation.truncate()).map(|coord| (entity, coord))
+    })
+}
+
+fn focus_world(cameras: Query<&Transform, With<Camera2d>>, worlds: Query<(Entity, &Transform, &WorldRules)>, config: Res<Config>, mut focused: ResMut<FocusedWorld>) {
+    let Ok(camera_transform) = cameras.get_single() else {
+        return;
+    };
+    let center = camera_transform.translation.truncate();
+    // Measured in mesh sizes along whichever axis is further, so the world whose mesh is in the middle of the screen
+    // always wins
+    let distance = |(_, transform, rules): &(Entity, &Transform, &WorldRules)| {
+        ((transform.translation.truncate() - center) / mesh_size(rules.grid, config.world.size)).abs().max_element()
+    };
+    let closest = worlds.iter().min_by(|a, b| distance(a).total_cmp(&distance(b))).map(|(entity, _, _)| entity);
+    if focused.0 != closest {
+        focused.0 = closest;
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn update_world(
+    mut commands: Commands,
     mut materials: ResMut<Assets<WorldMaterial>>,
-    mut timer: ResMut<WorldTickTimer>,
+    mut playback: ResMut<Playback>,
     time: Res<Time>,
-    mut world_query: Query<(&mut WorldComponent, &Handle<WorldMaterial>)>,
-    mut compute_shader: ResMut<BiosimComputeShader>,
+    mut world_query: Query<(Entity, &WorldId, &mut WorldComponent, &mut Generation, &mut Statistics, &mut BiosimComputeShader, &WorldRules, &Handle<WorldMaterial>)>,
+    mut recorder: Option<ResMut<Recorder>>,
+    mut script: Option<Res<Script>>,
+    mut replay_log: Option<ResMut<ReplayLog>>,
+    mut inspector: ResMut<Inspector>,
+    simulation: Res<Simulation>,
+    config: Res<Config>,
+    params: Res<ShaderParams>,
 ) {
-    if !timer.0.tick(time.delta()).just_finished() {
+    let mut due = playback.generations_due(time.delta());
+    // Worlds stop at `simulation.generations`, which `exit_after_generations` quits at
+    if let Some(last) = config.simulation.generations {
+        let generation = world_query.iter().map(|(_, _, _, generation, ..)| generation.0).max().unwrap_or(0);
+        due = due.min(last.saturating_sub(generation).try_into().unwrap_or(u32::MAX));
+    }
+    if due == 0 {
         return;
     }
     let _true_update_world_span = info_span!("update_world_past_timer").entered();
+    let deadline = Instant::now() + FAST_FORWARD_BUDGET;
 
-    for (mut world_component, mesh_handle) in &mut world_query {
+    // Several generations can be due in a single frame when fast forwarding or ticking faster than the frame rate.
+    // Every world runs each generation before any runs the next, so they stay in lockstep.
+    let mut ticked = 0;
+    let mut stopped = false;
+    while ticked < due && !stopped && (ticked == 0 || Instant::now() < deadline) {
+        for (entity, id, mut world_component, mut generation, mut statistics, mut compute_shader, rules, _) in &mut world_query {
+            // Only the first world is recorded
+            let mut recording_failed = false;
+            if let Some(recorder) = recorder.as_deref_mut().filter(|recorder| id.0 == 0 && recorder.wants(generation.0)) {
+                let _record_span = info_span!("recording").entered();
+                let whole = Region::whole(config.world.size);
+                let (cells, ages) = (simulation.read_region(&world_component, &compute_shader, whole), simulation.read_region_ages(&world_component, &compute_shader, whole));
+                let result = recorder.capture(&cells, &ages, &statistics);
+
+                match result {
+                    Err(e) => {
+                        error!("Recording failed, giving up on it: {e}");
+                        commands.remove_resource::<Recorder>();
+                        recording_failed = true;
+                    }
+                    Ok(()) if recorder.is_finished() => info!("Saved recording to {}", recorder.path().display()),
+                    Ok(()) => {}
+                }
+            }
+            if recording_failed {
+                recorder = None;
+            }
+
+            let tick_span = info_span!("ticking").entered();
+
+            let stats = simulation.tick(&mut world_component, &mut compute_shader, &rules.params(&params.0));
+            generation.0 += 1;
+            statistics.0.push(stats);
+
+            let mut logging_failed = false;
+            if let Some(log) = replay_log.as_deref_mut().filter(|log| log.wants_hash(generation.0)) {
+                let whole = Region::whole(config.world.size);
+                let (cells, ages) = (simulation.read_region(&world_component, &compute_shader, whole), simulation.read_region_ages(&world_component, &compute_shader, whole));
+                if let Err(e) = log.write_hash(*id, generation.0, &cells, &ages) {
+                    error!("Couldn't write to the replay log, giving up on it: {e}");
+                    commands.remove_resource::<ReplayLog>();
+                    logging_failed = true;
+                }
+            }
+            if logging_failed {
+                replay_log = None;
+            }
+
+            // The script's hook pauses after the generation it asked to stop at, once every world has run it
+            let mut hook_failed = false;
+            match script.as_deref().map(|script| script.on_generation(*id, generation.0, &stats)) {
+                Some(Err(e)) => {
+                    error!("The script's hook failed, giving up on it: {e}");
+                    commands.remove_resource::<Script>();
+                    hook_failed = true;
+                }
+                Some(Ok(true)) => stopped = true,
+                Some(Ok(false)) | None => {}
+            }
+            if hook_failed {
+                script = None;
+            }
+
+            if let Some((_, coord)) = inspector.hovered.filter(|(hovered, _)| *hovered == entity) {
+                inspector.record(generation.0, simulation.read_cell(&world_component, &compute_shader, coord));
+            }
+
+            tick_span.exit();
+        }
+        ticked += 1;
+    }
+
+    for (_, _, world_component, _, _, compute_shader, _, mesh_handle) in &world_query {
         let Some(world_material) = materials.get_mut(mesh_handle.id()) else {
-            break;
+            continue;
         };
+        simulation.present(world_component, compute_shader);
+        world_material.buffer = compute_shader.get_cells_buffer();
+        world_material.ages = compute_shader.get_ages_buffer();
+    }
+    playback.count_generations(ticked);
+    if stopped {
+        playback.paused = true;
+    }
+}
 
-        let tick_span = info_span!("ticking").entered();
+/// Quits once the worlds have run `simulation.generations` generations
+fn exit_after_generations(generations: Query<&Generation>, config: Res<Config>, mut exit: EventWriter<AppExit>) {
+    let Some(last) = config.simulation.generations else {
+        return;
+    };
+    if generations.iter().any(|generation| generation.0 >= last) {
+        info!("Ran {last} generations, exiting");
+        exit.send(AppExit);
+    }
+}
 
-        if cfg!(feature = "cpu") {
-            world_component.0 = tick(&world_component.0);
-            compute_shader.copy_to_buffer(&world_component.0);
-        } else {
-            compute_shader.dispatch();
-            compute_shader.swap_buffers();
-            world_material.buffer = compute_shader.get_cells_buffer();
-        };
+/// Tells the fragment shader how far the camera is zoomed in, which decides how strongly grid lines are drawn
+fn update_grid_lines(cameras: Query<&OrthographicProjection, With<Camera2d>>, mut params: ResMut<ShaderParams>) {
+    let Ok(projection) = cameras.get_single() else {
+        return;
+    };
+    // Only touch the params when the zoom changed, so they aren't uploaded every frame
+    let pixels_per_unit = 1.0 / projection.scale;
+    if params.0.grid_lines.pixels_per_unit != pixels_per_unit {
+        params.0.grid_lines.pixels_per_unit = pixels_per_unit;
+    }
+}
+
+/// Uploads the params to every world when they changed, along with the rules of the worlds whose rules changed
+fn upload_params(params: Res<ShaderParams>, worlds: Query<(&BiosimComputeShader, Ref<WorldRules>)>) {
+    for (compute_shader, rules) in &worlds {
+        if params.is_changed() || rules.is_changed() {
+            compute_shader.write_params(&rules.params(&params.0));
+        }
+    }
+}
+
+/// Saves what the camera currently sees to a png when F12 is pressed, with every world in view. This draws on the CPU
+/// so it doesn't depend on the window's surface.
+#[allow(clippy::too_many_arguments)]
+fn take_screenshot(
+    keys: Res<ButtonInput<KeyCode>>,
+    windows: Query<&Window>,
+    cameras: Query<(&Transform, &OrthographicProjection), With<Camera2d>>,
+    world_query: Query<(&WorldComponent, &Generation, &BiosimComputeShader, &WorldRules, &Transform)>,
+    simulation: Res<Simulation>,
+    config: Res<Config>,
+    params: Res<ShaderParams>,
+) {
+    if !keys.just_pressed(KeyCode::F12) {
+        return;
+    }
+    let (Ok(window), Ok((camera_transform, projection))) = (windows.get_single(), cameras.get_single()) else {
+        return;
+    };
+
+    let (width, height) = (window.width() as u32, window.height() as u32);
+    let center = camera_transform.translation.truncate();
+    let view = Rect::from_center_size(center, Vec2::new(window.width(), window.height()) * projection.scale);
+    let mut image = RgbaImage::filled(width, height, BACKGROUND_COLOR);
+    let mut generation = 0;
+    for (world_component, world_generation, compute_shader, rules, transform) in &world_query {
+        let world_center = transform.translation.truncate();
+        if view.intersect(Rect::from_center_size(world_center, mesh_size(rules.grid, config.world.size))).is_empty() {
+            continue;
+        }
+        let whole = Region::whole(config.world.size);
+        let (cells, ages) = (simulation.read_region(world_component, compute_shader, whole), simulation.read_region_ages(world_component, compute_shader, whole));
+        let viewport = Viewport { center: center - world_center, units_per_pixel: projection.scale };
+        draw_world(&mut image, &cells, &ages, &rules.params(&params.0), viewport);
+        generation = generation.max(world_generation.0);
+    }
+
+    let path = format!("screenshot-{generation}.png");
+    match image.save_png(path.as_ref()) {
+        Ok(()) => info!("Saved screenshot to {path}"),
+        Err(e) => error!("Couldn't save screenshot to {path}: {e}"),
+    }
+}
+
+/// Saves the selection, or without one the part of the focused world the camera currently sees, as an svg when F11 is
+/// pressed
+#[allow(clippy::too_many_arguments)]
+fn save_svg(
+    keys: Res<ButtonInput<KeyCode>>,
+    windows: Query<&Window>,
+    cameras: Query<(&Transform, &OrthographicProjection), With<Camera2d>>,
+    world_query: Query<(&WorldComponent, &WorldId, &Generation, &BiosimComputeShader, &WorldRules, &Transform)>,
+    focused: Res<FocusedWorld>,
+    clipboard: Res<Clipboard>,
+    simulation: Res<Simulation>,
+    config: Res<Config>,
+    params: Res<ShaderParams>,
+) {
+    if !keys.just_pressed(KeyCode::F11) {
+        return;
+    }
+    let selected = clipboard.selection.and_then(|(entity, selection)| world_query.get(entity).ok().map(|world| (world, Some(selection))));
+    let Some(((world_component, id, generation, compute_shader, rules, transform), selection)) = selected.or_else(|| focused.0.and_then(|entity| world_query.get(entity).ok()).map(|world| (world, None))) else {
+        return;
+    };
+
+    let (grid, width) = (rules.grid, config.world.size);
+    let region = match selection {
+        Some(selection) => selection.bounds(grid, width),
+        None => {
+            let (Ok(window), Ok((camera_transform, projection))) = (windows.get_single(), cameras.get_single()) else {
+                return;
+            };
+            let mesh_size = mesh_size(grid, width);
+            let half_view = Vec2 { x: window.width(), y: window.height() } * projection.scale / 2.0;
+            let to_uv = |position: Vec2| {
+                let uv = position / mesh_size + 0.5;
+                (uv.x, uv.y)
+            };
+            let center = camera_transform.translation.truncate() - transform.translation.truncate();
+            let Some(region) = Region::covering(grid, width, to_uv(center - half_view), to_uv(center + half_view)) else {
+                return;
+            };
+            region
+        }
+    };
+
+    let cells = simulation.read_region(world_component, compute_shader, Region::whole(width));
+    let path = format!("{}.svg", file_stem(*id, &config, generation.0));
+    let style = SvgStyle::new(&params.0.palette, &params.0.grid_lines, grid, config.svg.dead_cells);
+    let contains = |coord| selection.map_or(true, |selection| selection.contains(grid, coord));
+    match std::fs::write(&path, export_svg(&cells, width, grid, region, contains, &style)) {
+        Ok(()) => info!("Saved svg to {path}"),
+        Err(e) => error!("Couldn't save svg to {path}: {e}"),
+    }
+}
+
+/// Saves the focused world's current generation and the statistics so far to an npz file when F10 is pressed
+fn save_npz(
+    keys: Res<ButtonInput<KeyCode>>,
+    world_query: Query<(&WorldComponent, &WorldId, &Generation, &Statistics, &BiosimComputeShader)>,
+    focused: Res<FocusedWorld>,
+    simulation: Res<Simulation>,
+    config: Res<Config>,
+) {
+    if !keys.just_pressed(KeyCode::F10) {
+        return;
+    }
+    let Some((world_component, id, generation, statistics, compute_shader)) = focused.0.and_then(|entity| world_query.get(entity).ok()) else {
+        return;
+    };
+
+    let path = format!("{}.npz", file_stem(*id, &config, generation.0));
+    match write_npz(path.as_ref(), world_component, statistics, compute_shader, &simulation, &config) {
+        Ok(()) => info!("Saved npz to {path}"),
+        Err(e) => error!("Couldn't save npz to {path}: {e}"),
+    }
+}
+
+/// Exports are named like `world-<generation>`, or `world<n>-<generation>` for the n-th of several worlds
+pub fn file_stem(id: WorldId, config: &Config, generation: u64) -> String {
+    if config.worlds().len() == 1 {
+        format!("world-{generation}")
+    } else {
+        format!("world{}-{generation}", id.0 + 1)
+    }
+}
+
+/// Saves the current generation's cells and ages along with the statistics so far, in the format `load_npz` reads
+pub fn write_npz(path: &Path, world_component: &WorldComponent, statistics: &Statistics, compute_shader: &BiosimComputeShader, simulation: &Simulation, config: &Config) -> io::Result<()> {
+    let whole = Region::whole(config.world.size);
+    let (cells, ages) = (simulation.read_region(world_component, compute_shader, whole), simulation.read_region_ages(world_component, compute_shader, whole));
+    write_world(path, config.world.size, &cells, &ages, statistics)
+}
+
+/// Reads a world saved by `write_npz` or F10, which has to be as large as this one. Missing ages start over at 0, and
+/// missing statistics start over at generation 0.
+pub fn load_npz(path: &Path, config: &Config) -> io::Result<(Vec<Cell>, Vec<u32>, Statistics)> {
+    let mut npz = NpzReader::open(path)?;
+    let shape = world_shape(config.world.size);
+    let states = npz.read_array_of_shape::<u8>("cells", &shape)?;
+    let cells = cells_from_states(&states)?;
+    let ages = if npz.contains("ages") { npz.read_array_of_shape::<u32>("ages", &shape)? } else { vec![0; cells.len()] };
+    let statistics = npz.read_statistics()?.filter(|statistics| !statistics.0.is_empty()).unwrap_or_else(|| Statistics(vec![GenerationStats::initial(&cells)]));
+    Ok((cells, ages, statistics))
+}
 
-        tick_span.exit();
-  }
+/// The cells, ages and statistics of every world in `Config::worlds` before the first tick, which are random unless
+/// `world.pattern` is set or the script defines `setup`
+#[allow(clippy::type_complexity)]
+pub fn starting_worlds(config: &Config, script: Option<&Script>) -> io::Result<Vec<(Vec<Cell>, Vec<u32>, Statistics)>> {
+    let pattern = config.world.pattern.as_ref()
+        .map(|path| load_pattern(path, config).map_err(|e| io::Error::new(e.kind(), format!("couldn't load {path}: {e}"))))
+        .transpose()?;
+    // The script lays out every world the same way, so it only needs to run once
+    let scripted = match (&pattern, script) {
+        (None, Some(script)) => script.starting_cells(config.world.size).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        _ => None,
+    };
+    Ok(config.worlds().iter().map(|settings| pattern.clone().unwrap_or_else(|| {
+        let cells = scripted.clone().unwrap_or_else(|| new_random(config.world.size, settings.density, settings.seed));
+        let (ages, statistics) = (vec![0; cells.len()], Statistics(vec![GenerationStats::initial(&cells)]));
+        (cells, ages, statistics)
+    })).collect())
+}
+
+/// The cells every world starts out as when `world.pattern` is set, either an `.rle` pattern in the middle of an
+/// otherwise empty world or a world saved as `.npz`
+pub fn load_pattern(path: &str, config: &Config) -> io::Result<(Vec<Cell>, Vec<u32>, Statistics)> {
+    if path.ends_with(".npz") {
+        return load_npz(path.as_ref(), config);
+    }
+    let pattern = parse_rle(&fs::read_to_string(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let width = config.world.size;
+    let mut cells = vec![Cell::Dead; width * width];
+    for (coord, cell) in pattern.placed_at(WorldCoord { x: width / 2, y: width / 2 }, width) {
+        cells[get_index(coord, width)] = cell;
+    }
+    let (ages, statistics) = (vec![0; cells.len()], Statistics(vec![GenerationStats::initial(&cells)]));
+    Ok((cells, ages, statistics))
+}
+
+/// Swaps out the whole world for `cells` on whichever backend runs it, picking up at the generation after the last of
+/// `statistics`. The replay log makes the world again from `source`.
+pub fn replace_world(
+    world_component: &mut WorldComponent,
+    generation: &mut Generation,
+    statistics: &mut Statistics,
+    compute_shader: &BiosimComputeShader,
+    (cells, ages, new_statistics): (Vec<Cell>, Vec<u32>, Statistics),
+    source: WorldSource,
+) {
+    compute_shader.copy_to_buffer(&cells);
+    compute_shader.copy_ages_to_buffer(&ages);
+    (world_component.cells, world_component.ages) = (cells, ages);
+    world_component.changes.push(WorldChange::Replace { from: generation.0, source });
+    generation.0 = new_statistics.0.len() as u64 - 1;
+    *statistics = new_statistics;
+}
+
+/// Replaces every cell in `region` with what `edit` returns for it, on whichever simulator runs the world. Cells that
+/// change start over at age 0. Only `region` is uploaded to the GPU.
+pub fn edit_cells(world_component: &mut WorldComponent, compute_shader: &BiosimComputeShader, simulation: &Simulation, region: Region, mut edit: impl FnMut(WorldCoord, Cell) -> Cell) {
+    let mut cells = simulation.read_region(world_component, compute_shader, region);
+    let mut ages = simulation.read_region_ages(world_component, compute_shader, region);
+    for ((coord, cell), age) in region.coords().zip(&mut cells).zip(&mut ages) {
+        let edited = edit(coord, *cell);
+        if edited != *cell {
+            (*cell, *age) = (edited, 0);
+        }
+    }
+    simulation.write_region(world_component, compute_shader, region, &cells, &ages);
+    world_component.changes.push(WorldChange::Edit { region, cells });
+}
+
+/// Sets the cells at `coords`, which go row by row, to `cell` and starts them over at age 0 whatever they were before,
+/// so unlike `edit_cells` nothing has to be read back. Each run of neighbouring cells in a row is written on its own.
+pub fn fill_cells(world_component: &mut WorldComponent, compute_shader: &BiosimComputeShader, simulation: &Simulation, coords: impl IntoIterator<Item = WorldCoord>, cell: Cell) {
+    let mut runs: Vec<Region> = Vec::new();
+    for coord in coords {
+        match runs.last_mut() {
+            Some(run) if run.max.y == coord.y && run.max.x + 1 == coord.x => run.max.x = coord.x,
+            _ => runs.push(Region { min: coord, max: coord }),
+        }
+    }
+    for run in &runs {
+        simulation.write_region(world_component, compute_shader, *run, &vec![cell; run.width()], &vec![0; run.width()]);
+    }
+    world_component.changes.push(WorldChange::Fill { runs, cell });
 }
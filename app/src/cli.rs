@@ -0,0 +1,159 @@
+use clap::Parser;
+
+use crate::{config::Config, recording::{env_overrides, range_overrides}};
+
+/// Runs Life-like cellular automata on the GPU. Settings come from `biosim.toml`, which the flags below override.
+#[derive(Parser, Debug)]
+#[command(name = "biosim-rust", version, about)]
+pub struct Cli {
+    /// Config file to load instead of `biosim.toml`
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<String>,
+    /// Width and height of the world in cells, `world.size`
+    #[arg(long, value_name = "CELLS")]
+    pub size: Option<usize>,
+    /// Makes the starting cells the same every run, `world.seed`
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Rule like `B3/S23`, `simulation.rule`
+    #[arg(long)]
+    pub rule: Option<String>,
+    /// Square or hexagonal cells, `world.grid`
+    #[arg(long, value_parser = ["rect", "hex"])]
+    pub grid: Option<String>,
+    /// Whether generations run on the CPU or in the compute shader, `simulation.backend`
+    #[arg(long, value_parser = ["cpu", "gpu"])]
+    pub backend: Option<String>,
+    /// Starting pattern, an `.rle` file placed in the middle of an empty world or an `.npz` saved by the app,
+    /// `world.pattern`
+    #[arg(long, value_name = "PATH")]
+    pub pattern: Option<String>,
+    /// Script with a rule, a starting pattern or a hook run every generation, `simulation.script`
+    #[arg(long, value_name = "PATH")]
+    pub script: Option<String>,
+    /// Seconds between generations, `simulation.tick_interval`
+    #[arg(long, value_name = "SECONDS")]
+    pub tick_interval: Option<f32>,
+    /// Start paused, `simulation.paused`
+    #[arg(long)]
+    pub paused: bool,
+    /// Exit once this many generations have run, `simulation.generations`
+    #[arg(long, value_name = "N")]
+    pub generations: Option<u64>,
+    /// Run on the CPU without a window until `--generations`, writing snapshots, frames and statistics to `--output`
+    #[arg(long)]
+    pub headless: bool,
+    /// Directory headless runs write to, `headless.output`
+    #[arg(long, value_name = "DIR")]
+    pub output: Option<String>,
+    /// Save the worlds every N generations in headless runs, `headless.snapshot_every`
+    #[arg(long, value_name = "N")]
+    pub snapshot_every: Option<u64>,
+    /// Render the worlds to png every N generations in headless runs, `headless.frame_every`
+    #[arg(long, value_name = "N")]
+    pub frame_every: Option<u64>,
+    /// Take JSON-RPC commands from other programs on this port of localhost, `server.port`
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// Stream the first world to `biosim-viewer` on this port of localhost, `stream.port`
+    #[arg(long, value_name = "PORT")]
+    pub stream_port: Option<u16>,
+    /// Log the seeds, edits and rule changes of the run to this file, `replay.log`
+    #[arg(long, value_name = "PATH")]
+    pub replay_log: Option<String>,
+    /// Record the first world to a `.gif`, `.png`, `.apng`, `.npy` or `.npz` file, `recording.path`
+    #[arg(long, value_name = "PATH")]
+    pub record: Option<String>,
+    /// Generations to record, inclusive, `recording.first_generation` and `recording.last_generation`
+    #[arg(long, value_name = "FIRST..LAST")]
+    pub record_range: Option<String>,
+    /// Only record every N generations, `recording.every`
+    #[arg(long, value_name = "N")]
+    pub record_every: Option<u64>,
+    /// Size of a recorded cell in pixels, `recording.scale`
+    #[arg(long, value_name = "PIXELS")]
+    pub record_scale: Option<u32>,
+    /// Draw recorded cells as squares or hexagons, `recording.style`
+    #[arg(long, value_parser = ["pixels", "hex"])]
+    pub record_style: Option<String>,
+    /// Make a logged run again without a window, checking it against the log and saving the worlds to `--output`. Other
+    /// flags are ignored, the config comes from the log.
+    #[arg(long, value_name = "PATH", conflicts_with = "replay_log")]
+    pub replay: Option<String>,
+    /// Override any key of the config, e.g. `--set colors.alive=#ff0000`. Applied after the flags above.
+    #[arg(long, value_name = "KEY=VALUE")]
+    pub set: Vec<String>,
+}
+
+impl Cli {
+    /// The config file with the `BIOSIM_RECORD*` environment variables and then the flags applied on top of it
+    pub fn config(&self) -> Result<Config, String> {
+        let quoted = |value: &str| toml::Value::String(value.to_string()).to_string();
+        let mut overrides = env_overrides()?;
+        if let Some(size) = self.size {
+            overrides.push(format!("world.size={size}"));
+        }
+        if let Some(seed) = self.seed {
+            overrides.push(format!("world.seed={seed}"));
+        }
+        if let Some(rule) = &self.rule {
+            overrides.push(format!("simulation.rule={}", quoted(rule)));
+        }
+        if let Some(grid) = &self.grid {
+            overrides.push(format!("world.grid={grid}"));
+        }
+        if let Some(backend) = &self.backend {
+            overrides.push(format!("simulation.backend={backend}"));
+        }
+        if let Some(pattern) = &self.pattern {
+            overrides.push(format!("world.pattern={}", quoted(pattern)));
+        }
+        if let Some(script) = &self.script {
+            overrides.push(format!("simulation.script={}", quoted(script)));
+        }
+        if let Some(tick_interval) = self.tick_interval {
+            overrides.push(format!("simulation.tick_interval={tick_interval:?}"));
+        }
+        if self.paused {
+            overrides.push("simulation.paused=true".to_string());
+        }
+        if let Some(generations) = self.generations {
+            overrides.push(format!("simulation.generations={generations}"));
+        }
+        if let Some(output) = &self.output {
+            overrides.push(format!("headless.output={}", quoted(output)));
+        }
+        if let Some(snapshot_every) = self.snapshot_every {
+            overrides.push(format!("headless.snapshot_every={snapshot_every}"));
+        }
+        if let Some(frame_every) = self.frame_every {
+            overrides.push(format!("headless.frame_every={frame_every}"));
+        }
+        if let Some(port) = self.port {
+            overrides.push(format!("server.port={port}"));
+        }
+        if let Some(stream_port) = self.stream_port {
+            overrides.push(format!("stream.port={stream_port}"));
+        }
+        if let Some(replay_log) = &self.replay_log {
+            overrides.push(format!("replay.log={}", quoted(replay_log)));
+        }
+        if let Some(record) = &self.record {
+            overrides.push(format!("recording.path={}", quoted(record)));
+        }
+        if let Some(range) = &self.record_range {
+            overrides.extend(range_overrides("--record-range", range)?);
+        }
+        if let Some(every) = self.record_every {
+            overrides.push(format!("recording.every={every}"));
+        }
+        if let Some(scale) = self.record_scale {
+            overrides.push(format!("recording.scale={scale}"));
+        }
+        if let Some(style) = &self.record_style {
+            overrides.push(format!("recording.style={style}"));
+        }
+        overrides.extend(self.set.iter().cloned());
+        Config::load(self.config.as_deref(), &overrides)
+    }
+}
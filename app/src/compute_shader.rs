@@ -1,50 +1,135 @@
-use std::{mem, num::NonZero, sync::mpsc::channel};
+use std::{mem, num::NonZero, ops::RangeBounds, sync::mpsc::channel};
 
-use bevy::{ecs::system::Resource, log::info_span, render::{render_resource::{Buffer, ComputePipeline}, renderer::{RenderDevice, RenderQueue}}};
-use biosim_core::{world::Cell, WORLD_WIDTH};
-use ndarray::{ArrayBase, ArrayView, Dim, OwnedRepr, SliceArg};
+use bevy::{ecs::component::Component, log::info_span, render::{render_resource::{Buffer, BufferInitDescriptor, ComputePipeline}, renderer::{RenderDevice, RenderQueue}}};
+use biosim_core::{minimap::MinimapParams, statistics::GenerationStats, world::{get_index, Cell, WorldCoord, WorldParams}};
+use bytemuck::Pod;
 use wgpu::{BindGroup, BindGroupLayoutEntry, BufferDescriptor, PipelineLayoutDescriptor, ShaderStages};
 
-#[derive(Resource)]
+use crate::world::Region;
+
+/// The buffers and pipelines of a single world
+#[derive(Component)]
 pub struct BiosimComputeShader {
     render_device: RenderDevice,
     render_queue: RenderQueue,
-    pipeline: ComputePipeline,
-    bind_group: BindGroup,
+    /// Missing when the worlds are simulated on the CPU because the adapter can't run compute shaders, in which case the
+    /// buffers are only drawn from
+    pipelines: Option<ComputePipelines>,
     staging_input_buffer: Buffer,
     input_buffer: Buffer,
     output_buffer: Buffer,
+    /// How many generations each cell has been in its state, swapped along with the cells
+    age_input_buffer: Buffer,
+    age_output_buffer: Buffer,
     staging_output_buffer: Buffer,
+    stats_buffer: Buffer,
+    staging_stats_buffer: Buffer,
+    params_buffer: Buffer,
+    staging_cell_buffer: Buffer,
+    minimap_params: MinimapParams,
+    minimap_params_buffer: Buffer,
+    populations_buffer: Buffer,
+    staging_populations_buffer: Buffer,
+    width: usize,
+}
+
+struct ComputePipelines {
+    main: ComputePipeline,
+    bind_group: BindGroup,
+    minimap: ComputePipeline,
 }
 
 impl BiosimComputeShader {
-    pub fn dispatch(&self) {
+    /// Runs a single generation, returning its statistics
+    pub fn dispatch(&self) -> GenerationStats {
         const THREADS_PER_WORKGROUP: u32 = 32;
 
+        let pipelines = self.pipelines();
         let mut encoder = self.render_device.create_command_encoder(&Default::default());
+        encoder.clear_buffer(&self.stats_buffer, 0, None);
         {
             let mut pass = encoder.begin_compute_pass(&Default::default());
-            pass.set_pipeline(&self.pipeline);
-            pass.set_bind_group(0, &self.bind_group, &[]);
-            pass.dispatch_workgroups(WORLD_WIDTH as u32 / THREADS_PER_WORKGROUP, WORLD_WIDTH as u32 / THREADS_PER_WORKGROUP, 1);
+            pass.set_pipeline(&pipelines.main);
+            pass.set_bind_group(0, &pipelines.bind_group, &[]);
+            let workgroups = (self.width as u32).div_ceil(THREADS_PER_WORKGROUP);
+            pass.dispatch_workgroups(workgroups, workgroups, 1);
         }
+        encoder.copy_buffer_to_buffer(&self.stats_buffer, 0, &self.staging_stats_buffer, 0, self.stats_buffer.size());
 
         let gpu_execution_span = info_span!("gpu").entered();
         self.render_queue.submit([encoder.finish()]);        
         self.render_device.poll(wgpu::Maintain::Wait);
         gpu_execution_span.exit();
+
+        self.map_buffer(&self.staging_stats_buffer, wgpu::MapMode::Read);
+        let stats = *bytemuck::from_bytes::<GenerationStats>(&self.staging_stats_buffer.slice(..).get_mapped_range());
+        self.staging_stats_buffer.unmap();
+        stats
+    }
+
+    /// Counts the live cells in each block of the minimap (see `MinimapParams`) without reading back the world
+    pub fn minimap_populations(&self) -> Vec<u32> {
+        const THREADS_PER_WORKGROUP: u32 = 8;
+
+        let _minimap_span = info_span!("minimap").entered();
+        let pipelines = self.pipelines();
+        let bind_group = self.render_device.wgpu_device().create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &pipelines.minimap.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: self.input_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: self.minimap_params_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: self.populations_buffer.as_entire_binding() },
+            ],
+        });
+        let mut encoder = self.render_device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&Default::default());
+            pass.set_pipeline(&pipelines.minimap);
+            pass.set_bind_group(0, &bind_group, &[]);
+            let workgroups = self.minimap_params.blocks.div_ceil(THREADS_PER_WORKGROUP);
+            pass.dispatch_workgroups(workgroups, workgroups, 1);
+        }
+        encoder.copy_buffer_to_buffer(&self.populations_buffer, 0, &self.staging_populations_buffer, 0, self.populations_buffer.size());
+        self.render_queue.submit([encoder.finish()]);
+
+        self.map_buffer(&self.staging_populations_buffer, wgpu::MapMode::Read);
+        let populations = bytemuck::cast_slice(&self.staging_populations_buffer.slice(..).get_mapped_range()).to_vec();
+        self.staging_populations_buffer.unmap();
+        populations
+    }
+
+    /// The world's width in cells
+    pub fn width(&self) -> usize {
+        self.width
     }
 
     pub fn get_cells_buffer(&self) -> Buffer {
         self.input_buffer.clone()
     }
 
+    pub fn get_ages_buffer(&self) -> Buffer {
+        self.age_input_buffer.clone()
+    }
+
+    pub fn get_params_buffer(&self) -> Buffer {
+        self.params_buffer.clone()
+    }
+
     pub fn swap_buffers(&mut self) {
         (self.input_buffer, self.output_buffer) = (self.output_buffer.clone(), self.input_buffer.clone());
-        self.bind_group = Self::create_bind_group(&self.render_device, &self.pipeline, &self.input_buffer, &self.output_buffer);
+        (self.age_input_buffer, self.age_output_buffer) = (self.age_output_buffer.clone(), self.age_input_buffer.clone());
+        if let Some(pipelines) = &mut self.pipelines {
+            pipelines.bind_group = Self::create_bind_group(&self.render_device, &pipelines.main, &self.input_buffer, &self.output_buffer, &self.stats_buffer, &self.params_buffer, &self.age_input_buffer, &self.age_output_buffer);
+        }
     }
 
-    fn create_bind_group(render_device: &RenderDevice, pipeline: &ComputePipeline, input_buffer: &Buffer, output_buffer: &Buffer) -> BindGroup {
+    fn pipelines(&self) -> &ComputePipelines {
+        self.pipelines.as_ref().expect("the compute shader only runs on adapters that support it")
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_bind_group(render_device: &RenderDevice, pipeline: &ComputePipeline, input_buffer: &Buffer, output_buffer: &Buffer, stats_buffer: &Buffer, params_buffer: &Buffer, age_input_buffer: &Buffer, age_output_buffer: &Buffer) -> BindGroup {
         render_device.wgpu_device().create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
             layout: &pipeline.get_bind_group_layout(0),
@@ -57,6 +142,22 @@ impl BiosimComputeShader {
                     binding: 1,
                     resource: output_buffer.as_entire_binding()
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: stats_buffer.as_entire_binding()
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: params_buffer.as_entire_binding()
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: age_input_buffer.as_entire_binding()
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: age_output_buffer.as_entire_binding()
+                },
             ]
         })
     }
@@ -78,43 +179,92 @@ impl BiosimComputeShader {
         self.render_device.poll(wgpu::Maintain::Wait);
     }
 
-    #[allow(dead_code)]
-    pub fn read_back<S: SliceArg<Dim<[usize; 2]>>>(&self, slice_arg: S) -> ArrayBase<OwnedRepr<Cell>, S::OutDim> {
-        let _readback_span = info_span!("readback").entered();
-        let mut encoder = self.render_device.create_command_encoder(&Default::default());
-        encoder.copy_buffer_to_buffer(&self.output_buffer, 0, &self.staging_output_buffer, 0, self.output_buffer.size());
-        self.render_queue.submit([encoder.finish()]);
-        self.render_device.poll(wgpu::Maintain::Wait);
+    pub fn copy_ages_to_buffer(&self, ages: &[u32]) {
+        self.render_queue.write_buffer(&self.age_input_buffer, 0, bytemuck::cast_slice(ages));
+    }
+
+    /// Overwrites `region` of the current generation with `cells`, which are in the order of `Region::coords`. Only the
+    /// rows of the region are uploaded, which is much cheaper than `copy_to_buffer` for small edits.
+    pub fn write_region(&self, region: Region, cells: &[Cell]) {
+        self.write_buffer_region(&self.input_buffer, region, cells);
+    }
 
-        let chunk_from_gpu = {
-            self.map_buffer(&self.staging_output_buffer, wgpu::MapMode::Read);
+    /// Same as `write_region` for the ages of the cells
+    pub fn write_age_region(&self, region: Region, ages: &[u32]) {
+        self.write_buffer_region(&self.age_input_buffer, region, ages);
+    }
 
-            let output_data = self.staging_output_buffer.slice(..).get_mapped_range();
+    fn write_buffer_region<T: Pod>(&self, buffer: &Buffer, region: Region, data: &[T]) {
+        let _write_span = info_span!("write_region").entered();
+        for (y, row) in (region.min.y..=region.max.y).zip(data.chunks(region.width())) {
+            let offset = get_index(WorldCoord { x: region.min.x, y }, self.width) * mem::size_of::<T>();
+            self.render_queue.write_buffer(buffer, offset as u64, bytemuck::cast_slice(row));
+        }
+    }
 
-            let gpu_chunk_lock = info_span!("gpu_chunk").entered();
-            let bytes_from_gpu = ArrayView::from_shape((WORLD_WIDTH, WORLD_WIDTH, mem::size_of::<Cell>()), &output_data).unwrap();
-            let cells_from_gpu = bytes_from_gpu.map_axis(ndarray::Axis(2), |bytes| *bytemuck::from_bytes::<Cell>(bytes.as_slice().unwrap()));
-            let chunk_from_gpu = cells_from_gpu.slice(slice_arg);
-            gpu_chunk_lock.exit();
+    /// Replaces the params both shaders read, e.g. to follow the camera's zoom
+    pub fn write_params(&self, params: &WorldParams) {
+        self.render_queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(params));
+    }
 
-            chunk_from_gpu.into_owned()
-        };
+    /// Copies `region` of the current generation back from the GPU, in the order of `Region::coords`. Only the rows of
+    /// the region are copied, so small regions are much cheaper than the whole world, but this still waits for the GPU.
+    pub fn read_region(&self, region: Region) -> Vec<Cell> {
+        self.read_buffer_region(&self.input_buffer, region)
+    }
+
+    /// Same as `read_region` for the ages of the cells
+    pub fn read_age_region(&self, region: Region) -> Vec<u32> {
+        self.read_buffer_region(&self.age_input_buffer, region)
+    }
 
+    fn read_buffer_region<T: Pod>(&self, buffer: &Buffer, region: Region) -> Vec<T> {
+        let _readback_span = info_span!("readback").entered();
+        let offset = get_index(WorldCoord { x: 0, y: region.min.y }, self.width) * mem::size_of::<T>();
+        let size = (region.max.y - region.min.y + 1) * self.width * mem::size_of::<T>();
+        let mut encoder = self.render_device.create_command_encoder(&Default::default());
+        encoder.copy_buffer_to_buffer(buffer, offset as u64, &self.staging_output_buffer, 0, size as u64);
+        self.render_queue.submit([encoder.finish()]);
+
+        self.map_buffer_range(&self.staging_output_buffer, ..size as u64, wgpu::MapMode::Read);
+        let values = {
+            let rows = self.staging_output_buffer.slice(..size as u64).get_mapped_range();
+            bytemuck::cast_slice::<u8, T>(&rows).chunks(self.width).flat_map(|row| row[region.min.x..=region.max.x].iter().copied()).collect()
+        };
         self.staging_output_buffer.unmap();
+        values
+    }
 
-        chunk_from_gpu
+    /// Reads a single cell of the current generation, which is cheaper still than `read_region`
+    pub fn read_cell(&self, coord: WorldCoord) -> Cell {
+        let offset = get_index(coord, self.width) * mem::size_of::<Cell>();
+        let mut encoder = self.render_device.create_command_encoder(&Default::default());
+        encoder.copy_buffer_to_buffer(&self.input_buffer, offset as u64, &self.staging_cell_buffer, 0, self.staging_cell_buffer.size());
+        self.render_queue.submit([encoder.finish()]);
+
+        self.map_buffer(&self.staging_cell_buffer, wgpu::MapMode::Read);
+        let cell = *bytemuck::from_bytes::<Cell>(&self.staging_cell_buffer.slice(..).get_mapped_range());
+        self.staging_cell_buffer.unmap();
+        cell
     }
 
     fn map_buffer(&self, buffer: &Buffer, mode: wgpu::MapMode) {
+        self.map_buffer_range(buffer, .., mode);
+    }
+
+    fn map_buffer_range(&self, buffer: &Buffer, bounds: impl RangeBounds<u64>, mode: wgpu::MapMode) {
         let (tx, rx) = channel();
-        buffer.slice(..).map_async(mode, move |result| {
+        buffer.slice(bounds).map_async(mode, move |result| {
             tx.send(result).unwrap()
         });
         self.render_device.poll(wgpu::Maintain::Wait);
         rx.recv().unwrap().unwrap();
     }
 
-    pub fn new(buffer_length: usize, render_device: RenderDevice, render_queue: RenderQueue) -> BiosimComputeShader {
+    /// Sets up the buffers of a world `params` describes, along with the compute pipelines that simulate it on the GPU if
+    /// `compute` is set
+    pub fn new(params: WorldParams, render_device: RenderDevice, render_queue: RenderQueue, compute: bool) -> BiosimComputeShader {
+        let width = params.width as usize;
         let shader = unsafe { render_device.wgpu_device().create_shader_module_spirv(&wgpu::include_spirv_raw!(env!("biosim_rust_shader.spv"))) };
 
         // wgpu wants us to use staging buffers to transfer data between the cpu and gpu. Beyond our staging
@@ -122,7 +272,7 @@ impl BiosimComputeShader {
         // and output as writeonly. The two buffers are swapped each step of the simulation.
         let staging_input_buffer = render_device.create_buffer(&BufferDescriptor {
             label: Some("staging in"),
-            size: (buffer_length * mem::size_of::<Cell>()) as u64,
+            size: (width * width * mem::size_of::<Cell>()) as u64,
             usage: wgpu::BufferUsages::MAP_WRITE | wgpu::BufferUsages::COPY_SRC,
             mapped_at_creation: false,
         });
@@ -138,12 +288,49 @@ impl BiosimComputeShader {
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
+        // Ages are u32s, so their buffers are the same size as the cells'
+        let age_input_buffer = render_device.create_buffer(&BufferDescriptor {
+            label: Some("ages a"),
+            size: input_buffer.size(),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let age_output_buffer = render_device.create_buffer(&BufferDescriptor {
+            label: Some("ages b"),
+            size: input_buffer.size(),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
         let staging_output_buffer = render_device.create_buffer(&BufferDescriptor {
             label: Some("staging out"),
             size: output_buffer.size(),
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
             mapped_at_creation: false,
         });
+        let stats_buffer = render_device.create_buffer(&BufferDescriptor {
+            label: Some("stats"),
+            size: mem::size_of::<GenerationStats>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let staging_stats_buffer = render_device.create_buffer(&BufferDescriptor {
+            label: Some("staging stats"),
+            size: stats_buffer.size(),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let staging_cell_buffer = render_device.create_buffer(&BufferDescriptor {
+            label: Some("staging cell"),
+            size: mem::size_of::<Cell>() as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        // Shared with the fragment shader through `WorldMaterial`
+        let params_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("params"),
+            contents: bytemuck::bytes_of(&params),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
 
         // It seems like rust-gpu doesn't quite mark the SPIR-V it generates correctly or something, because wgpu can't
         // infer the layout correctly, which is why we are manually building it and passing it here.
@@ -160,6 +347,30 @@ impl BiosimComputeShader {
                 ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Storage { read_only: false }, has_dynamic_offset: false, min_binding_size: Some(NonZero::new(output_buffer.size()).unwrap()) },
                 count: None,
             },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Storage { read_only: false }, has_dynamic_offset: false, min_binding_size: Some(NonZero::new(stats_buffer.size()).unwrap()) },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 3,
+                visibility: ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Storage { read_only: true }, has_dynamic_offset: false, min_binding_size: Some(NonZero::new(params_buffer.size()).unwrap()) },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 4,
+                visibility: ShaderStages::COMPUTE | ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Storage { read_only: false }, has_dynamic_offset: false, min_binding_size: Some(NonZero::new(age_input_buffer.size()).unwrap()) },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 5,
+                visibility: ShaderStages::COMPUTE | ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Storage { read_only: false }, has_dynamic_offset: false, min_binding_size: Some(NonZero::new(age_output_buffer.size()).unwrap()) },
+                count: None,
+            },
         ]);
         let pipeline_layout = render_device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("pipeline layout"),
@@ -169,14 +380,68 @@ impl BiosimComputeShader {
             push_constant_ranges: &[]
         });
 
-        let pipeline = render_device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("Main compute pipeline"),
-            layout: Some(&pipeline_layout),
-            module: &shader,
-            entry_point: "main",
+        let minimap_params = MinimapParams::new(params.width);
+        let minimap_params_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("minimap params"),
+            contents: bytemuck::bytes_of(&minimap_params),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let populations_buffer = render_device.create_buffer(&BufferDescriptor {
+            label: Some("minimap populations"),
+            size: (minimap_params.blocks * minimap_params.blocks) as u64 * mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let staging_populations_buffer = render_device.create_buffer(&BufferDescriptor {
+            label: Some("staging minimap populations"),
+            size: populations_buffer.size(),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let minimap_bind_group_layout = render_device.create_bind_group_layout(Some("minimap bind group layout"), &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Storage { read_only: false }, has_dynamic_offset: false, min_binding_size: Some(NonZero::new(input_buffer.size()).unwrap()) },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Storage { read_only: true }, has_dynamic_offset: false, min_binding_size: Some(NonZero::new(minimap_params_buffer.size()).unwrap()) },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Storage { read_only: false }, has_dynamic_offset: false, min_binding_size: Some(NonZero::new(populations_buffer.size()).unwrap()) },
+                count: None,
+            },
+        ]);
+        let pipelines = compute.then(|| {
+            let main = render_device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("Main compute pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: "main",
+            });
+            let bind_group = Self::create_bind_group(&render_device, &main, &input_buffer, &output_buffer, &stats_buffer, &params_buffer, &age_input_buffer, &age_output_buffer);
+            let minimap = render_device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("Minimap compute pipeline"),
+                layout: Some(&render_device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                    label: Some("minimap pipeline layout"),
+                    bind_group_layouts: &[&minimap_bind_group_layout],
+                    push_constant_ranges: &[]
+                })),
+                module: &shader,
+                entry_point: "minimap",
+            });
+            ComputePipelines { main, bind_group, minimap }
         });
-        let bind_group = Self::create_bind_group(&render_device, &pipeline, &input_buffer, &output_buffer);
 
-        BiosimComputeShader { render_device, render_queue, pipeline, bind_group, staging_input_buffer, input_buffer, output_buffer, staging_output_buffer }
+        BiosimComputeShader {
+            render_device, render_queue, pipelines, staging_input_buffer, input_buffer, output_buffer, age_input_buffer, age_output_buffer, staging_output_buffer, stats_buffer, staging_stats_buffer,
+            params_buffer, staging_cell_buffer, minimap_params, minimap_params_buffer, populations_buffer, staging_populations_buffer, width,
+        }
     }
 }
\ No newline at end of file
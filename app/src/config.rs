@@ -0,0 +1,638 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use bevy::{ecs::system::Resource, render::color::Color};
+use biosim_core::{hex_grid::Grid, render::{GridLines, Palette, RenderMode, GRADIENT_STOPS, MAX_STATES}, world::{Boundary, Rule, WorldParams}};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{palettes::BUILT_IN_PALETTES, recording::RecordingSettings, rules::{parse_rule, rule_notation}};
+
+/// Read at startup if it exists and no other config was asked for
+pub const DEFAULT_CONFIG_PATH: &str = "biosim.toml";
+
+/// Larger worlds don't fit in a single GPU storage buffer on most adapters
+pub const MAX_WORLD_SIZE: usize = 4096;
+
+/// Each world has buffers and pipelines of its own, so this keeps the GPU memory in check
+pub const MAX_WORLDS: usize = 16;
+
+/// Everything that can be set in `biosim.toml`. The `biosim.toml` at the root of the repo documents every key along
+/// with its default.
+#[derive(Resource, Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub world: WorldConfig,
+    pub simulation: SimulationConfig,
+    pub colors: ColorConfig,
+    pub grid_lines: GridLinesConfig,
+    /// Palettes on top of the built in ones, replacing any of them with the same name
+    pub palettes: BTreeMap<String, PaletteConfig>,
+    /// Worlds run side by side, each changing some of `world` and `simulation`. Empty means a single world.
+    pub worlds: Vec<WorldOverrides>,
+    pub headless: HeadlessConfig,
+    pub server: ServerConfig,
+    pub stream: StreamConfig,
+    pub replay: ReplayConfig,
+    pub agents: AgentsConfig,
+    pub svg: SvgConfig,
+    pub recording: RecordingConfig,
+    /// The TOML this was read from with every override applied, which replay logs start with
+    #[serde(skip)]
+    pub table: toml::Table,
+}
+
+/// The keys of `world` and `simulation` that can differ from one world to the next
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorldOverrides {
+    pub rule: Option<RuleNotation>,
+    pub boundary: Option<BoundaryOverride>,
+    pub grid: Option<GridOverride>,
+    pub density: Option<f64>,
+    pub seed: Option<u64>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(transparent)]
+pub struct BoundaryOverride(#[serde(with = "BoundaryName")] pub Boundary);
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(transparent)]
+pub struct GridOverride(#[serde(with = "GridName")] pub Grid);
+
+/// How one of the worlds starts out and evolves, see `Config::worlds`
+#[derive(Clone, Copy, Debug)]
+pub struct WorldSettings {
+    pub rule: Rule,
+    pub boundary: Boundary,
+    pub grid: Grid,
+    pub density: f64,
+    pub seed: Option<u64>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorldConfig {
+    /// Worlds are square, so this is both their width and height in cells
+    pub size: usize,
+    /// Square or hexagonal cells, which each of `worlds` can change
+    #[serde(with = "GridName")]
+    pub grid: Grid,
+    /// The chance of each cell starting out alive
+    pub density: f64,
+    /// Makes the starting cells the same every run, otherwise they're different every time
+    pub seed: Option<u64>,
+    #[serde(with = "BoundaryName")]
+    pub boundary: Boundary,
+    /// Every world starts out as this `.rle` or `.npz` file instead of random cells
+    pub pattern: Option<String>,
+}
+
+impl Default for WorldConfig {
+    fn default() -> Self {
+        WorldConfig { size: 512, grid: Grid::Hex, density: 0.5, seed: None, boundary: Boundary::Dead, pattern: None }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Boundary", rename_all = "lowercase")]
+pub enum BoundaryName {
+    Dead,
+    Wrap,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Grid", rename_all = "lowercase")]
+pub enum GridName {
+    Rect,
+    Hex,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Cpu,
+    Gpu,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimulationConfig {
+    /// Seconds between generations
+    pub tick_interval: f32,
+    pub backend: Backend,
+    pub rule: RuleNotation,
+    pub paused: bool,
+    /// Exits once this many generations have run
+    pub generations: Option<u64>,
+    /// A `.rhai` script with a rule, a starting pattern or a hook to run every generation, see `Script`
+    pub script: Option<String>,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        let backend = if cfg!(feature = "cpu") { Backend::Cpu } else { Backend::Gpu };
+        SimulationConfig { tick_interval: 0.5, backend, rule: RuleNotation(Rule::LIFE), paused: false, generations: None, script: None }
+    }
+}
+
+/// A rule written like `B3/S23`, see `parse_rule`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(try_from = "String", into = "String")]
+pub struct RuleNotation(pub Rule);
+
+impl From<RuleNotation> for String {
+    fn from(notation: RuleNotation) -> String {
+        rule_notation(notation.0)
+    }
+}
+
+impl TryFrom<String> for RuleNotation {
+    type Error = String;
+
+    fn try_from(notation: String) -> Result<RuleNotation, String> {
+        parse_rule(&notation).map(RuleNotation)
+    }
+}
+
+/// What `--headless` runs write, see `run_headless`
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeadlessConfig {
+    /// Directory the snapshots, frames and statistics go in, which is created if it doesn't exist
+    pub output: String,
+    /// Save the worlds as `.npz` every this many generations. The last generation is always saved.
+    pub snapshot_every: Option<u64>,
+    /// Render the worlds to `.png` every this many generations
+    pub frame_every: Option<u64>,
+    /// Size of a cell in the frames in pixels
+    pub frame_scale: u32,
+}
+
+impl Default for HeadlessConfig {
+    fn default() -> Self {
+        HeadlessConfig { output: "output".to_string(), snapshot_every: None, frame_every: None, frame_scale: 2 }
+    }
+}
+
+/// Where the app takes commands from other programs, see `RpcServer`
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Listens on this port of localhost when set
+    pub port: Option<u16>,
+}
+
+/// Where spectators can watch the first world from, see `Broadcaster`
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct StreamConfig {
+    /// Streams on this port of localhost when set
+    pub port: Option<u16>,
+    /// Generations between keyframes, which viewers that fell behind catch up at
+    pub keyframe_every: u64,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        StreamConfig { port: None, keyframe_every: 100 }
+    }
+}
+
+/// Where runs are logged so `--replay` can make them again, see `ReplayLog`
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplayConfig {
+    /// Logs the run to this file when set
+    pub log: Option<String>,
+    /// Generations between the hashes of the worlds that replays are checked against
+    pub hash_every: u64,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        ReplayConfig { log: None, hash_every: 1 }
+    }
+}
+
+/// Creatures living in every world, see `Population`. Only headless runs and replays step them for now.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgentsConfig {
+    /// Creatures per world, none by default
+    pub count: usize,
+    /// Makes the creatures the same every run, each world adds its number to it
+    pub seed: Option<u64>,
+    /// Steps for the oscillator creatures sense to go up and back down
+    pub oscillator_period: u32,
+}
+
+impl Default for AgentsConfig {
+    fn default() -> Self {
+        AgentsConfig { count: 0, seed: None, oscillator_period: 32 }
+    }
+}
+
+/// What the first world is recorded to, see `Recorder`. The `BIOSIM_RECORD*` environment variables override these.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecordingConfig {
+    /// Records to this `.gif`, `.png`, `.apng`, `.npy` or `.npz` file when set
+    pub path: Option<String>,
+    pub first_generation: u64,
+    /// The last generation recorded, inclusive
+    pub last_generation: u64,
+    /// Only records every this many generations
+    pub every: u64,
+    /// Squares or hexagons, defaults to how the grid of the recorded world lays cells out
+    pub style: Option<RecordingStyleName>,
+    /// Size of a cell in pixels
+    pub scale: u32,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        RecordingConfig { path: None, first_generation: 0, last_generation: 99, every: 1, style: None, scale: 2 }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingStyleName {
+    Pixels,
+    #[serde(alias = "hexagons")]
+    Hex,
+}
+
+/// What F11 puts in svgs, see `save_svg`
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SvgConfig {
+    /// Draws the dead cells too, rather than leaving them transparent
+    pub dead_cells: bool,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColorConfig {
+    /// The palette to start with, either a built in one or one from `palettes`
+    pub palette: String,
+    /// Replace the colours of the starting palette
+    pub alive: Option<HexColor>,
+    pub dead: Option<HexColor>,
+    #[serde(with = "RenderModeName")]
+    pub mode: RenderMode,
+}
+
+impl Default for ColorConfig {
+    fn default() -> Self {
+        ColorConfig { palette: "classic".to_string(), alive: None, dead: None, mode: RenderMode::State }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(remote = "RenderMode", rename_all = "lowercase")]
+enum RenderModeName {
+    State,
+    Age,
+    Delta,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PaletteConfig {
+    /// Colours of the cell states in order, starting with dead and alive. States past the end use the last colour.
+    pub states: Vec<HexColor>,
+    /// Evenly spaced colours to blend between, defaulting to a fade from the live to the dead colour
+    #[serde(default)]
+    pub gradient: Vec<HexColor>,
+}
+
+impl PaletteConfig {
+    pub fn to_palette(&self) -> Result<Palette, String> {
+        if !(2..=MAX_STATES).contains(&self.states.len()) {
+            return Err(format!("needs between 2 and {MAX_STATES} state colours, got {}", self.states.len()));
+        }
+        let states: [[f32; 4]; MAX_STATES] = std::array::from_fn(|i| self.states[i.min(self.states.len() - 1)].0);
+
+        let gradient = match self.gradient.len() {
+            0 => vec![states[1], states[0]],
+            1 => vec![self.gradient[0].0; 2],
+            _ => self.gradient.iter().map(|color| color.0).collect(),
+        };
+        // Gradients with a different number of stops are resampled, which keeps them looking the same
+        let gradient = std::array::from_fn(|i| {
+            let position = i as f32 / (GRADIENT_STOPS - 1) as f32 * (gradient.len() - 1) as f32;
+            let index = (position as usize).min(gradient.len() - 2);
+            let t = position - index as f32;
+            std::array::from_fn(|channel| gradient[index][channel] + (gradient[index + 1][channel] - gradient[index][channel]) * t)
+        });
+        Ok(Palette { states, gradient })
+    }
+}
+
+/// Cell borders, which fade in as the camera zooms in
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct GridLinesConfig {
+    pub color: HexColor,
+    /// Width in pixels, 0 turns them off
+    pub width: f32,
+}
+
+impl Default for GridLinesConfig {
+    fn default() -> Self {
+        let grid_lines = GridLines::default();
+        GridLinesConfig { color: HexColor(grid_lines.color), width: grid_lines.width }
+    }
+}
+
+/// A colour written like `#rrggbb` or `#rrggbbaa`, kept as the linear rgba the shaders work in
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(try_from = "String")]
+pub struct HexColor(pub [f32; 4]);
+
+impl TryFrom<String> for HexColor {
+    type Error = String;
+
+    fn try_from(hex: String) -> Result<HexColor, String> {
+        match Color::hex(&hex) {
+            Ok(color) => Ok(HexColor(color.as_linear_rgba_f32())),
+            Err(_) => Err(format!("`{hex}` isn't a colour, expected `#rrggbb` or `#rrggbbaa`")),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config file at `path`, which otherwise is `biosim.toml` if it exists, and overrides keys in it with
+    /// every `<key>=<value>` in `overrides`, e.g. `world.size=256`. See `Cli` for the flags these come from.
+    pub fn load(path: Option<&str>, overrides: &[String]) -> Result<Config, String> {
+        let mut table = match path {
+            Some(path) => read_table(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => read_table(DEFAULT_CONFIG_PATH)?,
+            None => toml::Table::new(),
+        };
+        for assignment in overrides {
+            apply_override(&mut table, assignment)?;
+        }
+        Config::from_table(table)
+    }
+
+    /// Errors name the key that's wrong, e.g. "unknown variant `hexagon`, expected `rect` or `hex` in `world.grid`"
+    pub fn from_table(table: toml::Table) -> Result<Config, String> {
+        let mut config = Config::deserialize(toml::Value::Table(table.clone())).map_err(|e| e.to_string().trim_end().replace('\n', " "))?;
+        config.validate()?;
+        config.table = table;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=MAX_WORLD_SIZE).contains(&self.world.size) {
+            return Err(format!("`world.size` must be between 1 and {MAX_WORLD_SIZE}, got {}", self.world.size));
+        }
+        if !(0.0..=1.0).contains(&self.world.density) {
+            return Err(format!("`world.density` must be between 0 and 1, got {}", self.world.density));
+        }
+        if let Some(pattern) = self.world.pattern.as_ref().filter(|pattern| !(pattern.ends_with(".rle") || pattern.ends_with(".npz"))) {
+            return Err(format!("`world.pattern` must be an .rle or .npz file, got {pattern}"));
+        }
+        if let Some(script) = self.simulation.script.as_ref().filter(|script| !script.ends_with(".rhai")) {
+            return Err(format!("`simulation.script` must be a .rhai file, got {script}"));
+        }
+        if self.worlds.len() > MAX_WORLDS {
+            return Err(format!("`worlds` can have at most {MAX_WORLDS} worlds, got {}", self.worlds.len()));
+        }
+        for (i, world) in self.worlds.iter().enumerate() {
+            if let Some(density) = world.density.filter(|density| !(0.0..=1.0).contains(density)) {
+                return Err(format!("`worlds[{i}].density` must be between 0 and 1, got {density}"));
+            }
+        }
+        if !(self.simulation.tick_interval.is_finite() && self.simulation.tick_interval > 0.0) {
+            return Err(format!("`simulation.tick_interval` must be a positive number of seconds, got {}", self.simulation.tick_interval));
+        }
+        for (key, every) in [("snapshot_every", self.headless.snapshot_every), ("frame_every", self.headless.frame_every)] {
+            if every == Some(0) {
+                return Err(format!("`headless.{key}` must be at least 1"));
+            }
+        }
+        if self.stream.keyframe_every == 0 {
+            return Err("`stream.keyframe_every` must be at least 1".to_string());
+        }
+        if self.stream.port.is_some() && self.stream.port == self.server.port {
+            return Err("`stream.port` and `server.port` can't be the same port".to_string());
+        }
+        if self.replay.hash_every == 0 {
+            return Err("`replay.hash_every` must be at least 1".to_string());
+        }
+        if self.headless.frame_scale == 0 {
+            return Err("`headless.frame_scale` must be at least 1".to_string());
+        }
+        if self.agents.count > self.world.size * self.world.size / 2 {
+            return Err(format!("`agents.count` can be at most half the cells of the world, {}, got {}", self.world.size * self.world.size / 2, self.agents.count));
+        }
+        if self.agents.oscillator_period == 0 {
+            return Err("`agents.oscillator_period` must be at least 1".to_string());
+        }
+        self.recording()?;
+        self.palettes()?;
+        if !(self.grid_lines.width.is_finite() && self.grid_lines.width >= 0.0) {
+            return Err(format!("`grid_lines.width` must be a number of pixels, got {}", self.grid_lines.width));
+        }
+        Ok(())
+    }
+
+    /// The built in palettes followed by the ones added in `palettes`, with `colors.alive` and `colors.dead` applied to
+    /// the one the app starts with
+    pub fn palettes(&self) -> Result<Vec<(String, Palette)>, String> {
+        let colors = |hexes: &[&str]| hexes.iter().map(|hex| HexColor::try_from(hex.to_string()).expect("built in colours should parse")).collect();
+        let mut palettes: Vec<(String, PaletteConfig)> = BUILT_IN_PALETTES.iter()
+            .map(|(name, states, gradient)| (name.to_string(), PaletteConfig { states: colors(states), gradient: colors(gradient) }))
+            .collect();
+        for (name, palette) in &self.palettes {
+            match palettes.iter_mut().find(|(built_in, _)| built_in == name) {
+                Some((_, built_in)) => *built_in = palette.clone(),
+                None => palettes.push((name.clone(), palette.clone())),
+            }
+        }
+        if !palettes.iter().any(|(name, _)| *name == self.colors.palette) {
+            let names: Vec<&str> = palettes.iter().map(|(name, _)| name.as_str()).collect();
+            return Err(format!("`colors.palette` is `{}`, which isn't one of {}", self.colors.palette, names.join(", ")));
+        }
+
+        palettes.into_iter().map(|(name, palette)| {
+            let mut palette = palette.to_palette().map_err(|e| format!("palette `{name}` {e}"))?;
+            if name == self.colors.palette {
+                if let Some(dead) = self.colors.dead {
+                    palette.states[0] = dead.0;
+                }
+                if let Some(alive) = self.colors.alive {
+                    palette.states[1] = alive.0;
+                }
+            }
+            Ok((name, palette))
+        }).collect()
+    }
+
+    /// Index of `colors.palette` in `palettes` along with the palette
+    pub fn starting_palette(&self) -> (usize, Palette) {
+        let palettes = self.palettes().expect("config should have been validated");
+        let index = palettes.iter().position(|(name, _)| *name == self.colors.palette).unwrap();
+        (index, palettes[index].1)
+    }
+
+    /// One entry per world, which is just `world` and `simulation` unless `worlds` lists several with their own settings
+    pub fn worlds(&self) -> Vec<WorldSettings> {
+        let base = WorldSettings { rule: self.simulation.rule.0, boundary: self.world.boundary, grid: self.world.grid, density: self.world.density, seed: self.world.seed };
+        if self.worlds.is_empty() {
+            return vec![base];
+        }
+        self.worlds.iter().map(|world| WorldSettings {
+            rule: world.rule.map_or(base.rule, |rule| rule.0),
+            boundary: world.boundary.map_or(base.boundary, |boundary| boundary.0),
+            grid: world.grid.map_or(base.grid, |grid| grid.0),
+            density: world.density.unwrap_or(base.density),
+            seed: world.seed.or(base.seed),
+        }).collect()
+    }
+
+    /// Gives every world that would start out with different random cells each run a seed of its own, and the creatures
+    /// too, in `table` as well, so the same worlds can be made again from it
+    pub fn pin_seeds(&mut self) {
+        // TOML integers are signed
+        let random_seed = || rand::thread_rng().gen_range(0..=i64::MAX as u64);
+        if self.agents.count > 0 && self.agents.seed.is_none() {
+            let seed = random_seed();
+            self.agents.seed = Some(seed);
+            apply_override(&mut self.table, &format!("agents.seed={seed}")).expect("`agents` should be a table");
+        }
+        if self.world.seed.is_some() {
+            return;
+        }
+        if self.worlds.is_empty() {
+            let seed = random_seed();
+            self.world.seed = Some(seed);
+            apply_override(&mut self.table, &format!("world.seed={seed}")).expect("`world` should be a table");
+            return;
+        }
+        let Some(toml::Value::Array(tables)) = self.table.get_mut("worlds") else {
+            return;
+        };
+        for (world, table) in self.worlds.iter_mut().zip(tables).filter(|(world, _)| world.seed.is_none()) {
+            let seed = random_seed();
+            world.seed = Some(seed);
+            if let toml::Value::Table(table) = table {
+                table.insert("seed".to_string(), toml::Value::Integer(seed as i64));
+            }
+        }
+    }
+
+    /// What to record the first world to, if anything
+    pub fn recording(&self) -> Result<Option<RecordingSettings>, String> {
+        let Some(path) = &self.recording.path else {
+            return Ok(None);
+        };
+        let grid = match self.recording.style {
+            Some(RecordingStyleName::Pixels) => Grid::Rect,
+            Some(RecordingStyleName::Hex) => Grid::Hex,
+            None => self.worlds()[0].grid,
+        };
+        let recording = &self.recording;
+        let settings = RecordingSettings {
+            first_generation: recording.first_generation,
+            last_generation: recording.last_generation,
+            every: recording.every,
+            scale: recording.scale,
+            ..RecordingSettings::new(path, grid)?
+        };
+        settings.validate(self.world.size)?;
+        Ok(Some(settings))
+    }
+
+    /// Leaves the creatures out of runs that can't step them, in `table` too so replays of the run don't have them either
+    pub fn drop_agents(&mut self) {
+        self.agents.count = 0;
+        apply_override(&mut self.table, "agents.count=0").expect("`agents` should be a table");
+    }
+
+    /// The grid lines are drawn at the default zoom, which `update_grid_lines` keeps up to date with the camera
+    pub fn world_params(&self) -> WorldParams {
+        WorldParams {
+            width: self.world.size as u32,
+            palette: self.starting_palette().1,
+            grid_lines: GridLines { color: self.grid_lines.color.0, width: self.grid_lines.width, ..GridLines::default() },
+            render_mode: self.colors.mode,
+            rule: self.simulation.rule.0,
+            boundary: self.world.boundary,
+            grid: self.world.grid,
+        }
+    }
+}
+
+fn read_table(path: &str) -> Result<toml::Table, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("couldn't read {path}: {e}"))?;
+    text.parse().map_err(|e| format!("{path}: {e}"))
+}
+
+/// Sets a dotted key like `world.size` in `table`. The value is parsed as TOML, falling back to a plain string so that
+/// strings like `cpu` or `#ff0000` don't need quoting on the command line.
+fn apply_override(table: &mut toml::Table, assignment: &str) -> Result<(), String> {
+    let Some((key, value)) = assignment.split_once('=') else {
+        return Err(format!("`--set {assignment}` should look like `--set key=value`"));
+    };
+    let (key, value) = (key.trim(), value.trim());
+    let value = match format!("value = {value}").parse::<toml::Table>() {
+        Ok(mut parsed) => parsed.remove("value").unwrap(),
+        Err(_) => toml::Value::String(value.to_string()),
+    };
+
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = parts.pop().unwrap();
+    let mut table = table;
+    for part in parts {
+        table = match table.entry(part).or_insert_with(|| toml::Value::Table(toml::Table::new())) {
+            toml::Value::Table(inner) => inner,
+            _ => return Err(format!("can't set `{key}` because `{part}` isn't a table")),
+        };
+    }
+    table.insert(last.to_string(), value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overridden(assignments: &[&str]) -> Result<Config, String> {
+        let mut table = toml::Table::new();
+        for assignment in assignments {
+            apply_override(&mut table, assignment)?;
+        }
+        Config::from_table(table)
+    }
+
+    #[test]
+    fn overrides_set_nested_keys() {
+        let config = overridden(&["world.size=64", "world.grid = rect", "simulation.rule=B36/S23", "simulation.tick_interval=0.25", "svg.dead_cells=true"]).unwrap();
+        assert_eq!(config.world.size, 64);
+        assert_eq!(config.world.grid, Grid::Rect);
+        assert_eq!(config.simulation.rule.0, parse_rule("B36/S23").unwrap());
+        assert_eq!(config.simulation.tick_interval, 0.25);
+        assert!(config.svg.dead_cells);
+
+        let mut table = toml::Table::new();
+        apply_override(&mut table, "a.b.c=\"quoted\"").unwrap();
+        apply_override(&mut table, "a.b.d=[1, 2]").unwrap();
+        assert_eq!(table["a"]["b"]["c"].as_str(), Some("quoted"));
+        assert_eq!(table["a"]["b"]["d"].as_array().map(Vec::len), Some(2));
+    }
+
+    #[test]
+    fn broken_overrides_are_rejected() {
+        assert!(overridden(&["world.size"]).unwrap_err().contains("key=value"));
+        assert!(overridden(&["world=3", "world.size=64"]).unwrap_err().contains("`world` isn't a table"));
+        for assignment in ["world.size=big", "world.size=-1", "world.grid=hexagon", "simulation.paused=3", "simulation.rule=B9/S23", "world.sise=64"] {
+            assert!(overridden(&[assignment]).is_err(), "{assignment}");
+        }
+        assert!(overridden(&["world.size=0"]).unwrap_err().contains("`world.size`"));
+    }
+}
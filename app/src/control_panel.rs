@@ -0,0 +1,342 @@
+use bevy::prelude::*;
+use bevy_egui::{egui::{self, ComboBox, DragValue, ScrollArea, Slider, Ui}, EguiContexts};
+use biosim_core::{hex_grid::Grid, render::RenderMode, statistics::GenerationStats, world::Boundary};
+use egui_plot::{Legend, Line, Plot, PlotPoints};
+
+use crate::{biosim_plugin::{load_npz, replace_world, write_npz, FocusedWorld, Generation, ShaderParams, WorldComponent, WorldId, WorldRules}, compute_shader::BiosimComputeShader, config::{Backend, Config}, inspector::Inspector, palettes::Palettes, playback::{Playback, PlaybackText, MAX_TICK_INTERVAL, MIN_TICK_INTERVAL}, replay::WorldSource, rules::{parse_rule, preset_name, rule_notation, RULE_PRESETS}, simulator::Simulation, statistics::Statistics, world::new_random};
+
+/// How many of the latest generations the statistics plot shows, which keeps it cheap to draw in long runs
+const PLOT_GENERATIONS: usize = 2000;
+
+const MAX_GRID_LINES_WIDTH: f32 = 4.0;
+
+/// Gap between the panel and the text it pushes aside
+const PANEL_MARGIN: f32 = 8.0;
+
+/// The panel docked on the left that changes the simulation while it runs. Tab shows and hides it.
+#[derive(Resource)]
+pub struct ControlPanel {
+    pub open: bool,
+    /// How much of the window the panel takes up, 0 while it's hidden
+    width: f32,
+    /// The world the panel last showed
+    shown_world: Option<Entity>,
+    /// The rule as it's being typed, which takes effect whenever it parses
+    rule_text: String,
+    /// Density and seed of the next reseed, which is random unless `fixed_seed` is set
+    density: f64,
+    fixed_seed: bool,
+    seed: u64,
+    /// Where the world is saved to and loaded from
+    path: String,
+    /// How the last save or load went
+    status: String,
+}
+
+impl ControlPanel {
+    pub fn new(config: &Config) -> ControlPanel {
+        ControlPanel {
+            open: true,
+            width: 0.0,
+            shown_world: None,
+            rule_text: rule_notation(config.simulation.rule.0),
+            density: config.world.density,
+            fixed_seed: config.world.seed.is_some(),
+            seed: config.world.seed.unwrap_or(0),
+            path: "world.npz".to_string(),
+            status: String::new(),
+        }
+    }
+
+    /// Makes the panel show the focused world's rule again, after something other than the panel changed it
+    pub fn reset_rule_text(&mut self) {
+        self.shown_world = None;
+    }
+}
+
+/// Buttons that change the world itself, which are acted on once the panel is drawn
+#[derive(Default)]
+struct WorldActions {
+    reseed: bool,
+    save: bool,
+    load: bool,
+    /// Every world moves over when switching, not just the focused one
+    switch_to: Option<Backend>,
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn control_panel(
+    mut contexts: EguiContexts,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut panel: ResMut<ControlPanel>,
+    mut playback: ResMut<Playback>,
+    mut params: ResMut<ShaderParams>,
+    mut palettes: ResMut<Palettes>,
+    mut inspector: ResMut<Inspector>,
+    mut world_query: Query<(&WorldId, &mut WorldComponent, &mut Generation, &mut Statistics, &BiosimComputeShader, &mut WorldRules)>,
+    focused: Res<FocusedWorld>,
+    mut simulation: ResMut<Simulation>,
+    config: Res<Config>,
+) {
+    if keys.just_pressed(KeyCode::Tab) {
+        panel.open = !panel.open;
+    }
+    if !panel.open {
+        if panel.width != 0.0 {
+            panel.width = 0.0;
+        }
+        return;
+    }
+
+    // The rule and world sections are about the focused world, so the rule being typed starts over when it changes
+    let world_count = world_query.iter().count();
+    let mut world = focused.0.and_then(|entity| world_query.get_mut(entity).ok());
+    if panel.shown_world != focused.0 {
+        panel.shown_world = focused.0;
+        if let Some((_, _, _, _, _, rules)) = &world {
+            panel.rule_text = rule_notation(rules.rule);
+        }
+    }
+
+    let mut actions = WorldActions::default();
+    let response = egui::SidePanel::left("control_panel").show(contexts.ctx_mut(), |ui| {
+        ScrollArea::vertical().show(ui, |ui| {
+            if let Some((id, _, _, statistics, _, rules)) = &mut world {
+                if world_count > 1 {
+                    ui.label(format!("World {} of {world_count}, the one closest to the middle of the screen", id.0 + 1));
+                }
+                ui.heading("Rule");
+                rule_controls(ui, &mut panel, rules);
+                ui.separator();
+
+                ui.heading("World");
+                actions.reseed = world_controls(ui, &mut panel);
+                ui.separator();
+
+                ui.heading("Statistics");
+                statistics_plot(ui, statistics);
+                ui.separator();
+
+                ui.heading("Save and load");
+                (actions.save, actions.load) = file_controls(ui, &mut panel);
+                ui.separator();
+            }
+
+            ui.heading("Playback");
+            playback_controls(ui, &mut playback);
+            actions.switch_to = simulator_controls(ui, &simulation);
+            ui.separator();
+
+            ui.heading("Colours");
+            color_controls(ui, &mut palettes, &mut params);
+        });
+    });
+    let width = response.response.rect.width();
+    if panel.width != width {
+        panel.width = width;
+    }
+
+    if let Some(backend) = actions.switch_to {
+        let worlds = world_query.iter_mut().map(|(_, world_component, _, _, compute_shader, _)| (world_component.into_inner(), compute_shader));
+        simulation.switch(backend, worlds);
+    }
+
+    let Some((_, mut world_component, mut generation, mut statistics, compute_shader, _)) = focused.0.and_then(|entity| world_query.get_mut(entity).ok()) else {
+        return;
+    };
+    let path = panel.path.clone();
+    if actions.reseed {
+        // Random worlds get a seed too, so the replay log can make them again
+        let seed = if panel.fixed_seed { panel.seed } else { rand::random() };
+        let cells = new_random(config.world.size, panel.density, Some(seed));
+        let (ages, initial) = (vec![0; cells.len()], Statistics(vec![GenerationStats::initial(&cells)]));
+        replace_world(&mut world_component, &mut generation, &mut statistics, compute_shader, (cells, ages, initial), WorldSource::Random { density: panel.density, seed });
+        inspector.hover(None);
+    }
+    if actions.save {
+        panel.status = match write_npz(path.as_ref(), &world_component, &statistics, compute_shader, &simulation, &config) {
+            Ok(()) => format!("Saved generation {} to {path}", generation.0),
+            Err(e) => format!("Couldn't save to {path}: {e}"),
+        };
+    }
+    if actions.load {
+        panel.status = match load_npz(path.as_ref(), &config) {
+            Ok(world) => {
+                replace_world(&mut world_component, &mut generation, &mut statistics, compute_shader, world, WorldSource::File(path.clone()));
+                inspector.hover(None);
+                format!("Loaded generation {} from {path}", generation.0)
+            }
+            Err(e) => format!("Couldn't load {path}: {e}"),
+        };
+    }
+}
+
+/// A preset picker and a text field for the rule, whether the world wraps around and whether its cells are squares or
+/// hexagons, which changes their neighbours
+fn rule_controls(ui: &mut Ui, panel: &mut ControlPanel, rules: &mut Mut<WorldRules>) {
+    let (mut rule, mut boundary, mut grid) = (rules.rule, rules.boundary, rules.grid);
+    ComboBox::from_label("Preset").selected_text(preset_name(rule).unwrap_or("Custom")).show_ui(ui, |ui| {
+        for (name, notation) in RULE_PRESETS {
+            ui.selectable_value(&mut rule, parse_rule(notation).expect("presets should parse"), name);
+        }
+    });
+    if rule != rules.rule {
+        panel.rule_text = rule_notation(rule);
+    }
+
+    let typed = ui.horizontal(|ui| {
+        ui.label("Rule");
+        ui.text_edit_singleline(&mut panel.rule_text)
+    }).inner;
+    match parse_rule(&panel.rule_text) {
+        Ok(parsed) if typed.changed() => rule = parsed,
+        Ok(_) => {}
+        Err(e) => {
+            ui.colored_label(ui.visuals().error_fg_color, e);
+        }
+    }
+
+    ui.horizontal(|ui| {
+        ui.radio_value(&mut boundary, Boundary::Dead, "Dead edges");
+        ui.radio_value(&mut boundary, Boundary::Wrap, "Wrap around");
+    });
+    ui.horizontal(|ui| {
+        ui.radio_value(&mut grid, Grid::Rect, "Squares");
+        ui.radio_value(&mut grid, Grid::Hex, "Hexagons");
+    });
+
+    // Only touch the rules when something changed, so they aren't uploaded every frame
+    if rule != rules.rule {
+        rules.rule = rule;
+    }
+    if boundary != rules.boundary {
+        rules.boundary = boundary;
+    }
+    if grid != rules.grid {
+        rules.grid = grid;
+    }
+}
+
+/// Whether generations run on the CPU or in the compute shader, returning the one picked when it changed. The GPU can't
+/// be picked when the adapter can't run the compute shader well.
+fn simulator_controls(ui: &mut Ui, simulation: &Simulation) -> Option<Backend> {
+    let mut backend = simulation.backend();
+    ui.horizontal(|ui| {
+        ui.label("Simulate on");
+        ui.radio_value(&mut backend, Backend::Cpu, "CPU");
+        let gpu = ui.add_enabled(simulation.gpu_unsupported.is_none(), egui::RadioButton::new(backend == Backend::Gpu, "GPU"));
+        if gpu.clicked() {
+            backend = Backend::Gpu;
+        }
+        if let Some(reason) = &simulation.gpu_unsupported {
+            gpu.on_disabled_hover_text(format!("Unavailable, {reason}"));
+        }
+    });
+    (backend != simulation.backend()).then_some(backend)
+}
+
+/// The same controls as the playback keys, with a slider for the tick interval
+fn playback_controls(ui: &mut Ui, playback: &mut Playback) {
+    ui.horizontal(|ui| {
+        if ui.button(if playback.paused { "Resume" } else { "Pause" }).clicked() {
+            playback.paused = !playback.paused;
+        }
+        if ui.button("Step").clicked() {
+            playback.step();
+        }
+        if ui.selectable_label(playback.fast_forward, "Fast forward").clicked() {
+            playback.fast_forward = !playback.fast_forward;
+            playback.paused = false;
+        }
+    });
+    let mut tick_interval = playback.tick_interval();
+    if ui.add(Slider::new(&mut tick_interval, MIN_TICK_INTERVAL..=MAX_TICK_INTERVAL).logarithmic(true).suffix(" s").text("Tick interval")).changed() {
+        playback.set_tick_interval(tick_interval);
+    }
+}
+
+/// Density and seed for a fresh random world, returning whether one was asked for
+fn world_controls(ui: &mut Ui, panel: &mut ControlPanel) -> bool {
+    ui.add(Slider::new(&mut panel.density, 0.0..=1.0).text("Density"));
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut panel.fixed_seed, "Seed");
+        ui.add_enabled(panel.fixed_seed, DragValue::new(&mut panel.seed));
+    });
+    ui.button("Reseed").clicked()
+}
+
+fn color_controls(ui: &mut Ui, palettes: &mut Palettes, params: &mut ResMut<ShaderParams>) {
+    let mut current = palettes.current;
+    ComboBox::from_label("Palette").selected_text(&palettes.current().0).show_ui(ui, |ui| {
+        for (index, (name, _)) in palettes.palettes.iter().enumerate() {
+            ui.selectable_value(&mut current, index, name);
+        }
+    });
+    if current != palettes.current {
+        palettes.current = current;
+        params.0.palette = palettes.current().1;
+    }
+
+    let (mut render_mode, mut grid_lines_width) = (params.0.render_mode, params.0.grid_lines.width);
+    ui.horizontal(|ui| {
+        ui.radio_value(&mut render_mode, RenderMode::State, "State");
+        ui.radio_value(&mut render_mode, RenderMode::Age, "Age");
+        ui.radio_value(&mut render_mode, RenderMode::Delta, "Births and deaths");
+    });
+    ui.add(Slider::new(&mut grid_lines_width, 0.0..=MAX_GRID_LINES_WIDTH).suffix(" px").text("Grid lines"));
+
+    if render_mode != params.0.render_mode {
+        params.0.render_mode = render_mode;
+    }
+    if grid_lines_width != params.0.grid_lines.width {
+        params.0.grid_lines.width = grid_lines_width;
+    }
+}
+
+/// Population, births and deaths over the last `PLOT_GENERATIONS` generations
+fn statistics_plot(ui: &mut Ui, statistics: &Statistics) {
+    let first = statistics.0.len().saturating_sub(PLOT_GENERATIONS);
+    let line = |name: &str, stat: fn(&GenerationStats) -> u32| {
+        let points: PlotPoints = statistics.0[first..].iter().enumerate().map(|(i, stats)| [(first + i) as f64, stat(stats) as f64]).collect();
+        Line::new(points).name(name)
+    };
+    Plot::new("statistics").height(160.0).legend(Legend::default()).allow_drag(false).allow_zoom(false).allow_scroll(false).show(ui, |plot| {
+        plot.line(line("population", |stats| stats.population));
+        plot.line(line("births", |stats| stats.births));
+        plot.line(line("deaths", |stats| stats.deaths));
+    });
+}
+
+/// A path along with save and load buttons, returning which of them were clicked
+fn file_controls(ui: &mut Ui, panel: &mut ControlPanel) -> (bool, bool) {
+    ui.horizontal(|ui| {
+        ui.label("File");
+        ui.text_edit_singleline(&mut panel.path);
+    });
+    let clicked = ui.horizontal(|ui| (ui.button("Save").clicked(), ui.button("Load").clicked())).inner;
+    if !panel.status.is_empty() {
+        ui.label(&panel.status);
+    }
+    clicked
+}
+
+/// Moves the playback text out from under the panel
+pub fn make_room_for_panel(panel: Res<ControlPanel>, mut styles: Query<&mut Style, With<PlaybackText>>) {
+    let left = Val::Px(panel.width + PANEL_MARGIN);
+    for mut style in &mut styles {
+        if style.left != left {
+            style.left = left;
+        }
+    }
+}
+
+/// Keeps typing in the panel's text fields and clicking on it from also reaching the world
+pub fn block_input_under_panel(mut contexts: EguiContexts, mut keys: ResMut<ButtonInput<KeyCode>>, mut buttons: ResMut<ButtonInput<MouseButton>>) {
+    let ctx = contexts.ctx_mut();
+    if ctx.wants_keyboard_input() {
+        keys.reset_all();
+    }
+    if ctx.is_pointer_over_area() || ctx.is_using_pointer() {
+        buttons.reset_all();
+    }
+}
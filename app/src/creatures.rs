@@ -0,0 +1,44 @@
+use biosim_core::{agents::{spawn, step_creatures, AgentParams, Creature, Intent, NOBODY}, world::{Cell, WorldParams}};
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::config::AgentsConfig;
+
+/// The creatures of a world along with what `step_creatures` keeps between steps
+pub struct Population {
+    pub creatures: Vec<Creature>,
+    intents: Vec<Intent>,
+    occupancy: Vec<u32>,
+    claims: Vec<u32>,
+    params: AgentParams,
+    steps: u32,
+}
+
+impl Population {
+    /// `agents.count` newborns on free cells of a world with `world_params`, placed by `agents.seed` plus `offset` so
+    /// every world gets different ones
+    pub fn spawn(agents: &AgentsConfig, world_params: &WorldParams, offset: u64) -> Population {
+        let params = AgentParams { width: world_params.width, boundary: world_params.boundary, grid: world_params.grid, oscillator_period: agents.oscillator_period };
+        let mut rng = match agents.seed {
+            Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(offset)),
+            None => StdRng::from_entropy(),
+        };
+        let cells = (params.width * params.width) as usize;
+        let mut occupancy = vec![NOBODY; cells];
+        // `agents.count` is at most half the cells, so there's always a free one
+        let creatures: Vec<Creature> = (0..agents.count)
+            .map(|i| spawn(i as u32, &mut occupancy, &params, &mut rng).expect("the world should have a free cell"))
+            .collect();
+        Population { intents: vec![Intent::default(); creatures.len()], creatures, occupancy, claims: vec![NOBODY; cells], params, steps: 0 }
+    }
+
+    /// Keeps up with the boundary and grid of a world whose rules changed
+    pub fn follow(&mut self, world_params: &WorldParams) {
+        (self.params.boundary, self.params.grid) = (world_params.boundary, world_params.grid);
+    }
+
+    /// Steps every creature once on `cells`, which have just ticked
+    pub fn step(&mut self, cells: &mut [Cell], ages: &mut [u32]) {
+        step_creatures(&mut self.creatures, &mut self.intents, &mut self.occupancy, &mut self.claims, cells, ages, &self.params, self.steps);
+        self.steps = self.steps.wrapping_add(1);
+    }
+}
@@ -0,0 +1,148 @@
+use std::{error::Error, fs::{self, File}, io::{BufWriter, Write}, path::Path, time::Instant};
+
+use biosim_core::{statistics::GenerationStats, world::{Cell, WorldParams}};
+use biosim_stream::Broadcaster;
+
+use crate::{biosim_plugin::{file_stem, starting_worlds, WorldId, WorldRules}, config::{AgentsConfig, Config}, creatures::Population, npy::write_world, rasterizer::{rasterize, Viewport}, recording::{Recorder, RecordingSettings, RecordingStyle}, replay::ReplayLog, script::Script, statistics::Statistics, streaming::stream_hello, world::tick};
+
+/// A world as headless runs and replays keep it, which is always on the CPU
+pub struct HeadlessWorld {
+    pub id: WorldId,
+    pub cells: Vec<Cell>,
+    pub ages: Vec<u32>,
+    pub statistics: Statistics,
+    pub params: WorldParams,
+    /// None without `agents.count`
+    pub population: Option<Population>,
+}
+
+impl HeadlessWorld {
+    pub fn new(id: WorldId, (cells, ages, statistics): (Vec<Cell>, Vec<u32>, Statistics), params: WorldParams, agents: &AgentsConfig) -> HeadlessWorld {
+        let population = (agents.count > 0).then(|| Population::spawn(agents, &params, id.0 as u64));
+        HeadlessWorld { id, cells, ages, statistics, params, population }
+    }
+
+    /// Runs a generation and then steps the creatures on it. The statistics are of the generation alone.
+    pub fn tick(&mut self) -> GenerationStats {
+        let stats;
+        (self.cells, self.ages, stats) = tick(&self.cells, &self.ages, &self.params);
+        if let Some(population) = &mut self.population {
+            population.step(&mut self.cells, &mut self.ages);
+        }
+        self.statistics.0.push(stats);
+        stats
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.statistics.0.len() as u64 - 1
+    }
+}
+
+/// Runs every world on the CPU until `simulation.generations` without bevy or a window, so experiments can run on
+/// machines without a display or GPU. Snapshots, frames and the statistics of every generation are written to
+/// `headless.output`, and the first world is recorded like it would be in the app. The script's hook can stop the run
+/// early, which saves the worlds as if it had reached the last generation. With `replay.log` set the hashes of the
+/// worlds are logged for `--replay` to check.
+pub fn run_headless(config: &Config, recording: Option<RecordingSettings>, script: Option<&Script>) -> Result<(), Box<dyn Error>> {
+    let Some(last) = config.simulation.generations else {
+        return Err("headless runs need to know when to stop, pass --generations or set `simulation.generations`".into());
+    };
+    let output = Path::new(&config.headless.output);
+    fs::create_dir_all(output).map_err(|e| format!("couldn't create {}: {e}", output.display()))?;
+    let shared = config.world_params();
+    let mut worlds: Vec<HeadlessWorld> = config.worlds().into_iter().zip(starting_worlds(config, script)?).enumerate()
+        .map(|(id, (settings, start))| HeadlessWorld::new(WorldId(id), start, WorldRules::from(settings).params(&shared), &config.agents))
+        .collect();
+    let mut recorder = recording.map(|recording| Recorder::new(recording, worlds[0].params));
+    let mut broadcaster = match config.stream.port {
+        Some(port) => {
+            let hello = stream_hello(config.world.size, worlds[0].params.grid, &worlds[0].params.palette);
+            let broadcaster = Broadcaster::listen(port, hello, config.stream.keyframe_every).map_err(|e| format!("couldn't stream on port {port}: {e}"))?;
+            println!("Streaming the first world on 127.0.0.1:{port}");
+            Some(broadcaster)
+        }
+        None => None,
+    };
+    let mut replay_log = config.replay.log.as_ref().map(|path| ReplayLog::create(path, config).map_err(|e| format!("couldn't create {path}: {e}"))).transpose()?;
+
+    let started = Instant::now();
+    let mut stopped = false;
+    loop {
+        // Worlds all start at the same generation, so they all get there together
+        let finished = stopped || worlds[0].generation() >= last;
+        for world in &worlds {
+            save_outputs(config, output, world, finished)?;
+            if let Some(log) = replay_log.as_mut().filter(|log| finished || log.wants_hash(world.generation())) {
+                log.write_hash(world.id, world.generation(), &world.cells, &world.ages).map_err(|e| format!("couldn't write to the replay log: {e}"))?;
+            }
+        }
+        if let Some(recorder) = recorder.as_mut().filter(|recorder| recorder.wants(worlds[0].generation())) {
+            recorder.capture(&worlds[0].cells, &worlds[0].ages, &worlds[0].statistics)?;
+            if recorder.is_finished() {
+                println!("Saved recording to {}", recorder.path().display());
+            }
+        }
+        if let Some(broadcaster) = &mut broadcaster {
+            broadcaster.broadcast(worlds[0].generation(), &worlds[0].cells);
+        }
+        if finished {
+            break;
+        }
+        for world in &mut worlds {
+            let stats = world.tick();
+            if let Some(script) = script {
+                stopped |= script.on_generation(world.id, world.generation(), &stats)?;
+            }
+        }
+    }
+    let reached = worlds[0].generation();
+    if recorder.is_some_and(|recorder| !recorder.is_finished()) {
+        eprintln!("The recording ends after generation {reached}, so it wasn't saved");
+    }
+
+    let path = output.join("statistics.csv");
+    write_statistics(&path, &worlds).map_err(|e| format!("couldn't save statistics to {}: {e}", path.display()))?;
+    if stopped {
+        println!("The script stopped the run at generation {reached}");
+    }
+    println!("Reached generation {reached} in {:.1}s, saved statistics to {}", started.elapsed().as_secs_f32(), path.display());
+    Ok(())
+}
+
+/// Saves the snapshot and frame of `world` due at its current generation, if any. The `last` generation is always
+/// saved.
+fn save_outputs(config: &Config, output: &Path, world: &HeadlessWorld, last: bool) -> Result<(), Box<dyn Error>> {
+    let generation = world.generation();
+    let due = |every: Option<u64>| every.is_some_and(|every| generation % every == 0);
+    let stem = file_stem(world.id, config, generation);
+
+    if due(config.headless.snapshot_every) || last {
+        let path = output.join(format!("{stem}.npz"));
+        write_world(&path, config.world.size, &world.cells, &world.ages, &world.statistics).map_err(|e| format!("couldn't save {}: {e}", path.display()))?;
+        println!("Saved {}", path.display());
+    }
+    if due(config.headless.frame_every) {
+        let grid = world.params.grid;
+        let (width, height) = RecordingStyle::for_grid(grid).frame_size(config.world.size, config.headless.frame_scale);
+        let viewport = Viewport::whole_world(grid, config.world.size, width, height);
+        let path = output.join(format!("{stem}.png"));
+        rasterize(&world.cells, &world.ages, &world.params, width, height, viewport).save_png(&path)
+            .map_err(|e| format!("couldn't save {}: {e}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// One row per world and generation, with a column per statistic
+pub fn write_statistics(path: &Path, worlds: &[HeadlessWorld]) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let names: Vec<&str> = Statistics::default().series().iter().map(|(name, _)| *name).collect();
+    writeln!(file, "world,generation,{}", names.join(","))?;
+    for world in worlds {
+        let series = world.statistics.series();
+        for generation in 0..world.statistics.0.len() {
+            let values: Vec<String> = series.iter().map(|(_, values)| values[generation].to_string()).collect();
+            writeln!(file, "{},{generation},{}", world.id.0 + 1, values.join(","))?;
+        }
+    }
+    file.flush()
+}
@@ -0,0 +1,158 @@
+use std::collections::VecDeque;
+
+use bevy::{prelude::*, window::PrimaryWindow};
+use biosim_core::{hex_grid::{hexel_axial, Grid}, world::{Boundary, Cell, WorldCoord, WorldCursor, WorldOffset}};
+
+use crate::{biosim_plugin::{world_at_position, Generation, WorldComponent, WorldRules}, compute_shader::BiosimComputeShader, config::Config, painting::{cursor_over_ui, cursor_position}, simulator::Simulation, world::Region};
+
+/// How many generations of the hovered cell are remembered
+const HISTORY_LENGTH: usize = 32;
+
+/// Screen space gap between the cursor and the inspector
+const CURSOR_OFFSET: Vec2 = Vec2::new(16.0, 16.0);
+
+/// The cell under the cursor, along with the states it has been in since the cursor got there
+#[derive(Resource, Default)]
+pub struct Inspector {
+    /// The world the cell is in and the cell
+    pub hovered: Option<(Entity, WorldCoord)>,
+    /// Pairs of generation and state, oldest first
+    history: VecDeque<(u64, Cell)>,
+    /// The cells around the hovered one, which are only read again when the world changes
+    patch: Option<Patch>,
+}
+
+/// The 5×5 cells and ages around `coord` in `generation` of `world`, with the cell itself in the middle
+struct Patch {
+    world: Entity,
+    coord: WorldCoord,
+    generation: u64,
+    cells: [Cell; 25],
+    ages: [u32; 25],
+}
+
+impl Inspector {
+    /// Starts a new history whenever the hovered cell changes
+    pub fn hover(&mut self, hovered: Option<(Entity, WorldCoord)>) {
+        if hovered != self.hovered {
+            self.hovered = hovered;
+            self.history.clear();
+        }
+    }
+
+    /// Remembers the hovered cell's state in `generation`, which `update_world` calls after every tick so that none are
+    /// missed when several run in a frame
+    pub fn record(&mut self, generation: u64, cell: Cell) {
+        if self.history.back().is_some_and(|(last, _)| *last == generation) {
+            return;
+        }
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back((generation, cell));
+    }
+}
+
+/// Marks the text describing the hovered cell
+#[derive(Component)]
+pub struct InspectorText;
+
+pub fn spawn_inspector_text(mut commands: Commands) {
+    let style = TextStyle { font_size: 16.0, color: Color::WHITE, ..default() };
+    commands.spawn((
+        TextBundle::from_section("", style)
+            .with_style(Style { position_type: PositionType::Absolute, display: Display::None, padding: UiRect::all(Val::Px(6.0)), ..default() })
+            .with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.6)),
+        InspectorText,
+    ));
+}
+
+/// Shows the hovered cell's coordinates, state, live neighbours, next state and history next to the cursor
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn inspect(
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    world_query: Query<(Entity, Ref<WorldComponent>, &Generation, &BiosimComputeShader, Ref<WorldRules>, &Transform)>,
+    simulation: Res<Simulation>,
+    config: Res<Config>,
+    mut inspector: ResMut<Inspector>,
+    mut texts: Query<(&mut Text, &mut Style), With<InspectorText>>,
+    interactions: Query<&Interaction>,
+) {
+    let width = config.world.size;
+    let screen_position = windows.get_single().ok().and_then(|window| window.cursor_position());
+    let worlds = world_query.iter().map(|(entity, _, _, _, rules, transform)| (entity, transform, rules.into_inner()));
+    let hovered = cursor_position(&windows, &cameras).filter(|_| !cursor_over_ui(&interactions)).and_then(|position| world_at_position(worlds, &config, position));
+    inspector.hover(hovered);
+
+    let world = hovered.and_then(|(world, coord)| world_query.get(world).ok().map(|world| (coord, world)));
+    let (Some((coord, (entity, world_component, generation, compute_shader, rules, _))), Some(screen_position)) = (world, screen_position) else {
+        for (_, mut style) in &mut texts {
+            style.display = Display::None;
+        }
+        return;
+    };
+
+    // Edits and rule changes show up without a new generation, so they read the patch again too
+    let stale = world_component.is_changed() || rules.is_changed();
+    if stale || !inspector.patch.as_ref().is_some_and(|patch| (patch.world, patch.coord, patch.generation) == (entity, coord, generation.0)) {
+        let (cells, ages) = read_patch(&simulation, &world_component, compute_shader, &rules, width, coord);
+        inspector.patch = Some(Patch { world: entity, coord, generation: generation.0, cells, ages });
+    }
+    let patch = inspector.patch.as_ref().expect("the patch was just read");
+    let cursor = WorldCursor::new(&patch.cells, &patch.ages, 5, Boundary::Dead, rules.grid, WorldCoord { x: 2, y: 2 });
+    let cell = patch.cells[12];
+    let (age, living_neighbors, next_state) = (cursor.get_age(), cursor.count_living_neighbors(), cursor.get_new_state(rules.rule));
+    inspector.record(generation.0, cell);
+
+    let axial = match rules.grid {
+        Grid::Rect => String::new(),
+        Grid::Hex => {
+            let (q, r) = hexel_axial(coord);
+            format!("  axial ({q}, {r})")
+        }
+    };
+    let history: String = inspector.history.iter().map(|(_, cell)| if *cell == Cell::Alive { '#' } else { '.' }).collect();
+    let first_generation = inspector.history.front().map_or(generation.0, |(first, _)| *first);
+
+    for (mut text, mut style) in &mut texts {
+        text.sections[0].value = format!(
+            "Cell ({}, {}){axial}\n{cell:?} for {} generations, with {} live neighbours\nNext generation: {:?}\nHistory since generation {first_generation}: {history}",
+            coord.x,
+            coord.y,
+            age,
+            living_neighbors,
+            next_state,
+        );
+        style.display = Display::Flex;
+        style.left = Val::Px(screen_position.x + CURSOR_OFFSET.x);
+        style.top = Val::Px(screen_position.y + CURSOR_OFFSET.y);
+    }
+}
+
+/// Copies the cells around `coord` into a patch of their own, along with their ages
+fn read_patch(simulation: &Simulation, world_component: &WorldComponent, compute_shader: &BiosimComputeShader, rules: &WorldRules, width: usize, coord: WorldCoord) -> ([Cell; 25], [u32; 25]) {
+    // The rule only looks at the cells near this one. Hex neighbours can be two rows away (see `Grid::is_neighbor`), so
+    // the patch reaches that far. Off the edge of the world cells count as dead unless it wraps around.
+    let region = Region::around(Grid::Rect, width, coord, 2);
+    let (mut neighborhood, mut ages) = ([Cell::Dead; 25], [0; 25]);
+    let cells = simulation.read_region(world_component, compute_shader, region).into_iter().zip(simulation.read_region_ages(world_component, compute_shader, region));
+    for (neighbor, (cell, age)) in region.coords().zip(cells) {
+        let index = (neighbor.y + 2 - coord.y) * 5 + neighbor.x + 2 - coord.x;
+        (neighborhood[index], ages[index]) = (cell, age);
+    }
+    if rules.boundary == Boundary::Wrap {
+        // Neighbours across the edge are read one at a time, which only happens along the edges. Each only copies its
+        // own row back from the GPU.
+        for (index, (x, y)) in (-2..=2).flat_map(|y| (-2..=2).map(move |x| (x, y))).enumerate() {
+            if !rules.grid.is_neighbor(WorldOffset { x, y }) || coord.offset(WorldOffset { x, y }, width).0 {
+                continue;
+            }
+            let wrapped = coord.offset_wrapping(WorldOffset { x, y }, width);
+            let single = Region { min: wrapped, max: wrapped };
+            neighborhood[index] = simulation.read_region(world_component, compute_shader, single)[0];
+            ages[index] = simulation.read_region_ages(world_component, compute_shader, single)[0];
+        }
+    }
+    (neighborhood, ages)
+}
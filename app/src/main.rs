@@ -3,14 +3,70 @@
 use bevy::prelude::*;
 
 use biosim_plugin::BiosimPlugin;
+use clap::{error::ErrorKind, CommandFactory, Parser};
+use cli::Cli;
+use headless::run_headless;
+use replay::replay;
+use script::load_script;
 
 mod world;
 mod biosim_plugin;
 mod compute_shader;
+mod recording;
+mod rasterizer;
+mod svg;
+mod npy;
+mod statistics;
+mod config;
+mod playback;
+mod painting;
+mod selection;
+mod inspector;
+mod palettes;
+mod minimap;
+mod rules;
+mod control_panel;
+mod cli;
+mod rle;
+mod headless;
+mod simulator;
+mod script;
+mod rpc;
+mod streaming;
+mod replay;
+mod creatures;
 
 fn main() {
+    let cli = Cli::parse();
+    if let Some(log) = &cli.replay {
+        if let Err(e) = replay(log, cli.output.as_deref()) {
+            eprintln!("error: {e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    let mut config = cli.config().unwrap_or_else(|e| Cli::command().error(ErrorKind::ValueValidation, format!("invalid config: {e}")).exit());
+    if !cli.headless && config.agents.count > 0 {
+        eprintln!("warning: creatures only live in --headless runs for now, so the app leaves them out");
+        config.drop_agents();
+    }
+    if config.replay.log.is_some() {
+        config.pin_seeds();
+    }
+    let script = load_script(&mut config).unwrap_or_else(|e| Cli::command().error(ErrorKind::ValueValidation, format!("invalid script: {e}")).exit());
+    // Only the first world is recorded
+    let recording = config.recording().expect("config should have been validated");
+
+    if cli.headless {
+        if let Err(e) = run_headless(&config, recording, script.as_ref()) {
+            eprintln!("error: {e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     App::new()
-        .add_plugins((DefaultPlugins, BiosimPlugin))
+        .add_plugins((DefaultPlugins, BiosimPlugin { config, recording, script }))
         .run();
 }
 
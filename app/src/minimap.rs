@@ -0,0 +1,191 @@
+use std::time::Duration;
+
+use bevy::{prelude::*, render::{render_asset::RenderAssetUsages, render_resource::{Extent3d, TextureDimension, TextureFormat}}, window::PrimaryWindow};
+use biosim_core::{hex_grid::{uv_to_world_space, world_space_to_uv, Grid}, minimap::MinimapParams, render::BACKGROUND_COLOR, util::DOption};
+
+use crate::{biosim_plugin::{FocusedWorld, ShaderParams, WorldComponent, WorldRules}, compute_shader::BiosimComputeShader, config::Config, rasterizer::to_srgba8, simulator::Simulation};
+
+/// The minimap is as large as fits in this many pixels while keeping the shape of the world mesh
+const MINIMAP_BOUNDS: Vec2 = Vec2::new(360.0, 180.0);
+
+/// How often the minimap is redrawn, which needs the live cells of every block counted
+const MINIMAP_INTERVAL: Duration = Duration::from_millis(250);
+
+const VIEWPORT_COLOR: Color = Color::rgb(1.0, 0.2, 0.2);
+
+/// A downsampled view of the whole focused world in the bottom right corner, shading blocks of cells by how many are
+/// alive
+#[derive(Resource)]
+pub struct Minimap {
+    image: Handle<Image>,
+    params: MinimapParams,
+    /// The grid the image is shaped for, which follows the focused world
+    grid: Grid,
+    /// Size of the image, which is drawn one to one on screen
+    size: UVec2,
+    timer: Timer,
+}
+
+/// Marks the node showing the minimap, which can be clicked to jump there
+#[derive(Component)]
+pub struct MinimapNode;
+
+/// Marks the outline of what the camera sees on the minimap
+#[derive(Component)]
+pub struct MinimapViewport;
+
+/// Size of the minimap of a world with `grid`
+fn minimap_size(grid: Grid) -> UVec2 {
+    let multiplier = grid.width_multiplier();
+    let height = (MINIMAP_BOUNDS.x / multiplier).min(MINIMAP_BOUNDS.y);
+    UVec2::new((height * multiplier) as u32, height as u32)
+}
+
+pub fn spawn_minimap(mut commands: Commands, mut images: ResMut<Assets<Image>>, config: Res<Config>) {
+    let grid = config.worlds()[0].grid;
+    let size = minimap_size(grid);
+
+    let extent = Extent3d { width: size.x, height: size.y, depth_or_array_layers: 1 };
+    let image = images.add(Image::new_fill(extent, TextureDimension::D2, &to_srgba8(BACKGROUND_COLOR), TextureFormat::Rgba8UnormSrgb, RenderAssetUsages::default()));
+
+    let style = Style {
+        position_type: PositionType::Absolute,
+        right: Val::Px(8.0),
+        bottom: Val::Px(8.0),
+        width: Val::Px(size.x as f32),
+        height: Val::Px(size.y as f32),
+        overflow: Overflow::clip(),
+        ..default()
+    };
+    commands.spawn((ImageBundle { image: UiImage::new(image.clone()), style, background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(), ..default() }, Interaction::default(), MinimapNode))
+        .with_children(|minimap| {
+            minimap.spawn((
+                NodeBundle { style: Style { position_type: PositionType::Absolute, border: UiRect::all(Val::Px(1.0)), ..default() }, border_color: BorderColor(VIEWPORT_COLOR), ..default() },
+                MinimapViewport,
+            ));
+        });
+
+    let timer = Timer::new(MINIMAP_INTERVAL, TimerMode::Repeating);
+    commands.insert_resource(Minimap { image, params: MinimapParams::new(config.world.size as u32), grid, size, timer });
+}
+
+/// Redraws the minimap every `MINIMAP_INTERVAL`. On the GPU only the populations of the blocks are read back.
+#[allow(clippy::too_many_arguments)]
+pub fn update_minimap(
+    time: Res<Time>,
+    mut minimap: ResMut<Minimap>,
+    mut images: ResMut<Assets<Image>>,
+    world_query: Query<(&WorldComponent, &BiosimComputeShader, &WorldRules)>,
+    focused: Res<FocusedWorld>,
+    simulation: Res<Simulation>,
+    config: Res<Config>,
+    params: Res<ShaderParams>,
+    mut nodes: Query<&mut Style, With<MinimapNode>>,
+) {
+    if !minimap.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let Some((world_component, compute_shader, rules)) = focused.0.and_then(|entity| world_query.get(entity).ok()) else {
+        return;
+    };
+    let Some(image) = images.get_mut(&minimap.image) else {
+        return;
+    };
+
+    // Hex worlds are wider than rect ones, so the minimap changes shape when focusing a world with another grid
+    if rules.grid != minimap.grid {
+        let size = minimap_size(rules.grid);
+        (minimap.grid, minimap.size) = (rules.grid, size);
+        image.resize(Extent3d { width: size.x, height: size.y, depth_or_array_layers: 1 });
+        for mut style in &mut nodes {
+            (style.width, style.height) = (Val::Px(size.x as f32), Val::Px(size.y as f32));
+        }
+    }
+
+    let populations = simulation.minimap_populations(world_component, compute_shader, &minimap.params);
+
+    let (grid, width, size) = (minimap.grid, config.world.size, minimap.size);
+    let [dead, alive] = [params.0.palette.states[0], params.0.palette.states[1]];
+    for row in 0..size.y {
+        for column in 0..size.x {
+            // Image rows go down but uvs go up
+            let (u, v) = ((column as f32 + 0.5) / size.x as f32, 1.0 - (row as f32 + 0.5) / size.y as f32);
+            let color = match grid.coord_at(width, u, v) {
+                DOption(true, coord) => {
+                    let block_size = minimap.params.block_size as usize;
+                    let (block_x, block_y) = ((coord.x / block_size) as u32, (coord.y / block_size) as u32);
+                    let density = populations[minimap.params.block_index(coord)] as f32 / minimap.params.block_area(block_x, block_y) as f32;
+                    std::array::from_fn(|channel| dead[channel] + (alive[channel] - dead[channel]) * density)
+                }
+                DOption(false, _) => BACKGROUND_COLOR,
+            };
+            let index = ((row * size.x + column) * 4) as usize;
+            image.data[index..index + 4].copy_from_slice(&to_srgba8(color));
+        }
+    }
+}
+
+/// Outlines what the camera currently sees on the minimap
+#[allow(clippy::too_many_arguments)]
+pub fn update_minimap_viewport(
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Transform, &OrthographicProjection), With<Camera2d>>,
+    worlds: Query<(&Transform, &WorldRules)>,
+    focused: Res<FocusedWorld>,
+    minimap: Res<Minimap>,
+    config: Res<Config>,
+    mut viewports: Query<&mut Style, With<MinimapViewport>>,
+) {
+    let (Ok(window), Ok((camera_transform, projection))) = (windows.get_single(), cameras.get_single()) else {
+        return;
+    };
+    let Some((world_transform, rules)) = focused.0.and_then(|entity| worlds.get(entity).ok()) else {
+        return;
+    };
+    let (grid, width) = (rules.grid, config.world.size);
+    let center = camera_transform.translation.truncate() - world_transform.translation.truncate();
+    let half_view = Vec2::new(window.width(), window.height()) * projection.scale / 2.0;
+    let to_pixels = |position: Vec2| {
+        let (u, v) = world_space_to_uv(grid, width, position.x, position.y);
+        (Vec2::new(u, 1.0 - v) * minimap.size.as_vec2()).clamp(Vec2::ZERO, minimap.size.as_vec2())
+    };
+    // The top left corner on screen is the bottom left in world space
+    let top_left = to_pixels(center + Vec2::new(-half_view.x, half_view.y));
+    let bottom_right = to_pixels(center + Vec2::new(half_view.x, -half_view.y));
+
+    for mut style in &mut viewports {
+        style.left = Val::Px(top_left.x);
+        style.top = Val::Px(top_left.y);
+        style.width = Val::Px(bottom_right.x - top_left.x);
+        style.height = Val::Px(bottom_right.y - top_left.y);
+    }
+}
+
+/// Moves the camera to wherever the minimap is clicked or dragged over
+pub fn jump_to_minimap(
+    windows: Query<&Window, With<PrimaryWindow>>,
+    minimap_nodes: Query<(&Interaction, &Node, &GlobalTransform), With<MinimapNode>>,
+    mut cameras: Query<&mut Transform, (With<Camera2d>, Without<WorldComponent>)>,
+    worlds: Query<(&Transform, &WorldRules), With<WorldComponent>>,
+    focused: Res<FocusedWorld>,
+    config: Res<Config>,
+) {
+    let Some(cursor) = windows.get_single().ok().and_then(|window| window.cursor_position()) else {
+        return;
+    };
+    let Some((world_center, grid)) = focused.0.and_then(|entity| worlds.get(entity).ok()).map(|(transform, rules)| (transform.translation.truncate(), rules.grid)) else {
+        return;
+    };
+    for (interaction, node, transform) in &minimap_nodes {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let bounds = Rect::from_center_size(transform.translation().truncate(), node.size());
+        let uv = ((cursor - bounds.min) / bounds.size()).clamp(Vec2::ZERO, Vec2::ONE);
+        let (x, y) = uv_to_world_space(grid, config.world.size, uv.x, 1.0 - uv.y);
+        for mut camera_transform in &mut cameras {
+            camera_transform.translation.x = world_center.x + x;
+            camera_transform.translation.y = world_center.y + y;
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use std::{fs::File, io::{self, BufReader, BufWriter, Read, Write}, path::Path};
+
+use biosim_core::{statistics::GenerationStats, world::Cell};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::statistics::Statistics;
+
+/// Types that can be written into a numpy array
+pub trait Element: Copy {
+    /// numpy's name for the type
+    const DESCR: &'static str;
+    const SIZE: usize;
+
+    fn extend_le_bytes(self, bytes: &mut Vec<u8>);
+
+    /// The opposite of `extend_le_bytes`, `bytes` is exactly `SIZE` long
+    fn from_le_bytes(bytes: &[u8]) -> Self;
+}
+
+impl Element for u8 {
+    const DESCR: &'static str = "|u1";
+    const SIZE: usize = 1;
+
+    fn extend_le_bytes(self, bytes: &mut Vec<u8>) {
+        bytes.push(self);
+    }
+
+    fn from_le_bytes(bytes: &[u8]) -> Self {
+        bytes[0]
+    }
+}
+
+impl Element for u32 {
+    const DESCR: &'static str = "<u4";
+    const SIZE: usize = 4;
+
+    fn extend_le_bytes(self, bytes: &mut Vec<u8>) {
+        bytes.extend(self.to_le_bytes());
+    }
+
+    fn from_le_bytes(bytes: &[u8]) -> Self {
+        u32::from_le_bytes(bytes.try_into().unwrap())
+    }
+}
+
+impl Element for u64 {
+    const DESCR: &'static str = "<u8";
+    const SIZE: usize = 8;
+
+    fn extend_le_bytes(self, bytes: &mut Vec<u8>) {
+        bytes.extend(self.to_le_bytes());
+    }
+
+    fn from_le_bytes(bytes: &[u8]) -> Self {
+        u64::from_le_bytes(bytes.try_into().unwrap())
+    }
+}
+
+/// The shape of a single generation of a world `width` cells wide. Arrays are indexed by `[y, x]`, so the first row is
+/// the bottom of the world.
+pub fn world_shape(width: usize) -> [usize; 2] {
+    [width, width]
+}
+
+/// Cells as numbers, dead cells are 0 and live cells 1
+pub fn cell_states(cells: &[Cell]) -> Vec<u8> {
+    cells.iter().map(|cell| *cell as u8).collect()
+}
+
+/// The opposite of `cell_states`
+pub fn cells_from_states(states: &[u8]) -> io::Result<Vec<Cell>> {
+    states.iter().map(|state| match state {
+        0 => Ok(Cell::Dead),
+        1 => Ok(Cell::Alive),
+        _ => Err(invalid_data(format!("{state} isn't a cell state, expected 0 for dead or 1 for alive"))),
+    }).collect()
+}
+
+/// Writes the header of a (version 1.0) npy file. It has to be followed by exactly as many elements as `shape` holds.
+pub fn write_header<T: Element>(writer: &mut impl Write, shape: &[usize]) -> io::Result<()> {
+    let dimensions: Vec<String> = shape.iter().map(|length| length.to_string()).collect();
+    // Python needs a trailing comma to tell a one element tuple apart from a number
+    let shape = if shape.len() == 1 { format!("({},)", dimensions[0]) } else { format!("({})", dimensions.join(", ")) };
+    let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {shape}, }}", T::DESCR);
+
+    // Everything up to the data needs to be a multiple of 64 bytes, counting the 10 bytes before the header and the
+    // newline that ends it
+    let padding = (64 - (10 + header.len() + 1) % 64) % 64;
+    header.extend(std::iter::repeat(' ').take(padding));
+    header.push('\n');
+
+    writer.write_all(b"\x93NUMPY\x01\x00")?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())
+}
+
+/// Reads the header `write_header` writes, returning the shape of the array. Arrays of other types than `T` or in
+/// Fortran order are refused.
+pub fn read_header<T: Element>(reader: &mut impl Read) -> io::Result<Vec<usize>> {
+    let mut preamble = [0; 8];
+    reader.read_exact(&mut preamble)?;
+    if &preamble[..6] != b"\x93NUMPY" {
+        return Err(invalid_data("not an npy file".to_string()));
+    }
+    // Version 1 stores the length of the header in 2 bytes, later versions in 4
+    let header_length = if preamble[6] == 1 {
+        let mut length = [0; 2];
+        reader.read_exact(&mut length)?;
+        u16::from_le_bytes(length) as usize
+    } else {
+        let mut length = [0; 4];
+        reader.read_exact(&mut length)?;
+        u32::from_le_bytes(length) as usize
+    };
+    let mut header = vec![0; header_length];
+    reader.read_exact(&mut header)?;
+    let header = String::from_utf8_lossy(&header);
+
+    let value_of = |key: &str| header.split_once(&format!("'{key}':")).map(|(_, rest)| rest.trim_start());
+    let descr = value_of("descr").and_then(|rest| rest.strip_prefix('\'')).and_then(|rest| rest.split('\'').next());
+    if descr != Some(T::DESCR) {
+        return Err(invalid_data(format!("expected an array of {}, got {}", T::DESCR, descr.unwrap_or("an unknown type"))));
+    }
+    if value_of("fortran_order").is_some_and(|rest| rest.starts_with("True")) {
+        return Err(invalid_data("arrays in Fortran order aren't supported".to_string()));
+    }
+    let shape = value_of("shape").and_then(|rest| rest.strip_prefix('(')).and_then(|rest| rest.split(')').next())
+        .ok_or_else(|| invalid_data("the header has no shape".to_string()))?;
+    shape.split(',').map(str::trim).filter(|length| !length.is_empty())
+        .map(|length| length.parse().map_err(|_| invalid_data(format!("`{length}` isn't a length"))))
+        .collect()
+}
+
+pub fn read_data<T: Element>(reader: &mut impl Read, length: usize) -> io::Result<Vec<T>> {
+    let size = length.checked_mul(T::SIZE).ok_or_else(|| invalid_data(format!("{length} elements are too many")))?;
+    let mut bytes = vec![0; size];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes.chunks_exact(T::SIZE).map(T::from_le_bytes).collect())
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub fn write_data<T: Element>(writer: &mut impl Write, data: &[T]) -> io::Result<()> {
+    let mut bytes = Vec::with_capacity(data.len() * T::SIZE);
+    for element in data {
+        element.extend_le_bytes(&mut bytes);
+    }
+    writer.write_all(&bytes)
+}
+
+/// Saves a single generation's cells and ages along with the statistics so far, which is what F10 and the control panel
+/// save and `load_npz` reads
+pub fn write_world(path: &Path, width: usize, cells: &[Cell], ages: &[u32], statistics: &Statistics) -> io::Result<()> {
+    let mut npz = NpzWriter::create(path)?;
+    npz.add_array("cells", &world_shape(width), &cell_states(cells))?;
+    npz.add_array("ages", &world_shape(width), ages)?;
+    npz.add_statistics(statistics)?;
+    npz.finish()
+}
+
+/// Writes several named arrays into a single npz file, which numpy loads with `np.load`
+pub struct NpzWriter {
+    zip: ZipWriter<BufWriter<File>>,
+}
+
+impl NpzWriter {
+    pub fn create(path: &Path) -> io::Result<NpzWriter> {
+        Ok(NpzWriter { zip: ZipWriter::new(BufWriter::new(File::create(path)?)) })
+    }
+
+    /// Starts a new array, which then has to be filled by calls to `write_data` before starting the next one
+    pub fn start_array<T: Element>(&mut self, name: &str, shape: &[usize]) -> io::Result<()> {
+        let size = shape.iter().product::<usize>() * T::SIZE;
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated).large_file(size >= u32::MAX as usize);
+        self.zip.start_file(format!("{name}.npy"), options).map_err(io::Error::other)?;
+        write_header::<T>(&mut self.zip, shape)
+    }
+
+    pub fn write_data<T: Element>(&mut self, data: &[T]) -> io::Result<()> {
+        write_data(&mut self.zip, data)
+    }
+
+    pub fn add_array<T: Element>(&mut self, name: &str, shape: &[usize], data: &[T]) -> io::Result<()> {
+        self.start_array::<T>(name, shape)?;
+        self.write_data(data)
+    }
+
+    /// Adds one array per statistic, each indexed by generation
+    pub fn add_statistics(&mut self, statistics: &Statistics) -> io::Result<()> {
+        for (name, series) in statistics.series() {
+            self.add_array(name, &[series.len()], &series)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.zip.finish().map_err(io::Error::other)?.flush()
+    }
+}
+
+/// Reads named arrays out of an npz file, like the ones `NpzWriter` writes
+pub struct NpzReader {
+    zip: ZipArchive<BufReader<File>>,
+}
+
+impl NpzReader {
+    pub fn open(path: &Path) -> io::Result<NpzReader> {
+        Ok(NpzReader { zip: ZipArchive::new(BufReader::new(File::open(path)?)).map_err(io::Error::other)? })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.zip.index_for_name(&format!("{name}.npy")).is_some()
+    }
+
+    /// The shape and elements of the array called `name`
+    pub fn read_array<T: Element>(&mut self, name: &str) -> io::Result<(Vec<usize>, Vec<T>)> {
+        self.read_entry(name, None)
+    }
+
+    /// The elements of the array called `name`, which has to have `shape`
+    pub fn read_array_of_shape<T: Element>(&mut self, name: &str, shape: &[usize]) -> io::Result<Vec<T>> {
+        Ok(self.read_entry(name, Some(shape))?.1)
+    }
+
+    /// Checks the shape in the header against `expected` and the size of the entry before reading anything, so a broken
+    /// or hostile header can't make it allocate more than the file holds
+    fn read_entry<T: Element>(&mut self, name: &str, expected: Option<&[usize]>) -> io::Result<(Vec<usize>, Vec<T>)> {
+        let mut file = self.zip.by_name(&format!("{name}.npy")).map_err(|e| io::Error::other(format!("{name}: {e}")))?;
+        let shape = read_header::<T>(&mut file).map_err(|e| io::Error::new(e.kind(), format!("{name}: {e}")))?;
+        if let Some(expected) = expected.filter(|expected| shape != *expected) {
+            return Err(invalid_data(format!("{name} has shape {shape:?}, expected {expected:?}")));
+        }
+        let size = shape.iter().try_fold(T::SIZE, |size, length| size.checked_mul(*length)).filter(|size| *size as u64 <= file.size());
+        let Some(size) = size else {
+            return Err(invalid_data(format!("{name} has shape {shape:?}, which is more than the {} bytes it takes up", file.size())));
+        };
+        let data = read_data(&mut file, size / T::SIZE)?;
+        Ok((shape, data))
+    }
+
+    /// The statistics `NpzWriter::add_statistics` added, if they're all there
+    pub fn read_statistics(&mut self) -> io::Result<Option<Statistics>> {
+        if !["population", "births", "deaths"].iter().all(|name| self.contains(name)) {
+            return Ok(None);
+        }
+        let (_, population) = self.read_array::<u32>("population")?;
+        let (_, births) = self.read_array::<u32>("births")?;
+        let (_, deaths) = self.read_array::<u32>("deaths")?;
+        if population.len() != births.len() || population.len() != deaths.len() {
+            return Err(invalid_data("the statistics cover different numbers of generations".to_string()));
+        }
+        let stats = population.into_iter().zip(births).zip(deaths).map(|((population, births), deaths)| GenerationStats { population, births, deaths });
+        Ok(Some(Statistics(stats.collect())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf};
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("biosim-npy-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn headers_round_trip() {
+        for shape in [vec![7], vec![3, 5]] {
+            let mut bytes = Vec::new();
+            write_header::<u32>(&mut bytes, &shape).unwrap();
+            assert_eq!(bytes.len() % 64, 0);
+            assert_eq!(read_header::<u32>(&mut bytes.as_slice()).unwrap(), shape);
+            assert!(read_header::<u8>(&mut bytes.as_slice()).is_err());
+        }
+    }
+
+    #[test]
+    fn worlds_round_trip() {
+        let cells: Vec<Cell> = (0..16).map(|i| if i % 3 == 0 { Cell::Alive } else { Cell::Dead }).collect();
+        let ages: Vec<u32> = (0..16).map(|i| i * 100_000).collect();
+        let statistics = Statistics(vec![GenerationStats::initial(&cells), GenerationStats { population: 4, births: 1, deaths: 3 }]);
+        let path = temp_path("world.npz");
+        write_world(&path, 4, &cells, &ages, &statistics).unwrap();
+
+        let mut npz = NpzReader::open(&path).unwrap();
+        let states = npz.read_array_of_shape::<u8>("cells", &world_shape(4)).unwrap();
+        assert_eq!(cells_from_states(&states).unwrap(), cells);
+        assert_eq!(npz.read_array_of_shape::<u32>("ages", &world_shape(4)).unwrap(), ages);
+        let read = npz.read_statistics().unwrap().unwrap();
+        let series = |statistics: &Statistics| statistics.0.iter().map(|stats| (stats.population, stats.births, stats.deaths)).collect::<Vec<_>>();
+        assert_eq!(series(&read), series(&statistics));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn other_world_sizes_are_rejected() {
+        let path = temp_path("small.npz");
+        write_world(&path, 4, &[Cell::Dead; 16], &[0; 16], &Statistics::default()).unwrap();
+        let error = NpzReader::open(&path).unwrap().read_array_of_shape::<u8>("cells", &world_shape(8)).unwrap_err();
+        assert!(error.to_string().contains("expected [8, 8]"), "{error}");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn oversized_entries_are_rejected() {
+        let path = temp_path("oversized.npz");
+        let mut npz = NpzWriter::create(&path).unwrap();
+        // The header promises far more than follows it
+        npz.start_array::<u32>("ages", &[1 << 20, 1 << 20]).unwrap();
+        npz.write_data(&[0u32; 16]).unwrap();
+        npz.finish().unwrap();
+        let error = NpzReader::open(&path).unwrap().read_array::<u32>("ages").unwrap_err();
+        assert!(error.to_string().contains("more than the"), "{error}");
+        fs::remove_file(&path).unwrap();
+    }
+}
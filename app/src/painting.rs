@@ -0,0 +1,153 @@
+use std::collections::HashSet;
+
+use bevy::{prelude::*, window::PrimaryWindow};
+use biosim_core::world::{get_index, Cell, WorldCoord};
+
+use crate::{biosim_plugin::{edit_cells, fill_cells, WorldComponent, WorldRules}, compute_shader::BiosimComputeShader, config::Config, selection::Clipboard, simulator::Simulation, world::{coord_at_position, Region}};
+
+/// Largest brush radius in cells
+const MAX_BRUSH_RADIUS: usize = 64;
+
+/// Distance in world space between the brush stamps filling in a stroke, small enough that cells can't be skipped
+const STROKE_SPACING: f32 = 0.5;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PaintMode {
+    Draw,
+    Erase,
+    /// Flips each cell at most once per stroke
+    Toggle,
+}
+
+/// What dragging with the left mouse button does to the cells under the cursor
+#[derive(Resource)]
+pub struct Brush {
+    pub mode: PaintMode,
+    /// Cells up to this many steps from the cursor are painted, see `Grid::distance`
+    pub radius: usize,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Brush { mode: PaintMode::Draw, radius: 0 }
+    }
+}
+
+impl Brush {
+    fn paint(&self, cell: Cell) -> Cell {
+        match (self.mode, cell) {
+            (PaintMode::Draw, _) | (PaintMode::Toggle, Cell::Dead) => Cell::Alive,
+            (PaintMode::Erase, _) | (PaintMode::Toggle, Cell::Alive) => Cell::Dead,
+        }
+    }
+}
+
+/// D, E and T pick drawing, erasing and toggling, and the square brackets shrink and grow the brush
+pub fn handle_brush_keys(keys: Res<ButtonInput<KeyCode>>, mut brush: ResMut<Brush>) {
+    if keys.just_pressed(KeyCode::KeyD) {
+        brush.mode = PaintMode::Draw;
+    }
+    if keys.just_pressed(KeyCode::KeyE) {
+        brush.mode = PaintMode::Erase;
+    }
+    if keys.just_pressed(KeyCode::KeyT) {
+        brush.mode = PaintMode::Toggle;
+    }
+    if keys.just_pressed(KeyCode::BracketLeft) {
+        brush.radius = brush.radius.saturating_sub(1);
+    }
+    if keys.just_pressed(KeyCode::BracketRight) {
+        brush.radius = (brush.radius + 1).min(MAX_BRUSH_RADIUS);
+    }
+}
+
+/// The stroke drawn since the left mouse button was pressed
+#[derive(Default)]
+pub struct Stroke {
+    /// Strokes only start on a fresh click that isn't meant for the selection tools
+    active: bool,
+    last_position: Option<Vec2>,
+    /// The worlds and indices of the cells painted so far
+    painted: HashSet<(Entity, usize)>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn paint(
+    buttons: Res<ButtonInput<MouseButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    brush: Res<Brush>,
+    clipboard: Res<Clipboard>,
+    simulation: Res<Simulation>,
+    config: Res<Config>,
+    mut world_query: Query<(Entity, &mut WorldComponent, &BiosimComputeShader, &WorldRules, &Transform)>,
+    interactions: Query<&Interaction>,
+    mut stroke: Local<Stroke>,
+) {
+    if buttons.just_pressed(MouseButton::Left) {
+        stroke.active = !clipboard.wants_mouse(&keys) && !cursor_over_ui(&interactions);
+    }
+    if !buttons.pressed(MouseButton::Left) || !stroke.active {
+        *stroke = Stroke::default();
+        return;
+    }
+    let Some(position) = cursor_position(&windows, &cameras) else {
+        return;
+    };
+
+    // Stamp the brush all along the way from the last frame's cursor position, so fast strokes don't leave gaps
+    let width = config.world.size;
+    let from = stroke.last_position.unwrap_or(position);
+    stroke.last_position = Some(position);
+    let stamps = (from.distance(position) / STROKE_SPACING).ceil() as usize;
+
+    // Strokes can cross from one world into another, which each see the stroke relative to their own mesh
+    for (entity, mut world_component, compute_shader, rules, transform) in &mut world_query {
+        let grid = rules.grid;
+        let world_center = transform.translation.truncate();
+        let centers = (0..=stamps).filter_map(|stamp| coord_at_position(grid, width, from.lerp(position, stamp as f32 / stamps.max(1) as f32) - world_center));
+
+        let mut targets = HashSet::new();
+        let mut region: Option<Region> = None;
+        for center in centers {
+            let around = Region::around(grid, width, center, brush.radius);
+            for coord in around.coords().filter(|coord| grid.distance(center, *coord) <= brush.radius as u32) {
+                let index = get_index(coord, width);
+                if stroke.painted.insert((entity, index)) || brush.mode != PaintMode::Toggle {
+                    targets.insert(index);
+                }
+            }
+            region = Some(region.map_or(around, |region| region.union(around)));
+        }
+        let Some(region) = region.filter(|_| !targets.is_empty()) else {
+            continue;
+        };
+
+        // Drawing and erasing don't depend on what the cells were, so only toggling has to read them back
+        match brush.mode {
+            PaintMode::Draw | PaintMode::Erase => {
+                let mut targets: Vec<usize> = targets.into_iter().collect();
+                targets.sort_unstable();
+                let coords = targets.into_iter().map(|index| WorldCoord { x: index % width, y: index / width });
+                fill_cells(&mut world_component, compute_shader, &simulation, coords, brush.paint(Cell::Dead));
+            }
+            PaintMode::Toggle => edit_cells(&mut world_component, compute_shader, &simulation, region, |coord: WorldCoord, cell| {
+                if targets.contains(&get_index(coord, width)) { brush.paint(cell) } else { cell }
+            }),
+        }
+    }
+}
+
+/// Whether the cursor is over a UI node that takes clicks, like the minimap, rather than the world
+pub fn cursor_over_ui(interactions: &Query<&Interaction>) -> bool {
+    interactions.iter().any(|interaction| *interaction != Interaction::None)
+}
+
+/// Where the cursor is in world space, if it's over the window
+pub fn cursor_position(windows: &Query<&Window, With<PrimaryWindow>>, cameras: &Query<(&Camera, &GlobalTransform)>) -> Option<Vec2> {
+    let (Ok(window), Ok((camera, camera_transform))) = (windows.get_single(), cameras.get_single()) else {
+        return None;
+    };
+    window.cursor_position().and_then(|cursor| camera.viewport_to_world_2d(camera_transform, cursor))
+}
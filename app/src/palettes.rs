@@ -0,0 +1,49 @@
+use bevy::prelude::*;
+use biosim_core::render::{Palette, RenderMode};
+
+use crate::biosim_plugin::ShaderParams;
+
+/// Palettes that are always available, as sRGB colours for each state and the stops of the gradient. `classic` is the
+/// same as `Palette::default`.
+pub const BUILT_IN_PALETTES: [(&str, &[&str], &[&str]); 3] = [
+    ("classic", &["#ffffff", "#000000"], &["#000000", "#898989", "#bcbcbc", "#e1e1e1"]),
+    ("dark", &["#121212", "#e0e0e0"], &["#e0e0e0", "#ffb000", "#fe6100", "#dc267f"]),
+    // Okabe and Ito's blue, and viridis for the gradient, which stay distinguishable with every kind of colour blindness
+    ("colorblind", &["#ffffff", "#0072b2"], &["#440154", "#31688e", "#35b779", "#fde725"]),
+];
+
+/// Every palette the app can switch between, built in ones first
+#[derive(Resource)]
+pub struct Palettes {
+    pub palettes: Vec<(String, Palette)>,
+    pub current: usize,
+}
+
+impl Palettes {
+    pub fn current(&self) -> &(String, Palette) {
+        &self.palettes[self.current]
+    }
+}
+
+/// P switches to the next palette and shift+P to the previous one. A toggles colouring live cells by their age and B
+/// toggles highlighting the cells born and died in the last generation.
+pub fn handle_palette_keys(keys: Res<ButtonInput<KeyCode>>, mut palettes: ResMut<Palettes>, mut params: ResMut<ShaderParams>) {
+    for (key, mode) in [(KeyCode::KeyA, RenderMode::Age), (KeyCode::KeyB, RenderMode::Delta)] {
+        if keys.just_pressed(key) {
+            params.0.render_mode = if params.0.render_mode == mode { RenderMode::State } else { mode };
+        }
+    }
+    if !keys.just_pressed(KeyCode::KeyP) {
+        return;
+    }
+    let count = palettes.palettes.len();
+    palettes.current = if keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        (palettes.current + count - 1) % count
+    } else {
+        (palettes.current + 1) % count
+    };
+
+    let (name, palette) = palettes.current();
+    params.0.palette = *palette;
+    info!("Switched to the {name} palette");
+}
@@ -0,0 +1,150 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::biosim_plugin::Generation;
+
+/// How long fast forward may spend ticking each frame, so the app still draws at a reasonable frame rate
+pub const FAST_FORWARD_BUDGET: Duration = Duration::from_millis(12);
+
+pub const MIN_TICK_INTERVAL: f32 = 0.001;
+pub const MAX_TICK_INTERVAL: f32 = 10.0;
+
+/// Decides how many generations run each frame. Changed with the keyboard, see `handle_playback_keys`.
+#[derive(Resource)]
+pub struct Playback {
+    pub paused: bool,
+    /// Run as many generations as fit in `FAST_FORWARD_BUDGET` every frame, ignoring the tick interval
+    pub fast_forward: bool,
+    /// Generations still to run one after the other while paused, see `step`
+    steps_requested: u32,
+    timer: Timer,
+    /// Generations per second, measured over roughly the last second
+    rate: f32,
+    generations_since_measuring: u32,
+    time_since_measuring: Duration,
+}
+
+impl Playback {
+    pub fn new(tick_interval: f32, paused: bool) -> Playback {
+        Playback {
+            paused,
+            fast_forward: false,
+            steps_requested: 0,
+            timer: Timer::from_seconds(tick_interval, TimerMode::Repeating),
+            rate: 0.0,
+            generations_since_measuring: 0,
+            time_since_measuring: Duration::ZERO,
+        }
+    }
+
+    pub fn tick_interval(&self) -> f32 {
+        self.timer.duration().as_secs_f32()
+    }
+
+    pub fn set_tick_interval(&mut self, tick_interval: f32) {
+        self.timer.set_duration(Duration::from_secs_f32(tick_interval.clamp(MIN_TICK_INTERVAL, MAX_TICK_INTERVAL)));
+    }
+
+    /// Pauses after advancing a single generation
+    pub fn step(&mut self) {
+        self.step_by(1);
+    }
+
+    /// Pauses after advancing `generations` more generations, which can take several frames
+    pub fn step_by(&mut self, generations: u32) {
+        self.paused = true;
+        self.fast_forward = false;
+        self.steps_requested = self.steps_requested.saturating_add(generations);
+    }
+
+    /// Generations requested by `step_by` that haven't run yet
+    pub fn steps_requested(&self) -> u32 {
+        self.steps_requested
+    }
+
+    pub fn rate(&self) -> f32 {
+        self.rate
+    }
+
+    /// How many generations are due after `delta` of time has passed. Fast forward asks for as many as possible, which
+    /// callers should cut off at `FAST_FORWARD_BUDGET`.
+    pub fn generations_due(&mut self, delta: Duration) -> u32 {
+        self.time_since_measuring += delta;
+        if self.time_since_measuring >= Duration::from_secs(1) {
+            self.rate = self.generations_since_measuring as f32 / self.time_since_measuring.as_secs_f32();
+            (self.generations_since_measuring, self.time_since_measuring) = (0, Duration::ZERO);
+        }
+
+        if self.steps_requested > 0 {
+            self.steps_requested
+        } else if self.paused {
+            0
+        } else if self.fast_forward {
+            u32::MAX
+        } else {
+            self.timer.tick(delta).times_finished_this_tick()
+        }
+    }
+
+    /// Counts generations towards the measured rate and the steps that are left
+    pub fn count_generations(&mut self, generations: u32) {
+        self.generations_since_measuring += generations;
+        self.steps_requested = self.steps_requested.saturating_sub(generations);
+    }
+}
+
+/// Space pauses and resumes, period steps a single generation, minus and equals halve or double the speed and F
+/// toggles fast forward
+pub fn handle_playback_keys(keys: Res<ButtonInput<KeyCode>>, mut playback: ResMut<Playback>) {
+    if keys.just_pressed(KeyCode::Space) {
+        playback.paused = !playback.paused;
+    }
+    if keys.just_pressed(KeyCode::Period) {
+        playback.step();
+    }
+    if keys.any_just_pressed([KeyCode::Minus, KeyCode::NumpadSubtract]) {
+        let tick_interval = playback.tick_interval() * 2.0;
+        playback.set_tick_interval(tick_interval);
+    }
+    if keys.any_just_pressed([KeyCode::Equal, KeyCode::NumpadAdd]) {
+        let tick_interval = playback.tick_interval() / 2.0;
+        playback.set_tick_interval(tick_interval);
+    }
+    if keys.just_pressed(KeyCode::KeyF) {
+        playback.fast_forward = !playback.fast_forward;
+        playback.paused = false;
+    }
+}
+
+/// Marks the text showing the generation and playback state
+#[derive(Component)]
+pub struct PlaybackText;
+
+pub fn spawn_playback_text(mut commands: Commands) {
+    let style = TextStyle { font_size: 18.0, color: Color::WHITE, ..default() };
+    commands.spawn((
+        TextBundle::from_section("", style)
+            .with_style(Style { position_type: PositionType::Absolute, top: Val::Px(8.0), left: Val::Px(8.0), padding: UiRect::all(Val::Px(6.0)), ..default() })
+            .with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.6)),
+        PlaybackText,
+    ));
+}
+
+pub fn update_playback_text(playback: Res<Playback>, generations: Query<&Generation>, mut texts: Query<&mut Text, With<PlaybackText>>) {
+    let generation = generations.iter().map(|generation| generation.0).max().unwrap_or(0);
+    let state = if playback.paused {
+        "paused".to_string()
+    } else if playback.fast_forward {
+        "fast forward".to_string()
+    } else {
+        format!("every {}s", playback.tick_interval())
+    };
+
+    for mut text in &mut texts {
+        text.sections[0].value = format!(
+            "Generation {generation}\n{:.1} generations/s ({state})\nSpace: pause  .: step  -/=: speed  F: fast forward",
+            playback.rate(),
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use bevy::math::Vec2;
+use biosim_core::{hex_grid::Grid, render::{fragment_color, GridLines, BACKGROUND_COLOR}, world::{Cell, WorldParams}};
+
+/// The part of the world to draw, in the same units as the world mesh (and so bevy's 2d camera).
+#[derive(Clone, Copy, Debug)]
+pub struct Viewport {
+    pub center: Vec2,
+    pub units_per_pixel: f32,
+}
+
+impl Viewport {
+    /// Fits the whole mesh of a world `world_width` cells wide into an image of the given size
+    pub fn whole_world(grid: Grid, world_width: usize, width: u32, height: u32) -> Viewport {
+        let mesh_size = mesh_size(grid, world_width);
+        let units_per_pixel = (mesh_size.x / width as f32).max(mesh_size.y / height as f32);
+        Viewport { center: Vec2::ZERO, units_per_pixel }
+    }
+}
+
+/// Size of the mesh the plugin spawns for a world `world_width` cells wide
+pub fn mesh_size(grid: Grid, world_width: usize) -> Vec2 {
+    Vec2 { x: world_width as f32 * grid.width_multiplier(), y: world_width as f32 }
+}
+
+/// An 8 bit srgba image
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl RgbaImage {
+    /// An image of a single linear colour
+    pub fn filled(width: u32, height: u32, color: [f32; 4]) -> RgbaImage {
+        RgbaImage { width, height, data: to_srgba8(color).repeat((width * height) as usize) }
+    }
+
+    pub fn save_png(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.data)?;
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+/// Draws `cells` the same way the fragment shader does, without needing a window or GPU. Anything off the world mesh
+/// is drawn in the background colour, and grid lines fade in at the viewport's zoom like they do on screen.
+pub fn rasterize(cells: &[Cell], ages: &[u32], params: &WorldParams, width: u32, height: u32, viewport: Viewport) -> RgbaImage {
+    let mut image = RgbaImage::filled(width, height, BACKGROUND_COLOR);
+    draw_world(&mut image, cells, ages, params, viewport);
+    image
+}
+
+/// Draws `cells` over `image` like `rasterize`, but leaves the pixels off the world mesh as they were. This is how
+/// several worlds end up in the same image, with viewports centred relative to each of their meshes.
+pub fn draw_world(image: &mut RgbaImage, cells: &[Cell], ages: &[u32], params: &WorldParams, viewport: Viewport) {
+    let params = &WorldParams { grid_lines: GridLines { pixels_per_unit: 1.0 / viewport.units_per_pixel, ..params.grid_lines }, ..*params };
+    let mesh_size = mesh_size(params.grid, params.width as usize);
+    let (width, height) = (image.width, image.height);
+    for y in 0..height {
+        for x in 0..width {
+            // Image rows go down but world space goes up
+            let offset = Vec2 { x: x as f32 + 0.5 - width as f32 / 2.0, y: height as f32 / 2.0 - (y as f32 + 0.5) };
+            let position = viewport.center + offset * viewport.units_per_pixel;
+            let u = position.x / mesh_size.x + 0.5;
+            let v = 0.5 - position.y / mesh_size.y;
+
+            if (0.0..1.0).contains(&u) && (0.0..1.0).contains(&v) {
+                let index = ((y * width + x) * 4) as usize;
+                image.data[index..index + 4].copy_from_slice(&to_srgba8(fragment_color(cells, ages, params, u, v)));
+            }
+        }
+    }
+}
+
+/// The shader outputs linear colours which bevy converts to srgb on the way to the screen
+pub fn to_srgba8(color: [f32; 4]) -> [u8; 4] {
+    let [r, g, b, a] = color;
+    let encode = |c: f32| {
+        let c = c.clamp(0.0, 1.0);
+        if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+    };
+    [encode(r), encode(g), encode(b), a.clamp(0.0, 1.0)].map(|c| (c * 255.0).round() as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use biosim_core::{render::{RenderMode, BORN_COLOR, DIED_COLOR, GRADIENT_STOPS}, world::WorldCoord};
+
+    use super::*;
+    use crate::{config::Config, world::cell_outline};
+
+    /// The default params for a world without grid lines, which would fade in over the edges of the cells
+    fn params(grid: Grid, width: u32, render_mode: RenderMode) -> WorldParams {
+        let params = Config::default().world_params();
+        WorldParams { width, grid, render_mode, grid_lines: GridLines { width: 0.0, ..params.grid_lines }, ..params }
+    }
+
+    fn pixel(image: &RgbaImage, x: u32, y: u32) -> [u8; 4] {
+        let index = ((y * image.width + x) * 4) as usize;
+        image.data[index..index + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn rect_worlds_are_drawn_in_every_mode() {
+        // The first row of cells is the bottom row of the image
+        let cells = [Cell::Alive, Cell::Dead, Cell::Dead, Cell::Alive];
+        let ages = [0, 0, 5, 300];
+        let at = [(1, 1), (2, 1), (1, 0), (2, 0)];
+        let palette = params(Grid::Rect, 2, RenderMode::State).palette;
+        let (dead, alive) = (palette.cell_to_color(Cell::Dead), palette.cell_to_color(Cell::Alive));
+
+        for (mode, expected) in [
+            (RenderMode::State, [alive, dead, dead, alive]),
+            (RenderMode::Age, [palette.gradient[0], dead, dead, palette.gradient[GRADIENT_STOPS - 1]]),
+            (RenderMode::Delta, [BORN_COLOR, DIED_COLOR, dead, alive]),
+        ] {
+            // A cell per pixel, with a column of pixels off the mesh on either side
+            let image = rasterize(&cells, &ages, &params(Grid::Rect, 2, mode), 4, 2, Viewport::whole_world(Grid::Rect, 2, 4, 2));
+            for ((x, y), color) in at.into_iter().zip(expected) {
+                assert_eq!(pixel(&image, x, y), to_srgba8(color), "{mode:?} at {x}, {y}");
+            }
+            for (x, y) in [(0, 0), (0, 1), (3, 0), (3, 1)] {
+                assert_eq!(pixel(&image, x, y), to_srgba8(BACKGROUND_COLOR), "{mode:?} at {x}, {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn hex_worlds_are_drawn_in_every_mode() {
+        let width = 4;
+        let coords: Vec<WorldCoord> = (0..width).flat_map(|y| (0..width).map(move |x| WorldCoord { x, y })).collect();
+        let cells: Vec<Cell> = coords.iter().map(|coord| if (coord.x + coord.y) % 3 == 0 { Cell::Alive } else { Cell::Dead }).collect();
+        let ages: Vec<u32> = coords.iter().map(|coord| coord.x as u32 * 100).collect();
+        // Four pixels per unit of the 24x4 mesh, so every hexel is 8 pixels tall
+        let (image_width, image_height) = (96, 16);
+        let viewport = Viewport::whole_world(Grid::Hex, width, image_width, image_height);
+
+        for mode in [RenderMode::State, RenderMode::Age, RenderMode::Delta] {
+            let params = params(Grid::Hex, width as u32, mode);
+            let image = rasterize(&cells, &ages, &params, image_width, image_height, viewport);
+            for (coord, (cell, age)) in coords.iter().zip(cells.iter().zip(&ages)) {
+                let expected = match mode {
+                    RenderMode::State => params.palette.cell_to_color(*cell),
+                    RenderMode::Age => params.palette.age_to_color(*cell, *age),
+                    RenderMode::Delta => params.palette.delta_to_color(*cell, *age),
+                };
+                // The middle of the hexel, going by its corners
+                let outline = cell_outline(Grid::Hex, width, *coord);
+                let u = outline.iter().map(|(u, _)| u).sum::<f32>() / outline.len() as f32;
+                let v = outline.iter().map(|(_, v)| v).sum::<f32>() / outline.len() as f32;
+                let (x, y) = ((u * image_width as f32) as u32, ((1.0 - v) * image_height as f32) as u32);
+                assert_eq!(pixel(&image, x, y), to_srgba8(expected), "{mode:?} at {coord:?}");
+            }
+            // The rows of hexels zigzag to the right going up, leaving the bottom right and top left of the mesh empty
+            for (x, y) in [(image_width - 1, image_height - 1), (0, 0)] {
+                assert_eq!(pixel(&image, x, y), to_srgba8(BACKGROUND_COLOR), "{mode:?} at {x}, {y}");
+            }
+        }
+    }
+}
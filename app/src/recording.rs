@@ -0,0 +1,281 @@
+use std::{env, error::Error, fs::File, io::{BufWriter, Write}, path::{Path, PathBuf}};
+
+use bevy::ecs::system::Resource;
+use biosim_core::{hex_grid::Grid, world::{Cell, WorldParams}};
+
+use crate::{npy::{self, cell_states, world_shape, NpzWriter}, rasterizer::{rasterize, Viewport}, statistics::Statistics};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RecordingFormat {
+    Gif,
+    Apng,
+    /// A frames x height x width array of cell states
+    Npy,
+    /// Like `Npy`, plus the generation of each frame and the statistics of the run
+    Npz,
+}
+
+impl RecordingFormat {
+    fn is_image(self) -> bool {
+        matches!(self, RecordingFormat::Gif | RecordingFormat::Apng)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RecordingStyle {
+    /// One square of `scale`x`scale` pixels per cell
+    Pixels,
+    /// Hexagons laid out the same way the fragment shader draws them
+    Hexagons,
+}
+
+impl RecordingStyle {
+    /// The style that draws cells the way `grid` lays them out
+    pub fn for_grid(grid: Grid) -> RecordingStyle {
+        match grid {
+            Grid::Rect => RecordingStyle::Pixels,
+            Grid::Hex => RecordingStyle::Hexagons,
+        }
+    }
+
+    pub fn grid(self) -> Grid {
+        match self {
+            RecordingStyle::Pixels => Grid::Rect,
+            RecordingStyle::Hexagons => Grid::Hex,
+        }
+    }
+
+    /// Size of the frames of a world `world_width` cells wide, with cells `scale` pixels in size
+    pub fn frame_size(self, world_width: usize, scale: u32) -> (u32, u32) {
+        match self {
+            RecordingStyle::Pixels => (world_width as u32 * scale, world_width as u32 * scale),
+            // The hex layout packs the world into world_width / 2 rows of hexels that are roughly square
+            RecordingStyle::Hexagons => {
+                let height = world_width as u32 / 2 * scale;
+                ((height as f32 * Grid::Hex.width_multiplier()) as u32, height)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RecordingSettings {
+    pub path: PathBuf,
+    pub format: RecordingFormat,
+    pub style: RecordingStyle,
+    pub first_generation: u64,
+    pub last_generation: u64,
+    pub every: u64,
+    /// Size of a single cell in pixels
+    pub scale: u32,
+    pub frame_delay_ms: u16,
+}
+
+impl RecordingSettings {
+    /// Records to `path` with the defaults, drawing cells the way `grid` lays them out
+    pub fn new(path: impl Into<PathBuf>, grid: Grid) -> Result<RecordingSettings, String> {
+        let path = path.into();
+        let format = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("gif") => RecordingFormat::Gif,
+            Some("png" | "apng") => RecordingFormat::Apng,
+            Some("npy") => RecordingFormat::Npy,
+            Some("npz") => RecordingFormat::Npz,
+            _ => return Err(format!("can't tell the recording format of {}, expected a .gif, .png, .apng, .npy or .npz file", path.display())),
+        };
+        let style = RecordingStyle::for_grid(grid);
+
+        Ok(RecordingSettings { path, format, style, first_generation: 0, last_generation: 99, every: 1, scale: 2, frame_delay_ms: 100 })
+    }
+
+    /// Checks the settings make sense for a world `world_width` cells wide
+    pub fn validate(&self, world_width: usize) -> Result<(), String> {
+        let (width, height) = self.frame_size(world_width);
+        if self.format.is_image() && (width > u16::MAX as u32 || height > u16::MAX as u32) {
+            return Err(format!("{width}x{height} frames are too large, try a smaller scale"));
+        }
+        if self.first_generation > self.last_generation {
+            return Err(format!("recording starts at generation {} but ends before it at {}", self.first_generation, self.last_generation));
+        }
+        if self.every == 0 {
+            return Err("can't record every 0th generation".to_string());
+        }
+        if self.scale == 0 {
+            return Err("recording scale must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        (self.last_generation - self.first_generation) / self.every + 1
+    }
+
+    /// Size of the frames of a world `world_width` cells wide
+    pub fn frame_size(&self, world_width: usize) -> (u32, u32) {
+        self.style.frame_size(world_width, self.scale)
+    }
+}
+
+/// The `recording` keys set by the `BIOSIM_RECORD*` environment variables, see the readme. These go on top of the
+/// config file, and the flags on top of them.
+pub fn env_overrides() -> Result<Vec<String>, String> {
+    let mut overrides = Vec::new();
+    if let Ok(path) = env::var("BIOSIM_RECORD") {
+        overrides.push(format!("recording.path={}", toml::Value::String(path)));
+    }
+    if let Ok(range) = env::var("BIOSIM_RECORD_RANGE") {
+        overrides.extend(range_overrides("BIOSIM_RECORD_RANGE", &range)?);
+    }
+    for (name, key) in [("BIOSIM_RECORD_EVERY", "every"), ("BIOSIM_RECORD_SCALE", "scale"), ("BIOSIM_RECORD_STYLE", "style")] {
+        if let Ok(value) = env::var(name) {
+            overrides.push(format!("recording.{key}={value}"));
+        }
+    }
+    Ok(overrides)
+}
+
+/// The first and last generation of `range`, which looks like `first..last`, as `recording` keys. `name` is where the
+/// range came from.
+pub fn range_overrides(name: &str, range: &str) -> Result<[String; 2], String> {
+    let Some((first, last)) = range.split_once("..") else {
+        return Err(format!("{name} should look like `first..last`, got `{range}`"));
+    };
+    Ok([format!("recording.first_generation={first}"), format!("recording.last_generation={last}")])
+}
+
+enum Encoder {
+    Gif(gif::Encoder<BufWriter<File>>),
+    Apng(png::Writer<BufWriter<File>>),
+    Npy(BufWriter<File>),
+    Npz(Box<NpzWriter>),
+}
+
+/// Captures a range of generations into an animated GIF or APNG, or a numpy array. Frames are encoded as soon as
+/// they're captured so long recordings don't have to be held in memory.
+#[derive(Resource)]
+pub struct Recorder {
+    settings: RecordingSettings,
+    params: WorldParams,
+    encoder: Option<Encoder>,
+    frames_written: u64,
+}
+
+impl Recorder {
+    /// Prepares to record the world `params` describes, with `settings` that have been validated for it. Nothing is
+    /// written until the first frame is captured.
+    pub fn new(settings: RecordingSettings, params: WorldParams) -> Recorder {
+        Recorder { settings, params, encoder: None, frames_written: 0 }
+    }
+
+    pub fn wants(&self, generation: u64) -> bool {
+        !self.is_finished()
+            && (self.settings.first_generation..=self.settings.last_generation).contains(&generation)
+            && (generation - self.settings.first_generation) % self.settings.every == 0
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frames_written == self.settings.frame_count()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.settings.path
+    }
+
+    /// Adds `cells` as the next frame, finishing the file once the last frame has been written. `ages` are only used
+    /// when the params colour cells by age.
+    pub fn capture(&mut self, cells: &[Cell], ages: &[u32], statistics: &Statistics) -> Result<(), String> {
+        let encoder = match self.encoder.take() {
+            Some(encoder) => encoder,
+            None => self.create_encoder().map_err(|e| format!("couldn't start recording to {}: {e}", self.settings.path.display()))?,
+        };
+        let encoder = self.write_frame(encoder, cells, ages).map_err(|e| e.to_string())?;
+        self.frames_written += 1;
+
+        if self.is_finished() {
+            self.finish(encoder, statistics).map_err(|e| e.to_string())?;
+        } else {
+            self.encoder = Some(encoder);
+        }
+        Ok(())
+    }
+
+    fn write_frame(&self, encoder: Encoder, cells: &[Cell], ages: &[u32]) -> Result<Encoder, Box<dyn Error>> {
+        let render = || {
+            let world_width = self.params.width as usize;
+            let (width, height) = self.settings.frame_size(world_width);
+            // The style can draw the cells on another grid than the world's
+            let grid = self.settings.style.grid();
+            rasterize(cells, ages, &WorldParams { grid, ..self.params }, width, height, Viewport::whole_world(grid, world_width, width, height))
+        };
+
+        Ok(match encoder {
+            Encoder::Gif(mut encoder) => {
+                let mut image = render();
+                let mut gif_frame = gif::Frame::from_rgba_speed(image.width as u16, image.height as u16, &mut image.data, 10);
+                gif_frame.delay = self.settings.frame_delay_ms / 10;
+                encoder.write_frame(&gif_frame)?;
+                Encoder::Gif(encoder)
+            }
+            Encoder::Apng(mut writer) => {
+                writer.write_image_data(&render().data)?;
+                Encoder::Apng(writer)
+            }
+            Encoder::Npy(mut file) => {
+                npy::write_data(&mut file, &cell_states(cells))?;
+                Encoder::Npy(file)
+            }
+            Encoder::Npz(mut npz) => {
+                npz.write_data(&cell_states(cells))?;
+                Encoder::Npz(npz)
+            }
+        })
+    }
+
+    fn finish(&self, encoder: Encoder, statistics: &Statistics) -> Result<(), Box<dyn Error>> {
+        match encoder {
+            // The gif trailer is written when the encoder is dropped
+            Encoder::Gif(encoder) => drop(encoder),
+            Encoder::Apng(writer) => writer.finish()?,
+            Encoder::Npy(mut file) => file.flush()?,
+            Encoder::Npz(mut npz) => {
+                let generations: Vec<u64> = (0..self.settings.frame_count()).map(|frame| self.settings.first_generation + frame * self.settings.every).collect();
+                npz.add_array("frame_generations", &[generations.len()], &generations)?;
+                npz.add_statistics(statistics)?;
+                npz.finish()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn create_encoder(&self) -> Result<Encoder, Box<dyn Error>> {
+        let (width, height) = self.settings.frame_size(self.params.width as usize);
+        let [rows, columns] = world_shape(self.params.width as usize);
+        let stack_shape = [self.settings.frame_count() as usize, rows, columns];
+        let create_file = || File::create(&self.settings.path).map(BufWriter::new);
+        match self.settings.format {
+            RecordingFormat::Gif => {
+                // Every frame gets its own palette
+                let mut encoder = gif::Encoder::new(create_file()?, width as u16, height as u16, &[])?;
+                encoder.set_repeat(gif::Repeat::Infinite)?;
+                Ok(Encoder::Gif(encoder))
+            }
+            RecordingFormat::Apng => {
+                let mut encoder = png::Encoder::new(create_file()?, width, height);
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_animated(self.settings.frame_count() as u32, 0)?;
+                encoder.set_frame_delay(self.settings.frame_delay_ms, 1000)?;
+                Ok(Encoder::Apng(encoder.write_header()?))
+            }
+            RecordingFormat::Npy => {
+                let mut file = create_file()?;
+                npy::write_header::<u8>(&mut file, &stack_shape)?;
+                Ok(Encoder::Npy(file))
+            }
+            RecordingFormat::Npz => {
+                let mut npz = NpzWriter::create(&self.settings.path)?;
+                npz.start_array::<u8>("cells", &stack_shape)?;
+                Ok(Encoder::Npz(Box::new(npz)))
+            }
+        }
+    }
+}
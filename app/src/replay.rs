@@ -0,0 +1,359 @@
+use std::{error::Error, fs::{self, File}, io::{self, BufWriter, Write}, path::Path};
+
+use bevy::prelude::*;
+use biosim_core::{hex_grid::Grid, statistics::GenerationStats, world::{get_index, Boundary, Cell, WorldCoord}};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    biosim_plugin::{file_stem, load_pattern, starting_worlds, Generation, WorldComponent, WorldId, WorldRules},
+    config::{BoundaryName, Config, GridName, RuleNotation},
+    headless::{write_statistics, HeadlessWorld},
+    npy::write_world,
+    script::load_script,
+    statistics::Statistics,
+    world::{new_random, Region},
+};
+
+/// An edit or replacement of a world's cells that hasn't been logged yet, see `WorldComponent::changes`
+#[derive(Clone, Debug)]
+pub enum WorldChange {
+    /// The cells of `region` after `edit_cells` changed it
+    Edit { region: Region, cells: Vec<Cell> },
+    /// The one-row `runs` that `fill_cells` set to `cell`
+    Fill { runs: Vec<Region>, cell: Cell },
+    /// `replace_world` swapped out the world at generation `from`
+    Replace { from: u64, source: WorldSource },
+}
+
+/// Where the cells of a replaced world came from
+#[derive(Clone, Debug)]
+pub enum WorldSource {
+    Random { density: f64, seed: u64 },
+    /// An `.rle` or `.npz` file, see `load_pattern`
+    File(String),
+}
+
+/// A line of a replay log, which is JSON. Worlds are numbered from 1 and every entry happened at `generation` of its
+/// world, in the order of the lines.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Entry {
+    /// The first line, with the whole config as TOML. Worlds that started out random have their seed in it.
+    Start { version: String, config: String },
+    /// What the cells and ages of the world hash to, see `world_hash`
+    Hash { world: usize, generation: u64, hash: String },
+    /// The cells of a rectangle after it was painted, pasted into or cleared, as rows of `.` for dead and `o` for
+    /// alive cells starting at `x`, `y`
+    Edit { world: usize, generation: u64, x: usize, y: usize, rows: Vec<String> },
+    /// Cells drawn or erased with the brush, which start over at age 0 even if they already were `alive`, as the `x`,
+    /// `y` and length of each run of them in a row
+    Fill { world: usize, generation: u64, alive: bool, runs: Vec<[usize; 3]> },
+    Rules {
+        world: usize,
+        generation: u64,
+        rule: RuleNotation,
+        #[serde(with = "BoundaryName")]
+        boundary: Boundary,
+        #[serde(with = "GridName")]
+        grid: Grid,
+    },
+    Reseed { world: usize, generation: u64, density: f64, seed: u64 },
+    Load { world: usize, generation: u64, path: String },
+}
+
+/// FNV-1a of the cells and ages, which is the same on every platform and simulator
+pub fn world_hash(cells: &[Cell], ages: &[u32]) -> String {
+    let bytes = cells.iter().map(|cell| *cell as u8).chain(ages.iter().flat_map(|age| age.to_le_bytes()));
+    let hash = bytes.fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+    format!("{hash:016x}")
+}
+
+/// Logs a run with `replay.log` set, so `--replay` can make the same worlds again. Hashes of every world are written
+/// every `replay.hash_every` generations, and the edits and rule changes made in between as they happen.
+#[derive(Resource)]
+pub struct ReplayLog {
+    file: BufWriter<File>,
+    hash_every: u64,
+}
+
+impl ReplayLog {
+    /// Starts the log at `path` with `config`, whose seeds should have been pinned, see `Config::pin_seeds`
+    pub fn create(path: &str, config: &Config) -> io::Result<ReplayLog> {
+        let mut log = ReplayLog { file: BufWriter::new(File::create(path)?), hash_every: config.replay.hash_every };
+        log.write(&Entry::Start { version: env!("CARGO_PKG_VERSION").to_string(), config: config.table.to_string() })?;
+        Ok(log)
+    }
+
+    pub fn wants_hash(&self, generation: u64) -> bool {
+        generation % self.hash_every == 0
+    }
+
+    pub fn write_hash(&mut self, world: WorldId, generation: u64, cells: &[Cell], ages: &[u32]) -> io::Result<()> {
+        self.write(&Entry::Hash { world: world.0 + 1, generation, hash: world_hash(cells, ages) })
+    }
+
+    /// Flushes every line, so the log is complete up to whatever crashed
+    pub fn write(&mut self, entry: &Entry) -> io::Result<()> {
+        serde_json::to_writer(&mut self.file, entry)?;
+        writeln!(self.file)?;
+        self.file.flush()
+    }
+}
+
+/// The log entries of `changes`, which are in the order they were made to a world now at `generation`
+fn change_entries(world: WorldId, generation: u64, changes: Vec<WorldChange>) -> Vec<Entry> {
+    // Only replacing a world changes its generation without ticking, so going back from the last change tells when
+    // each happened
+    let (world, mut generation) = (world.0 + 1, generation);
+    let mut entries: Vec<Entry> = changes.into_iter().rev().map(|change| match change {
+        WorldChange::Edit { region, cells } => {
+            let rows = cells.chunks(region.width()).map(|row| row.iter().map(|cell| if *cell == Cell::Alive { 'o' } else { '.' }).collect()).collect();
+            Entry::Edit { world, generation, x: region.min.x, y: region.min.y, rows }
+        }
+        WorldChange::Fill { runs, cell } => {
+            let runs = runs.iter().map(|run| [run.min.x, run.min.y, run.width()]).collect();
+            Entry::Fill { world, generation, alive: cell == Cell::Alive, runs }
+        }
+        WorldChange::Replace { from, source } => {
+            generation = from;
+            match source {
+                WorldSource::Random { density, seed } => Entry::Reseed { world, generation, density, seed },
+                WorldSource::File(path) => Entry::Load { world, generation, path },
+            }
+        }
+    }).collect();
+    entries.reverse();
+    entries
+}
+
+/// Logs what changed in each world since the last frame. This runs before `update_world`, so nothing can have ticked
+/// since. Changes are dropped when nothing is logged.
+pub fn log_changes(mut commands: Commands, mut replay_log: Option<ResMut<ReplayLog>>, mut worlds: Query<(&WorldId, &mut WorldComponent, &Generation, Ref<WorldRules>)>) {
+    for (id, mut world_component, generation, rules) in &mut worlds {
+        let rules_changed = rules.is_changed() && !rules.is_added();
+        if world_component.changes.is_empty() && !rules_changed {
+            continue;
+        }
+        let mut entries = change_entries(*id, generation.0, std::mem::take(&mut world_component.changes));
+        if rules_changed {
+            entries.push(Entry::Rules { world: id.0 + 1, generation: generation.0, rule: RuleNotation(rules.rule), boundary: rules.boundary, grid: rules.grid });
+        }
+
+        let Some(log) = replay_log.as_deref_mut() else {
+            continue;
+        };
+        if let Err(e) = entries.iter().try_for_each(|entry| log.write(entry)) {
+            error!("Couldn't write to the replay log, giving up on it: {e}");
+            commands.remove_resource::<ReplayLog>();
+            replay_log = None;
+        }
+    }
+}
+
+/// Makes the run logged at `path` again on the CPU, checking every hash in the log along the way, and saves the worlds
+/// as they end up to `headless.output` of the logged config, or `output`. A world that doesn't match a hash stops the
+/// replay right there, with the worlds saved as they were.
+pub fn replay(path: &str, output: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let text = fs::read_to_string(path).map_err(|e| format!("couldn't read {path}: {e}"))?;
+    let mut entries = text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()).map(|(number, line)| {
+        serde_json::from_str::<Entry>(line).map_err(|e| format!("{path}:{}: {e}", number + 1))
+    });
+    let Some(Entry::Start { version, config }) = entries.next().transpose()? else {
+        return Err(format!("{path} isn't a replay log, it has to start with the config").into());
+    };
+    // Other versions can tick, edit or hash differently, which would only show up as the run diverging
+    if version != env!("CARGO_PKG_VERSION") {
+        return Err(format!("{path} was logged by version {version}, but this is version {}, which can't replay it", env!("CARGO_PKG_VERSION")).into());
+    }
+
+    let table = config.parse().map_err(|e| format!("the config in {path} is broken: {e}"))?;
+    let mut config = Config::from_table(table).map_err(|e| format!("the config in {path} is invalid: {e}"))?;
+    // The replay mustn't overwrite the log or take the ports of the app it came from
+    config.replay.log = None;
+    (config.server.port, config.stream.port) = (None, None);
+    if let Some(output) = output {
+        config.headless.output = output.to_string();
+    }
+    let script = load_script(&mut config).map_err(|e| format!("invalid script: {e}"))?;
+    let shared = config.world_params();
+    let mut worlds: Vec<HeadlessWorld> = config.worlds().into_iter().zip(starting_worlds(&config, script.as_ref())?).enumerate()
+        .map(|(id, (settings, start))| HeadlessWorld::new(WorldId(id), start, WorldRules::from(settings).params(&shared), &config.agents))
+        .collect();
+
+    let (mut replayed, mut checked) = (0, 0);
+    let mut diverged = None;
+    for entry in entries {
+        let entry = entry?;
+        let (world, generation) = match &entry {
+            Entry::Start { .. } => return Err(format!("{path} starts more than once").into()),
+            Entry::Hash { world, generation, .. } | Entry::Edit { world, generation, .. } | Entry::Fill { world, generation, .. } | Entry::Rules { world, generation, .. }
+                | Entry::Reseed { world, generation, .. } | Entry::Load { world, generation, .. } => (*world, *generation),
+        };
+        let count = worlds.len();
+        let Some(world) = world.checked_sub(1).and_then(|index| worlds.get_mut(index)) else {
+            return Err(format!("{path} has world {world}, but there are only {count}").into());
+        };
+        if world.generation() > generation {
+            return Err(format!("{path} goes back to generation {generation} of world {}, which is already at {}", world.id.0 + 1, world.generation()).into());
+        }
+        while world.generation() < generation {
+            world.tick();
+        }
+
+        match entry {
+            Entry::Hash { hash, .. } => {
+                let replayed = world_hash(&world.cells, &world.ages);
+                if replayed != hash {
+                    diverged = Some(format!("world {} diverged at generation {generation}: the log has hash {hash} but the replay got {replayed}", world.id.0 + 1));
+                    break;
+                }
+                checked += 1;
+            }
+            Entry::Edit { x, y, rows, .. } => apply_edit(world, config.world.size, WorldCoord { x, y }, &rows)?,
+            Entry::Fill { alive, runs, .. } => apply_fill(world, config.world.size, if alive { Cell::Alive } else { Cell::Dead }, &runs)?,
+            Entry::Rules { rule, boundary, grid, .. } => {
+                world.params = WorldRules { rule: rule.0, boundary, grid }.params(&shared);
+                if let Some(population) = &mut world.population {
+                    population.follow(&world.params);
+                }
+            }
+            Entry::Reseed { density, seed, .. } => {
+                let cells = new_random(config.world.size, density, Some(seed));
+                (world.ages, world.statistics) = (vec![0; cells.len()], Statistics(vec![GenerationStats::initial(&cells)]));
+                world.cells = cells;
+            }
+            Entry::Load { path, .. } => {
+                (world.cells, world.ages, world.statistics) = load_pattern(&path, &config).map_err(|e| format!("couldn't load {path}: {e}"))?;
+            }
+            Entry::Start { .. } => unreachable!("checked above"),
+        }
+        replayed += 1;
+    }
+
+    let output = Path::new(&config.headless.output);
+    fs::create_dir_all(output).map_err(|e| format!("couldn't create {}: {e}", output.display()))?;
+    for world in &worlds {
+        let path = output.join(format!("{}.npz", file_stem(world.id, &config, world.generation())));
+        write_world(&path, config.world.size, &world.cells, &world.ages, &world.statistics).map_err(|e| format!("couldn't save {}: {e}", path.display()))?;
+        println!("Saved {}", path.display());
+    }
+    let statistics = output.join("statistics.csv");
+    write_statistics(&statistics, &worlds).map_err(|e| format!("couldn't save statistics to {}: {e}", statistics.display()))?;
+
+    if let Some(diverged) = diverged {
+        return Err(format!("{diverged}, the worlds were saved as they were then").into());
+    }
+    println!("Replayed {replayed} entries of {path}, all {checked} hashes matched");
+    Ok(())
+}
+
+/// Sets the cells of `rows` starting at `at`, like `edit_cells` does
+fn apply_edit(world: &mut HeadlessWorld, width: usize, at: WorldCoord, rows: &[String]) -> Result<(), String> {
+    for (y, row) in rows.iter().enumerate() {
+        for (x, state) in row.chars().enumerate() {
+            let coord = WorldCoord { x: at.x + x, y: at.y + y };
+            if !Region::whole(width).contains(coord) {
+                return Err(format!("an edit of world {} goes past the edge at [{}, {}]", world.id.0 + 1, coord.x, coord.y));
+            }
+            let cell = match state {
+                'o' => Cell::Alive,
+                '.' => Cell::Dead,
+                _ => return Err(format!("`{state}` in an edit of world {} isn't a cell, expected `.` or `o`", world.id.0 + 1)),
+            };
+            let index = get_index(coord, width);
+            if world.cells[index] != cell {
+                (world.cells[index], world.ages[index]) = (cell, 0);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sets the cells of `runs` to `cell` at age 0, like `fill_cells` does
+fn apply_fill(world: &mut HeadlessWorld, width: usize, cell: Cell, runs: &[[usize; 3]]) -> Result<(), String> {
+    for &[x, y, length] in runs {
+        let end = x.checked_add(length).filter(|end| *end <= width && y < width);
+        if length == 0 || end.is_none() {
+            return Err(format!("a fill of world {} has a run of {length} cells at [{x}, {y}] that doesn't fit", world.id.0 + 1));
+        }
+        let start = get_index(WorldCoord { x, y }, width);
+        world.cells[start..start + length].fill(cell);
+        world.ages[start..start + length].fill(0);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    /// Logs a few generations of a small world with an edit and a fill along the way, changing the cells the way
+    /// `edit_cells` and `fill_cells` do
+    fn log_run(path: &Path, output: &Path) {
+        let table = format!("[world]\nsize = 8\nseed = 3\n[headless]\noutput = {:?}", output.display().to_string()).parse().unwrap();
+        let config = Config::from_table(table).unwrap();
+        let mut log = ReplayLog::create(path.to_str().unwrap(), &config).unwrap();
+        let (cells, ages, statistics) = starting_worlds(&config, None).unwrap().remove(0);
+        let params = WorldRules::from(config.worlds()[0]).params(&config.world_params());
+        let mut world = HeadlessWorld::new(WorldId(0), (cells, ages, statistics), params, &config.agents);
+
+        for generation in 0..6 {
+            let changes = match generation {
+                2 => {
+                    let region = Region { min: WorldCoord { x: 1, y: 2 }, max: WorldCoord { x: 3, y: 3 } };
+                    let cells: Vec<Cell> = region.coords().map(|coord| if coord.x == 2 { Cell::Alive } else { Cell::Dead }).collect();
+                    for (coord, cell) in region.coords().zip(&cells) {
+                        let index = get_index(coord, 8);
+                        if world.cells[index] != *cell {
+                            (world.cells[index], world.ages[index]) = (*cell, 0);
+                        }
+                    }
+                    vec![WorldChange::Edit { region, cells }]
+                }
+                4 => {
+                    let runs = vec![
+                        Region { min: WorldCoord { x: 0, y: 5 }, max: WorldCoord { x: 6, y: 5 } },
+                        Region { min: WorldCoord { x: 4, y: 6 }, max: WorldCoord { x: 4, y: 6 } },
+                    ];
+                    for coord in runs.iter().flat_map(|run| run.coords()) {
+                        let index = get_index(coord, 8);
+                        (world.cells[index], world.ages[index]) = (Cell::Alive, 0);
+                    }
+                    vec![WorldChange::Fill { runs, cell: Cell::Alive }]
+                }
+                _ => Vec::new(),
+            };
+            for entry in change_entries(world.id, world.generation(), changes) {
+                log.write(&entry).unwrap();
+            }
+            log.write_hash(world.id, world.generation(), &world.cells, &world.ages).unwrap();
+            world.tick();
+        }
+    }
+
+    #[test]
+    fn replays_match_their_hashes() {
+        let dir = env::temp_dir().join(format!("biosim-replay-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (log, output) = (dir.join("run.replay"), dir.join("output"));
+        log_run(&log, &output);
+        let text = fs::read_to_string(&log).unwrap();
+        assert!(text.contains(r#"{"edit":"#) && text.contains(r#"{"fill":"#));
+        replay(log.to_str().unwrap(), None).unwrap();
+        assert!(output.join("world-5.npz").exists());
+
+        // A cell that's alive in the log but dead in the replay
+        let tampered = dir.join("tampered.replay");
+        fs::write(&tampered, text.replacen(r#""alive":true"#, r#""alive":false"#, 1)).unwrap();
+        let error = replay(tampered.to_str().unwrap(), None).unwrap_err().to_string();
+        assert!(error.contains("world 1 diverged at generation 4"), "{error}");
+
+        let other_version = dir.join("other-version.replay");
+        fs::write(&other_version, text.replacen(env!("CARGO_PKG_VERSION"), "0.0.0-other", 1)).unwrap();
+        let error = replay(other_version.to_str().unwrap(), None).unwrap_err().to_string();
+        assert!(error.contains("version 0.0.0-other"), "{error}");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
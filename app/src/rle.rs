@@ -0,0 +1,82 @@
+use biosim_core::{hex_grid::Grid, world::Cell};
+
+use crate::selection::Pattern;
+
+/// Reads a pattern in the run length encoded format most Life pattern collections use, e.g. a glider is
+/// `x = 3, y = 3\nbo$2bo$3o!`. The pattern is centred on the cell it's placed at. Only the live cells are kept, and the
+/// header's rule is ignored in favour of the world's. The format is made for rect grids, but patterns can be placed on
+/// hex grids as well.
+pub fn parse_rle(text: &str) -> Result<Pattern, String> {
+    let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#'));
+    let header = lines.next().ok_or("the file is empty")?;
+    let size = |name: &str| {
+        header.split(',')
+            .filter_map(|part| part.split_once('='))
+            .find(|(key, _)| key.trim() == name)
+            .and_then(|(_, value)| value.trim().parse::<i32>().ok())
+            .ok_or(format!("the header `{header}` should start like `x = 3, y = 3`"))
+    };
+    let (width, height) = (size("x")?, size("y")?);
+
+    let mut cells = Vec::new();
+    let (mut x, mut row) = (0, 0);
+    let mut count = String::new();
+    for c in lines.flat_map(str::chars) {
+        if c.is_ascii_digit() {
+            count.push(c);
+            continue;
+        }
+        let run = if count.is_empty() { 1 } else { count.parse::<i32>().map_err(|e| format!("run of `{count}`: {e}"))? };
+        count.clear();
+        match c {
+            'b' | '.' => x += run,
+            '$' => (x, row) = (0, row + run),
+            '!' => break,
+            c if c.is_ascii_alphabetic() => {
+                // Rows go down the screen while world coordinates go up
+                cells.extend((x..x + run).map(|x| ((x - width / 2, height / 2 - row), Cell::Alive)));
+                x += run;
+            }
+            c if c.is_whitespace() => {}
+            _ => return Err(format!("`{c}` isn't part of a run")),
+        }
+    }
+    Ok(Pattern { grid: Grid::Rect, cells })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn live_cells(text: &str) -> Vec<(i32, i32)> {
+        let mut cells: Vec<(i32, i32)> = parse_rle(text).unwrap().cells.into_iter().map(|(offset, _)| offset).collect();
+        cells.sort();
+        cells
+    }
+
+    #[test]
+    fn gliders_are_centred() {
+        assert_eq!(live_cells("#N Glider\nx = 3, y = 3, rule = B3/S23\nbo$2bo$3o!"), [(-1, -1), (0, -1), (0, 1), (1, -1), (1, 0)]);
+    }
+
+    #[test]
+    fn runs_and_row_ends_can_repeat() {
+        // 12 cells on the top row, then two empty rows and a cell on the fourth, spread over lines
+        let cells = live_cells("x = 12, y = 4\n12o3$\n11bo!");
+        assert_eq!(cells.len(), 13);
+        assert!((-6..6).all(|x| cells.contains(&(x, 2))));
+        assert!(cells.contains(&(5, -1)));
+    }
+
+    #[test]
+    fn everything_after_the_end_is_ignored() {
+        assert_eq!(live_cells("x = 2, y = 1\no!bo$oo"), [(-1, 0)]);
+    }
+
+    #[test]
+    fn broken_patterns_are_rejected() {
+        for text in ["", "# only a comment", "bo$2bo$3o!", "x = 3\nbo!", "x = 3, y = 3\nb?o!"] {
+            assert!(parse_rle(text).is_err(), "{text:?}");
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use std::{io::{self, BufRead, BufReader, Write}, net::{Ipv4Addr, TcpListener, TcpStream}, sync::{mpsc::{self, Receiver, Sender}, Mutex}, thread};
+
+use bevy::{ecs::query::QueryItem, prelude::*};
+use biosim_core::world::{Cell, WorldCoord};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+
+use crate::{biosim_plugin::{fill_cells, load_pattern, replace_world, write_npz, Generation, WorldComponent, WorldId, WorldRules}, compute_shader::BiosimComputeShader, config::Config, control_panel::ControlPanel, inspector::Inspector, playback::Playback, replay::WorldSource, rules::{parse_rule, rule_notation}, simulator::Simulation, statistics::Statistics, world::Region};
+
+/// Error codes from the JSON-RPC 2.0 spec
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// A valid request that couldn't be carried out, like loading a file that doesn't exist
+const FAILED: i64 = -32000;
+
+#[derive(Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn invalid_params(message: impl Into<String>) -> RpcError {
+        RpcError { code: INVALID_PARAMS, message: message.into() }
+    }
+
+    fn failed(message: impl Into<String>) -> RpcError {
+        RpcError { code: FAILED, message: message.into() }
+    }
+}
+
+type RpcResult = Result<Value, RpcError>;
+
+/// A request read from one of the connections, which is waiting for its result on `reply`
+struct RpcRequest {
+    method: String,
+    params: Value,
+    reply: Sender<RpcResult>,
+}
+
+/// Takes commands from other programs, like notebooks driving an experiment, as JSON-RPC 2.0 over TCP on
+/// `server.port` of localhost. Every request and response is a single line of JSON. Connections are served on threads
+/// of their own, which hand the requests over to `handle_rpc_requests` to carry out between frames.
+#[derive(Resource)]
+pub struct RpcServer {
+    requests: Mutex<Receiver<RpcRequest>>,
+    /// Replies to `step` requests, which are sent once every step asked for has run
+    stepping: Vec<Sender<RpcResult>>,
+}
+
+impl RpcServer {
+    pub fn listen(port: u16) -> io::Result<RpcServer> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let sender = sender.clone();
+                thread::spawn(move || {
+                    if let Err(e) = serve(stream, sender) {
+                        warn!("JSON-RPC connection closed: {e}");
+                    }
+                });
+            }
+        });
+        Ok(RpcServer { requests: Mutex::new(receiver), stepping: Vec::new() })
+    }
+}
+
+/// Answers the requests on a connection in the order they come in. Notifications, requests without an id, are carried
+/// out without an answer.
+fn serve(stream: TcpStream, requests: Sender<RpcRequest>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (id, result) = match serde_json::from_str::<Value>(&line) {
+            Ok(request) => {
+                let result = match request.get("method").and_then(Value::as_str) {
+                    Some(method) => {
+                        let (reply, result) = mpsc::channel();
+                        let params = request.get("params").cloned().unwrap_or(Value::Null);
+                        // The app quit, which closes the connection
+                        if requests.send(RpcRequest { method: method.to_string(), params, reply }).is_err() {
+                            return Ok(());
+                        }
+                        result.recv().unwrap_or_else(|_| Err(RpcError::failed("the app quit before answering")))
+                    }
+                    None => Err(RpcError { code: INVALID_REQUEST, message: "requests need a `method`".to_string() }),
+                };
+                let Some(id) = request.get("id").cloned() else {
+                    continue;
+                };
+                (id, result)
+            }
+            Err(e) => (Value::Null, Err(RpcError { code: PARSE_ERROR, message: e.to_string() })),
+        };
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(RpcError { code, message }) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } }),
+        };
+        writeln!(writer, "{response}")?;
+    }
+    Ok(())
+}
+
+/// Worlds are numbered from 1 like in file names, and the first one is meant when there's no `world`
+fn first_world() -> usize {
+    1
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WorldSelection {
+    #[serde(default = "first_world")]
+    world: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StepParams {
+    #[serde(default = "StepParams::one")]
+    generations: u32,
+}
+
+impl StepParams {
+    fn one() -> u32 {
+        1
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleParams {
+    rule: String,
+    #[serde(default = "first_world")]
+    world: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PathParams {
+    path: String,
+    #[serde(default = "first_world")]
+    world: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PaintParams {
+    /// `[x, y]` of every cell to paint
+    cells: Vec<[usize; 2]>,
+    #[serde(default = "PaintParams::alive")]
+    alive: bool,
+    #[serde(default = "first_world")]
+    world: usize,
+}
+
+impl PaintParams {
+    fn alive() -> bool {
+        true
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegionParams {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    #[serde(default = "first_world")]
+    world: usize,
+}
+
+/// Reads the params of a request, which can be left out when every one of them has a default
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|e| RpcError::invalid_params(e.to_string()))
+}
+
+type RpcWorld = (Entity, &'static WorldId, &'static mut WorldComponent, &'static mut Generation, &'static mut Statistics, &'static BiosimComputeShader, &'static mut WorldRules);
+
+/// The world numbered `world`, see `first_world`
+fn find_world<'a>(world_query: &'a mut Query<RpcWorld>, world: usize) -> Result<QueryItem<'a, RpcWorld>, RpcError> {
+    let entity = world_query.iter().find(|(_, id, ..)| id.0 + 1 == world).map(|(entity, ..)| entity);
+    let entity = entity.ok_or_else(|| RpcError::invalid_params(format!("there's no world {world}")))?;
+    Ok(world_query.get_mut(entity).expect("the world was just found"))
+}
+
+/// Carries out the requests that came in since the last frame:
+///
+/// - `pause` and `resume`
+/// - `step`, running `generations` (1 by default) more generations and pausing. Answers once they've run, with the
+///   generation the worlds are at.
+/// - `set_rule`, to a `rule` like `B36/S23`
+/// - `load_pattern`, replacing the world with the `.rle` or `.npz` file at `path` like `--pattern` does
+/// - `save`, the world to an `.npz` file at `path`
+/// - `paint`, making every `[x, y]` in `cells` alive, or dead when `alive` is false
+/// - `stats`, the generation, rule, population, births and deaths of the world
+/// - `region`, the cells in the rectangle from `x`, `y` that's `width` by `height` cells, as rows of 0s and 1s
+///
+/// Every method but `pause`, `resume` and `step` works on a single world, the `world`th (1 by default).
+#[allow(clippy::too_many_arguments)]
+pub fn handle_rpc_requests(
+    mut server: ResMut<RpcServer>,
+    mut playback: ResMut<Playback>,
+    mut panel: ResMut<ControlPanel>,
+    mut inspector: ResMut<Inspector>,
+    mut world_query: Query<RpcWorld>,
+    simulation: Res<Simulation>,
+    config: Res<Config>,
+) {
+    if playback.steps_requested() == 0 && !server.stepping.is_empty() {
+        let generation = world_query.iter().map(|(_, _, _, generation, ..)| generation.0).max().unwrap_or(0);
+        for reply in server.stepping.drain(..) {
+            let _ = reply.send(Ok(json!({ "generation": generation })));
+        }
+    }
+
+    let requests: Vec<RpcRequest> = server.requests.lock().expect("connections don't panic while sending").try_iter().collect();
+    for RpcRequest { method, params: request_params, reply } in requests {
+        let result = match method.as_str() {
+            "pause" => {
+                playback.paused = true;
+                Ok(Value::Null)
+            }
+            "resume" => {
+                playback.paused = false;
+                Ok(Value::Null)
+            }
+            "step" => match params::<StepParams>(request_params) {
+                Ok(StepParams { generations }) => {
+                    playback.step_by(generations);
+                    server.stepping.push(reply);
+                    continue;
+                }
+                Err(e) => Err(e),
+            },
+            "set_rule" => params::<RuleParams>(request_params).and_then(|RuleParams { rule, world: number }| {
+                let rule = parse_rule(&rule).map_err(RpcError::invalid_params)?;
+                let (.., mut rules) = find_world(&mut world_query, number)?;
+                if rules.rule != rule {
+                    rules.rule = rule;
+                    panel.reset_rule_text();
+                }
+                Ok(Value::Null)
+            }),
+            "load_pattern" => params::<PathParams>(request_params).and_then(|PathParams { path, world: number }| {
+                let loaded = load_pattern(&path, &config).map_err(|e| RpcError::failed(format!("couldn't load {path}: {e}")))?;
+                let (_, _, mut world_component, mut generation, mut statistics, compute_shader, _) = find_world(&mut world_query, number)?;
+                replace_world(&mut world_component, &mut generation, &mut statistics, compute_shader, loaded, WorldSource::File(path));
+                inspector.hover(None);
+                Ok(json!({ "generation": generation.0 }))
+            }),
+            "save" => params::<PathParams>(request_params).and_then(|PathParams { path, world: number }| {
+                let (_, _, world_component, generation, statistics, compute_shader, _) = find_world(&mut world_query, number)?;
+                write_npz(path.as_ref(), &world_component, &statistics, compute_shader, &simulation, &config).map_err(|e| RpcError::failed(format!("couldn't save to {path}: {e}")))?;
+                Ok(json!({ "generation": generation.0 }))
+            }),
+            "paint" => params::<PaintParams>(request_params).and_then(|PaintParams { cells, alive, world: number }| {
+                let mut coords: Vec<WorldCoord> = cells.iter().map(|[x, y]| WorldCoord { x: *x, y: *y }).collect();
+                if let Some(coord) = coords.iter().find(|coord| !Region::whole(config.world.size).contains(**coord)) {
+                    return Err(RpcError::invalid_params(format!("[{}, {}] is outside of the world", coord.x, coord.y)));
+                }
+                if coords.is_empty() {
+                    return Ok(Value::Null);
+                }
+                let (_, _, mut world_component, _, _, compute_shader, _) = find_world(&mut world_query, number)?;
+                // `fill_cells` writes runs of neighbouring cells, which are only neighbours in the list row by row
+                coords.sort_unstable_by_key(|coord| (coord.y, coord.x));
+                coords.dedup_by_key(|coord| (coord.y, coord.x));
+                fill_cells(&mut world_component, compute_shader, &simulation, coords, if alive { Cell::Alive } else { Cell::Dead });
+                Ok(Value::Null)
+            }),
+            "stats" => params::<WorldSelection>(request_params).and_then(|WorldSelection { world: number }| {
+                let (_, _, _, generation, statistics, _, rules) = find_world(&mut world_query, number)?;
+                let stats = statistics.0.last().copied().unwrap_or_default();
+                Ok(json!({
+                    "generation": generation.0,
+                    "rule": rule_notation(rules.rule),
+                    "population": stats.population,
+                    "births": stats.births,
+                    "deaths": stats.deaths,
+                }))
+            }),
+            "region" => params::<RegionParams>(request_params).and_then(|RegionParams { x, y, width, height, world: number }| {
+                let size = config.world.size;
+                if width == 0 || height == 0 || x.checked_add(width).is_none_or(|end| end > size) || y.checked_add(height).is_none_or(|end| end > size) {
+                    return Err(RpcError::invalid_params(format!("the region has to be at least one cell and fit in the world, which is {size} cells wide")));
+                }
+                let region = Region { min: WorldCoord { x, y }, max: WorldCoord { x: x + width - 1, y: y + height - 1 } };
+                let (_, _, world_component, _, _, compute_shader, _) = find_world(&mut world_query, number)?;
+                let cells = simulation.read_region(&world_component, compute_shader, region);
+                let rows: Vec<Vec<u32>> = cells.chunks(width).map(|row| row.iter().map(|cell| *cell as u32).collect()).collect();
+                Ok(json!({ "cells": rows }))
+            }),
+            _ => Err(RpcError { code: METHOD_NOT_FOUND, message: format!("there's no method `{method}`") }),
+        };
+        // The connection may have closed while waiting
+        let _ = reply.send(result);
+    }
+}
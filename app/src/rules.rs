@@ -0,0 +1,77 @@
+use biosim_core::world::Rule;
+
+/// Rules that can be picked by name in the control panel, as `B<birth>/S<survival>` notation
+pub const RULE_PRESETS: [(&str, &str); 6] = [
+    ("Life", "B3/S23"),
+    ("HighLife", "B36/S23"),
+    ("Day & Night", "B3678/S34678"),
+    ("Seeds", "B2/S"),
+    ("Life without death", "B3/S012345678"),
+    ("Maze", "B3/S12345"),
+];
+
+/// Parses a rule in `B<birth>/S<survival>` notation like `B3/S23`, where the digits are the numbers of live neighbours
+/// that make a dead cell come alive and a live cell stay alive. Case and the order of the two halves don't matter.
+pub fn parse_rule(notation: &str) -> Result<Rule, String> {
+    let mut rule = Rule { birth: 0, survival: 0 };
+    let (mut has_birth, mut has_survival) = (false, false);
+    for part in notation.trim().split('/') {
+        let mut chars = part.trim().chars();
+        let mask = match chars.next().map(|letter| letter.to_ascii_uppercase()) {
+            Some('B') if !has_birth => {
+                has_birth = true;
+                &mut rule.birth
+            }
+            Some('S') if !has_survival => {
+                has_survival = true;
+                &mut rule.survival
+            }
+            _ => return Err(format!("`{notation}` isn't a rule, expected something like `B3/S23`")),
+        };
+        for digit in chars {
+            match digit.to_digit(10).filter(|neighbors| *neighbors <= Rule::MAX_NEIGHBORS) {
+                Some(neighbors) => *mask |= 1 << neighbors,
+                None => return Err(format!("`{digit}` in rule `{notation}` isn't a number of neighbours from 0 to {}", Rule::MAX_NEIGHBORS)),
+            }
+        }
+    }
+    if !(has_birth && has_survival) {
+        return Err(format!("`{notation}` needs both a B and an S part, like `B3/S23`"));
+    }
+    Ok(rule)
+}
+
+/// The `B<birth>/S<survival>` notation of `rule`, the opposite of `parse_rule`
+pub fn rule_notation(rule: Rule) -> String {
+    let digits = |mask: u32| (0..=Rule::MAX_NEIGHBORS).filter(|neighbors| mask & (1 << neighbors) != 0).map(|neighbors| neighbors.to_string()).collect::<String>();
+    format!("B{}/S{}", digits(rule.birth), digits(rule.survival))
+}
+
+/// The name of the preset `rule` is, if any
+pub fn preset_name(rule: Rule) -> Option<&'static str> {
+    RULE_PRESETS.iter().find(|(_, notation)| parse_rule(notation) == Ok(rule)).map(|(name, _)| *name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_are_parsed() {
+        assert_eq!(parse_rule("B3/S23"), Ok(Rule { birth: 1 << 3, survival: 1 << 2 | 1 << 3 }));
+        assert_eq!(parse_rule("B36/S23"), Ok(Rule { birth: 1 << 3 | 1 << 6, survival: 1 << 2 | 1 << 3 }));
+        assert_eq!(parse_rule(" s23 / b3 "), parse_rule("B3/S23"));
+        assert_eq!(parse_rule("B2/S"), Ok(Rule { birth: 1 << 2, survival: 0 }));
+        for (_, notation) in RULE_PRESETS {
+            assert_eq!(rule_notation(parse_rule(notation).unwrap()), notation);
+        }
+    }
+
+    #[test]
+    fn broken_rules_are_rejected() {
+        for notation in ["", "23/3", "B3", "S23", "B3/B3", "B3/S23/S4", "B3;S23", "X3/S23", "B3/S2x"] {
+            assert!(parse_rule(notation).is_err(), "{notation}");
+        }
+        assert!(parse_rule("B9/S23").unwrap_err().contains("`9`"));
+    }
+}
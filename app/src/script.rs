@@ -0,0 +1,122 @@
+use std::{fs, sync::Arc};
+
+use bevy::ecs::system::Resource;
+use biosim_core::{statistics::GenerationStats, world::{Cell, Rule}};
+use rhai::{CallFnOptions, Dynamic, Engine, FuncArgs, Map, Scope, AST, INT};
+
+use crate::{biosim_plugin::WorldId, config::{Config, RuleNotation}};
+
+/// A Rhai script from `simulation.script`, which can define any of:
+///
+/// - `fn next(state, neighbors)`, the rule: whether a cell with `state` (0 or 1) and that many live neighbours is
+///   alive next generation. It's compiled into a `Rule` when loaded, which is the lookup table both the CPU and the
+///   compute shader run, so scripted rules cost nothing per generation.
+/// - `fn setup(x, y, size)`, whether the cell at `x`, `y` starts out alive, instead of random cells
+/// - `fn on_generation(world, generation, stats)`, run on the CPU after every world's generation with a map of its
+///   `population`, `births` and `deaths`. Returning `true` or a number other than 0 pauses the app, or stops a
+///   headless run, and returning nothing carries on.
+///
+/// Either `true`/`false` or 1/0 can be returned for a live or dead cell.
+#[derive(Resource, Clone)]
+pub struct Script {
+    engine: Arc<Engine>,
+    ast: Arc<AST>,
+    path: String,
+}
+
+impl Script {
+    pub fn load(path: &str) -> Result<Script, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("couldn't read {path}: {e}"))?;
+        let engine = Engine::new();
+        let ast = engine.compile(source).map_err(|e| format!("{path}: {e}"))?;
+        Ok(Script { engine: Arc::new(engine), ast: Arc::new(ast), path: path.to_string() })
+    }
+
+    fn defines(&self, name: &str, params: usize) -> bool {
+        self.ast.iter_functions().any(|function| function.name == name && function.params.len() == params)
+    }
+
+    /// Calls the function `name` of the script without running the statements outside of functions
+    fn call(&self, name: &str, args: impl FuncArgs) -> Result<Dynamic, String> {
+        let options = CallFnOptions::new().eval_ast(false);
+        self.engine.call_fn_with_options(options, &mut Scope::new(), &self.ast, name, args).map_err(|e| format!("{}: `{name}` failed: {e}", self.path))
+    }
+
+    /// Reads a cell returned by `name` as either a bool or 0/1
+    fn cell(&self, name: &str, value: Dynamic) -> Result<Cell, String> {
+        match (value.as_bool(), value.as_int()) {
+            (Ok(true), _) | (_, Ok(1)) => Ok(Cell::Alive),
+            (Ok(false), _) | (_, Ok(0)) => Ok(Cell::Dead),
+            _ => Err(format!("{}: `{name}` returned {value}, expected true, false, 1 or 0", self.path)),
+        }
+    }
+
+    /// The rule `next` amounts to, by asking it about every state and number of live neighbours. None if the script
+    /// doesn't define `next`.
+    pub fn rule(&self) -> Result<Option<Rule>, String> {
+        if !self.defines("next", 2) {
+            return Ok(None);
+        }
+        let mut rule = Rule { birth: 0, survival: 0 };
+        for neighbors in 0..=Rule::MAX_NEIGHBORS {
+            for (state, mask) in [(Cell::Dead, &mut rule.birth), (Cell::Alive, &mut rule.survival)] {
+                let next = self.call("next", (state as INT, neighbors as INT))?;
+                if self.cell("next", next)? == Cell::Alive {
+                    *mask |= 1 << neighbors;
+                }
+            }
+        }
+        Ok(Some(rule))
+    }
+
+    /// The cells of a world `width` cells wide as `setup` lays them out, or none if the script doesn't define it
+    pub fn starting_cells(&self, width: usize) -> Result<Option<Vec<Cell>>, String> {
+        if !self.defines("setup", 3) {
+            return Ok(None);
+        }
+        let mut cells = Vec::with_capacity(width * width);
+        for y in 0..width {
+            for x in 0..width {
+                let cell = self.call("setup", (x as INT, y as INT, width as INT))?;
+                cells.push(self.cell("setup", cell)?);
+            }
+        }
+        Ok(Some(cells))
+    }
+
+    pub fn has_hook(&self) -> bool {
+        self.defines("on_generation", 3)
+    }
+
+    /// Runs `on_generation` for the generation `world` just reached, returning whether it asked to stop. Worlds are
+    /// numbered from 1 like in file names.
+    pub fn on_generation(&self, world: WorldId, generation: u64, stats: &GenerationStats) -> Result<bool, String> {
+        if !self.has_hook() {
+            return Ok(false);
+        }
+        let mut map = Map::new();
+        map.insert("population".into(), (stats.population as INT).into());
+        map.insert("births".into(), (stats.births as INT).into());
+        map.insert("deaths".into(), (stats.deaths as INT).into());
+        let result = self.call("on_generation", (world.0 as INT + 1, generation as INT, map))?;
+        match (result.as_bool(), result.as_int()) {
+            (Ok(stop), _) => Ok(stop),
+            (_, Ok(stop)) => Ok(stop != 0),
+            _ if result.is_unit() => Ok(false),
+            _ => Err(format!("{}: `on_generation` returned {result}, expected true, false, a number or nothing", self.path)),
+        }
+    }
+}
+
+/// Loads `simulation.script` if it's set. A rule defined by the script stands in for `simulation.rule`, so every world
+/// without a rule of its own runs it.
+pub fn load_script(config: &mut Config) -> Result<Option<Script>, String> {
+    let Some(path) = config.simulation.script.clone() else {
+        return Ok(None);
+    };
+    let script = Script::load(&path)?;
+    if let Some(rule) = script.rule()? {
+        config.simulation.rule = RuleNotation(rule);
+    }
+    Ok(Some(script))
+}
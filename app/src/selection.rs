@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+
+use bevy::{prelude::*, window::PrimaryWindow};
+use biosim_core::{hex_grid::{uv_to_world_space, Grid}, world::{get_index, Cell, WorldCoord}};
+
+use crate::{biosim_plugin::{edit_cells, world_at_position, WorldComponent, WorldRules}, compute_shader::BiosimComputeShader, config::Config, painting::{cursor_over_ui, cursor_position}, simulator::Simulation, world::{cell_outline, coord_at_position, Region}};
+
+const SELECTION_COLOR: Color = Color::rgb(1.0, 0.75, 0.0);
+/// Colour of the live cells of a pattern that's about to be pasted
+const PREVIEW_COLOR: Color = Color::rgb(0.0, 0.8, 0.3);
+
+const SHIFT: [KeyCode; 2] = [KeyCode::ShiftLeft, KeyCode::ShiftRight];
+const ALT: [KeyCode; 2] = [KeyCode::AltLeft, KeyCode::AltRight];
+const CONTROL: [KeyCode; 4] = [KeyCode::ControlLeft, KeyCode::ControlRight, KeyCode::SuperLeft, KeyCode::SuperRight];
+
+#[derive(Clone, Copy, Debug)]
+pub enum Selection {
+    /// Drawn as a parallelogram on hex grids
+    Rect(Region),
+    /// Every cell up to `radius` steps from `center`, see `Grid::distance`
+    Hex { center: WorldCoord, radius: usize },
+}
+
+impl Selection {
+    pub fn bounds(&self, grid: Grid, width: usize) -> Region {
+        match *self {
+            Selection::Rect(region) => region,
+            Selection::Hex { center, radius } => Region::around(grid, width, center, radius),
+        }
+    }
+
+    pub fn contains(&self, grid: Grid, coord: WorldCoord) -> bool {
+        match *self {
+            Selection::Rect(region) => region.contains(coord),
+            Selection::Hex { center, radius } => grid.distance(center, coord) <= radius as u32,
+        }
+    }
+
+    /// The cell copied patterns are rotated around
+    pub fn center(&self) -> WorldCoord {
+        match *self {
+            Selection::Rect(region) => WorldCoord { x: (region.min.x + region.max.x) / 2, y: (region.min.y + region.max.y) / 2 },
+            Selection::Hex { center, .. } => center,
+        }
+    }
+
+    /// The selected cells along the edge of the selection
+    fn border(&self, grid: Grid, width: usize) -> Vec<WorldCoord> {
+        match *self {
+            Selection::Rect(region) => region.coords().filter(|coord| coord.x == region.min.x || coord.x == region.max.x || coord.y == region.min.y || coord.y == region.max.y).collect(),
+            // The outermost ring, and wherever the world's edge cuts the hexagon off
+            Selection::Hex { center, radius } => self.bounds(grid, width).coords()
+                .filter(|coord| self.contains(grid, *coord))
+                .filter(|coord| grid.distance(center, *coord) == radius as u32 || coord.x == 0 || coord.y == 0 || coord.x == width - 1 || coord.y == width - 1)
+                .collect(),
+        }
+    }
+}
+
+/// Cells copied out of a world, as offsets from the cell they're pasted at
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    /// The grid the offsets are laid out on, which rotating and flipping keeps to
+    pub grid: Grid,
+    pub cells: Vec<((i32, i32), Cell)>,
+}
+
+impl Pattern {
+    /// Rotates counterclockwise by a quarter turn on rect grids and a sixth of a turn on hex grids
+    pub fn rotate(&mut self) {
+        self.transform(|(x, y)| (-y, x), |(q, r, s)| (-s, -q, -r));
+    }
+
+    /// Mirrors left to right
+    pub fn flip(&mut self) {
+        self.transform(|(x, y)| (-x, y), |(q, r, s)| (-r, -q, -s));
+    }
+
+    fn transform(&mut self, rect: impl Fn((i32, i32)) -> (i32, i32), hex: impl Fn((i32, i32, i32)) -> (i32, i32, i32)) {
+        for (offset, _) in &mut self.cells {
+            *offset = match self.grid {
+                Grid::Rect => rect(*offset),
+                // Hex rotations and reflections are easiest in cube coordinates, which extend `hexel_axial` by s = -q - r
+                Grid::Hex => {
+                    let (q, r) = (offset.0 + offset.1, offset.0);
+                    let (q, r, _) = hex((q, r, -q - r));
+                    (r, q - r)
+                }
+            };
+        }
+    }
+
+    /// The cells of the pattern when pasted at `target`, leaving out any that fall off the world
+    pub fn placed_at(&self, target: WorldCoord, width: usize) -> impl Iterator<Item = (WorldCoord, Cell)> + '_ {
+        self.cells.iter().filter_map(move |((x, y), cell)| {
+            let (x, y) = (target.x as i32 + x, target.y as i32 + y);
+            let in_world = (0..width as i32).contains(&x) && (0..width as i32).contains(&y);
+            in_world.then_some((WorldCoord { x: x as usize, y: y as usize }, *cell))
+        })
+    }
+}
+
+/// The selection and copied pattern. Patterns copied out of one world can be pasted into any of them.
+#[derive(Resource, Default)]
+pub struct Clipboard {
+    /// The world the selection is in along with the selection
+    pub selection: Option<(Entity, Selection)>,
+    pub pattern: Option<Pattern>,
+    /// Whether the pattern follows the cursor, waiting for a click to paste it
+    pub pasting: bool,
+    /// The world and cell the selection being dragged out started at
+    drag_start: Option<(Entity, WorldCoord)>,
+}
+
+impl Clipboard {
+    /// Whether the left mouse button is busy selecting or pasting, rather than painting
+    pub fn wants_mouse(&self, keys: &ButtonInput<KeyCode>) -> bool {
+        self.pasting || self.drag_start.is_some() || keys.any_pressed(SHIFT) || keys.any_pressed(ALT)
+    }
+}
+
+/// Dragging with shift selects a rectangle (a parallelogram on hex grids) and dragging with alt selects a hexagon
+/// around the cell the drag started at
+#[allow(clippy::too_many_arguments)]
+pub fn select(
+    buttons: Res<ButtonInput<MouseButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    worlds: Query<(Entity, &Transform, &WorldRules)>,
+    config: Res<Config>,
+    interactions: Query<&Interaction>,
+    mut clipboard: ResMut<Clipboard>,
+) {
+    let width = config.world.size;
+    let position = cursor_position(&windows, &cameras);
+
+    let selecting = keys.any_pressed(SHIFT) || keys.any_pressed(ALT);
+    if buttons.just_pressed(MouseButton::Left) && !clipboard.pasting && selecting && !cursor_over_ui(&interactions) {
+        clipboard.drag_start = position.and_then(|position| world_at_position(&worlds, &config, position));
+    }
+    if !buttons.pressed(MouseButton::Left) {
+        clipboard.drag_start = None;
+    }
+    // Selections stay in the world they were started in
+    let Some((world, start)) = clipboard.drag_start else {
+        return;
+    };
+    let Ok((_, transform, rules)) = worlds.get(world) else {
+        return;
+    };
+    let grid = rules.grid;
+    let Some(end) = position.and_then(|position| coord_at_position(grid, width, position - transform.translation.truncate())) else {
+        return;
+    };
+
+    clipboard.selection = Some((world, if keys.any_pressed(ALT) {
+        Selection::Hex { center: start, radius: grid.distance(start, end) as usize }
+    } else {
+        Selection::Rect(Region {
+            min: WorldCoord { x: start.x.min(end.x), y: start.y.min(end.y) },
+            max: WorldCoord { x: start.x.max(end.x), y: start.y.max(end.y) },
+        })
+    }));
+}
+
+/// Ctrl+C, Ctrl+X and Ctrl+V copy, cut and paste. While pasting R rotates and X flips the pattern, and escape cancels
+/// pasting or clears the selection.
+pub fn handle_clipboard_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    simulation: Res<Simulation>,
+    config: Res<Config>,
+    mut world_query: Query<(&mut WorldComponent, &BiosimComputeShader, &WorldRules)>,
+    mut clipboard: ResMut<Clipboard>,
+) {
+    let control = keys.any_pressed(CONTROL);
+
+    if control && keys.any_just_pressed([KeyCode::KeyC, KeyCode::KeyX]) {
+        let Some((world, selection)) = clipboard.selection else {
+            return;
+        };
+        if let Ok((mut world_component, compute_shader, rules)) = world_query.get_mut(world) {
+            let grid = rules.grid;
+            let (bounds, center) = (selection.bounds(grid, config.world.size), selection.center());
+            let cells = simulation.read_region(&world_component, compute_shader, bounds);
+            let cells = bounds.coords().zip(cells)
+                .filter(|(coord, _)| selection.contains(grid, *coord))
+                .map(|(coord, cell)| ((coord.x as i32 - center.x as i32, coord.y as i32 - center.y as i32), cell))
+                .collect();
+            clipboard.pattern = Some(Pattern { grid, cells });
+
+            if keys.just_pressed(KeyCode::KeyX) {
+                edit_cells(&mut world_component, compute_shader, &simulation, bounds, |coord, cell| if selection.contains(grid, coord) { Cell::Dead } else { cell });
+            }
+        }
+    }
+    if control && keys.just_pressed(KeyCode::KeyV) && clipboard.pattern.is_some() {
+        clipboard.pasting = true;
+    }
+
+    if clipboard.pasting && !control {
+        if let Some(pattern) = &mut clipboard.pattern {
+            if keys.just_pressed(KeyCode::KeyR) {
+                pattern.rotate();
+            }
+            if keys.just_pressed(KeyCode::KeyX) {
+                pattern.flip();
+            }
+        }
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        if clipboard.pasting {
+            clipboard.pasting = false;
+        } else {
+            clipboard.selection = None;
+        }
+    }
+}
+
+/// Pastes the pattern at the cursor, into whichever world is under it, when clicking while pasting
+#[allow(clippy::too_many_arguments)]
+pub fn place_pattern(
+    buttons: Res<ButtonInput<MouseButton>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    simulation: Res<Simulation>,
+    config: Res<Config>,
+    mut world_query: Query<(Entity, &mut WorldComponent, &BiosimComputeShader, &WorldRules, &Transform)>,
+    interactions: Query<&Interaction>,
+    mut clipboard: ResMut<Clipboard>,
+) {
+    if !clipboard.pasting || !buttons.just_pressed(MouseButton::Left) || cursor_over_ui(&interactions) {
+        return;
+    }
+    let width = config.world.size;
+    let worlds = world_query.iter().map(|(entity, _, _, rules, transform)| (entity, transform, rules));
+    let (Some((world, target)), Some(pattern)) = (cursor_position(&windows, &cameras).and_then(|position| world_at_position(worlds, &config, position)), &clipboard.pattern) else {
+        return;
+    };
+
+    let placed: HashMap<usize, Cell> = pattern.placed_at(target, width).map(|(coord, cell)| (get_index(coord, width), cell)).collect();
+    let bounds = pattern.placed_at(target, width)
+        .map(|(coord, _)| Region { min: coord, max: coord })
+        .reduce(Region::union);
+    if let (Some(bounds), Ok((_, mut world_component, compute_shader, _, _))) = (bounds, world_query.get_mut(world)) {
+        edit_cells(&mut world_component, compute_shader, &simulation, bounds, |coord, cell| placed.get(&get_index(coord, width)).copied().unwrap_or(cell));
+    }
+    clipboard.pasting = false;
+}
+
+/// Outlines the selection, and the live cells of the pattern under the cursor while pasting
+pub fn draw_selection(
+    mut gizmos: Gizmos,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    worlds: Query<(Entity, &Transform, &WorldRules)>,
+    config: Res<Config>,
+    clipboard: Res<Clipboard>,
+) {
+    let width = config.world.size;
+    let grid_of = |world: Entity| worlds.get(world).map_or(Grid::Rect, |(_, _, rules)| rules.grid);
+    let mut outline = |world: Entity, coord: WorldCoord, color: Color| {
+        let Ok((_, transform, rules)) = worlds.get(world) else {
+            return;
+        };
+        let grid = rules.grid;
+        let world_center = transform.translation.truncate();
+        let corners: Vec<Vec2> = cell_outline(grid, width, coord).into_iter().map(|(u, v)| world_center + Vec2::from(uv_to_world_space(grid, width, u, v))).collect();
+        gizmos.linestrip_2d(corners.iter().chain(corners.first()).copied(), color);
+    };
+
+    if let Some((world, selection)) = clipboard.selection {
+        for coord in selection.border(grid_of(world), width) {
+            outline(world, coord, SELECTION_COLOR);
+        }
+    }
+
+    if !clipboard.pasting {
+        return;
+    }
+    let (Some((world, target)), Some(pattern)) = (cursor_position(&windows, &cameras).and_then(|position| world_at_position(&worlds, &config, position)), &clipboard.pattern) else {
+        return;
+    };
+    for (coord, _) in pattern.placed_at(target, width).filter(|(_, cell)| *cell == Cell::Alive) {
+        outline(world, coord, PREVIEW_COLOR);
+    }
+}
@@ -0,0 +1,175 @@
+use std::ops::Deref;
+
+use bevy::{ecs::system::Resource, render::renderer::{RenderAdapter, RenderAdapterInfo}};
+use biosim_core::{minimap::MinimapParams, statistics::GenerationStats, world::{get_index, Cell, WorldCoord, WorldParams}};
+use wgpu::{DeviceType, DownlevelFlags};
+
+use crate::{biosim_plugin::WorldComponent, compute_shader::BiosimComputeShader, config::Backend, world::{tick, Region}};
+
+/// Runs the generations of a world and knows where its current generation is kept. The CPU keeps it in the world's
+/// `WorldComponent` and uploads it after ticking, the GPU keeps it in the buffers of its `BiosimComputeShader`. Either
+/// way the world is drawn from those buffers.
+pub trait Simulator: Send + Sync {
+    fn backend(&self) -> Backend;
+
+    /// Runs a single generation, returning its statistics
+    fn tick(&self, world_component: &mut WorldComponent, compute_shader: &mut BiosimComputeShader, params: &WorldParams) -> GenerationStats;
+
+    /// Makes the buffers the world is drawn from show the current generation, which is needed after ticking
+    fn present(&self, world_component: &WorldComponent, compute_shader: &BiosimComputeShader);
+
+    /// The cells in `region` in the order of `Region::coords`
+    fn read_region(&self, world_component: &WorldComponent, compute_shader: &BiosimComputeShader, region: Region) -> Vec<Cell>;
+
+    /// The ages of the cells in `region`, like `read_region`
+    fn read_region_ages(&self, world_component: &WorldComponent, compute_shader: &BiosimComputeShader, region: Region) -> Vec<u32>;
+
+    /// A single cell, which is cheaper than `read_region` on the GPU
+    fn read_cell(&self, world_component: &WorldComponent, compute_shader: &BiosimComputeShader, coord: WorldCoord) -> Cell;
+
+    /// Overwrites `region` with `cells` and `ages`, which are in the order of `Region::coords`. The change shows up on
+    /// screen right away, even while paused.
+    fn write_region(&self, world_component: &mut WorldComponent, compute_shader: &BiosimComputeShader, region: Region, cells: &[Cell], ages: &[u32]);
+
+    /// How many cells are alive in each block of the minimap, see `MinimapParams`
+    fn minimap_populations(&self, world_component: &WorldComponent, compute_shader: &BiosimComputeShader, params: &MinimapParams) -> Vec<u32>;
+}
+
+pub struct CpuSimulator;
+
+impl Simulator for CpuSimulator {
+    fn backend(&self) -> Backend {
+        Backend::Cpu
+    }
+
+    fn tick(&self, world_component: &mut WorldComponent, _: &mut BiosimComputeShader, params: &WorldParams) -> GenerationStats {
+        let stats;
+        (world_component.cells, world_component.ages, stats) = tick(&world_component.cells, &world_component.ages, params);
+        stats
+    }
+
+    fn present(&self, world_component: &WorldComponent, compute_shader: &BiosimComputeShader) {
+        compute_shader.copy_to_buffer(&world_component.cells);
+        compute_shader.copy_ages_to_buffer(&world_component.ages);
+    }
+
+    fn read_region(&self, world_component: &WorldComponent, compute_shader: &BiosimComputeShader, region: Region) -> Vec<Cell> {
+        region.coords().map(|coord| world_component.cells[get_index(coord, compute_shader.width())]).collect()
+    }
+
+    fn read_region_ages(&self, world_component: &WorldComponent, compute_shader: &BiosimComputeShader, region: Region) -> Vec<u32> {
+        region.coords().map(|coord| world_component.ages[get_index(coord, compute_shader.width())]).collect()
+    }
+
+    fn read_cell(&self, world_component: &WorldComponent, compute_shader: &BiosimComputeShader, coord: WorldCoord) -> Cell {
+        world_component.cells[get_index(coord, compute_shader.width())]
+    }
+
+    fn write_region(&self, world_component: &mut WorldComponent, compute_shader: &BiosimComputeShader, region: Region, cells: &[Cell], ages: &[u32]) {
+        for ((coord, cell), age) in region.coords().zip(cells).zip(ages) {
+            let index = get_index(coord, compute_shader.width());
+            (world_component.cells[index], world_component.ages[index]) = (*cell, *age);
+        }
+        compute_shader.write_region(region, cells);
+        compute_shader.write_age_region(region, ages);
+    }
+
+    fn minimap_populations(&self, world_component: &WorldComponent, _: &BiosimComputeShader, params: &MinimapParams) -> Vec<u32> {
+        (0..params.blocks).flat_map(|y| (0..params.blocks).map(move |x| (x, y))).map(|(x, y)| params.block_population(&world_component.cells, x, y)).collect()
+    }
+}
+
+/// Leaves the `WorldComponent` as it was when the world was last handed over, see `Simulation::switch`
+pub struct GpuSimulator;
+
+impl Simulator for GpuSimulator {
+    fn backend(&self) -> Backend {
+        Backend::Gpu
+    }
+
+    fn tick(&self, _: &mut WorldComponent, compute_shader: &mut BiosimComputeShader, _: &WorldParams) -> GenerationStats {
+        let stats = compute_shader.dispatch();
+        compute_shader.swap_buffers();
+        stats
+    }
+
+    fn present(&self, _: &WorldComponent, _: &BiosimComputeShader) {}
+
+    fn read_region(&self, _: &WorldComponent, compute_shader: &BiosimComputeShader, region: Region) -> Vec<Cell> {
+        compute_shader.read_region(region)
+    }
+
+    fn read_region_ages(&self, _: &WorldComponent, compute_shader: &BiosimComputeShader, region: Region) -> Vec<u32> {
+        compute_shader.read_age_region(region)
+    }
+
+    fn read_cell(&self, _: &WorldComponent, compute_shader: &BiosimComputeShader, coord: WorldCoord) -> Cell {
+        compute_shader.read_cell(coord)
+    }
+
+    fn write_region(&self, _: &mut WorldComponent, compute_shader: &BiosimComputeShader, region: Region, cells: &[Cell], ages: &[u32]) {
+        compute_shader.write_region(region, cells);
+        compute_shader.write_age_region(region, ages);
+    }
+
+    fn minimap_populations(&self, _: &WorldComponent, compute_shader: &BiosimComputeShader, _: &MinimapParams) -> Vec<u32> {
+        compute_shader.minimap_populations()
+    }
+}
+
+pub fn simulator(backend: Backend) -> Box<dyn Simulator> {
+    match backend {
+        Backend::Cpu => Box::new(CpuSimulator),
+        Backend::Gpu => Box::new(GpuSimulator),
+    }
+}
+
+/// The simulator every world runs on, which starts out as `simulation.backend` and can be switched while running
+#[derive(Resource)]
+pub struct Simulation {
+    simulator: Box<dyn Simulator>,
+    /// Why the GPU can't run the worlds, which keeps them on the CPU
+    pub gpu_unsupported: Option<String>,
+}
+
+impl Deref for Simulation {
+    type Target = dyn Simulator;
+
+    fn deref(&self) -> &Self::Target {
+        self.simulator.as_ref()
+    }
+}
+
+impl Simulation {
+    /// Runs on `backend`, or on the CPU if the GPU was asked for but can't run the worlds
+    pub fn new(backend: Backend, gpu_unsupported: Option<String>) -> Simulation {
+        let backend = if gpu_unsupported.is_some() { Backend::Cpu } else { backend };
+        Simulation { simulator: simulator(backend), gpu_unsupported }
+    }
+
+    /// Hands every world over to the simulator for `backend`, moving their current generation out of the buffers they
+    /// were kept in. Does nothing if they already run there or the GPU can't run them.
+    pub fn switch<'a>(&mut self, backend: Backend, worlds: impl IntoIterator<Item = (&'a mut WorldComponent, &'a BiosimComputeShader)>) {
+        if backend == self.backend() || (backend == Backend::Gpu && self.gpu_unsupported.is_some()) {
+            return;
+        }
+        let next = simulator(backend);
+        for (world_component, compute_shader) in worlds {
+            let whole = Region::whole(compute_shader.width());
+            let (cells, ages) = (self.read_region(world_component, compute_shader, whole), self.read_region_ages(world_component, compute_shader, whole));
+            next.write_region(world_component, compute_shader, whole, &cells, &ages);
+        }
+        self.simulator = next;
+    }
+}
+
+/// Why worlds shouldn't run in the compute shader on this adapter, if there's a reason
+pub fn gpu_unsupported(adapter: &RenderAdapter, adapter_info: &RenderAdapterInfo) -> Option<String> {
+    if !adapter.get_downlevel_capabilities().flags.contains(DownlevelFlags::COMPUTE_SHADERS) {
+        Some(format!("{} can't run compute shaders", adapter_info.name))
+    } else if adapter_info.device_type == DeviceType::Cpu {
+        Some(format!("{} is a software renderer, which is slower than simulating on the CPU directly", adapter_info.name))
+    } else {
+        None
+    }
+}
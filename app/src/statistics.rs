@@ -0,0 +1,17 @@
+use bevy::ecs::component::Component;
+use biosim_core::statistics::GenerationStats;
+
+/// The stats of every generation a world has been through, indexed by generation
+#[derive(Component, Clone, Default)]
+pub struct Statistics(pub Vec<GenerationStats>);
+
+impl Statistics {
+    /// The history as named columns, ready to be exported
+    pub fn series(&self) -> [(&'static str, Vec<u32>); 3] {
+        [
+            ("population", self.0.iter().map(|stats| stats.population).collect()),
+            ("births", self.0.iter().map(|stats| stats.births).collect()),
+            ("deaths", self.0.iter().map(|stats| stats.deaths).collect()),
+        ]
+    }
+}
@@ -0,0 +1,61 @@
+use std::{io, time::Duration};
+
+use bevy::prelude::*;
+use biosim_core::{hex_grid::Grid, render::Palette};
+use biosim_stream::{Broadcaster, Hello};
+
+use crate::{biosim_plugin::{Generation, ShaderParams, WorldComponent, WorldId, WorldRules}, compute_shader::BiosimComputeShader, config::Config, rasterizer::to_srgba8, simulator::Simulation, world::Region};
+
+/// How often the first world is streamed while it's paused, so that painting and pasting show up for spectators too
+const PAUSED_STREAM_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What viewers are told about a world `width` cells wide
+pub fn stream_hello(width: usize, grid: Grid, palette: &Palette) -> Hello {
+    Hello { width: width as u32, grid, colors: [to_srgba8(palette.states[0]), to_srgba8(palette.states[1])] }
+}
+
+/// Streams the first world to spectators on `stream.port`, like the recording only the first world is streamed
+#[derive(Resource)]
+pub struct Spectators {
+    broadcaster: Broadcaster,
+    /// What viewers were last told about the world, which is sent again when the grid or palette changes
+    hello: Hello,
+    /// The generation last streamed
+    streamed: Option<u64>,
+    timer: Timer,
+}
+
+impl Spectators {
+    pub fn listen(config: &Config, port: u16, hello: Hello) -> io::Result<Spectators> {
+        let broadcaster = Broadcaster::listen(port, hello, config.stream.keyframe_every)?;
+        Ok(Spectators { broadcaster, hello, streamed: None, timer: Timer::new(PAUSED_STREAM_INTERVAL, TimerMode::Repeating) })
+    }
+}
+
+/// Streams the first world whenever it reaches a new generation, and every `PAUSED_STREAM_INTERVAL` in between. The
+/// cells are only read back from the GPU while someone is watching.
+pub fn stream_world(
+    time: Res<Time>,
+    mut spectators: ResMut<Spectators>,
+    world_query: Query<(&WorldId, &WorldComponent, &Generation, &BiosimComputeShader, &WorldRules)>,
+    simulation: Res<Simulation>,
+    params: Res<ShaderParams>,
+    config: Res<Config>,
+) {
+    let Some((_, world_component, generation, compute_shader, rules)) = world_query.iter().find(|(id, ..)| id.0 == 0) else {
+        return;
+    };
+    let hello = stream_hello(config.world.size, rules.grid, &params.0.palette);
+    if hello != spectators.hello {
+        spectators.broadcaster.set_hello(hello);
+        spectators.hello = hello;
+    }
+
+    let interval_passed = spectators.timer.tick(time.delta()).just_finished();
+    if (spectators.streamed == Some(generation.0) && !interval_passed) || !spectators.broadcaster.has_viewers() {
+        return;
+    }
+    let cells = simulation.read_region(world_component, compute_shader, Region::whole(config.world.size));
+    spectators.broadcaster.broadcast(generation.0, &cells);
+    spectators.streamed = Some(generation.0);
+}
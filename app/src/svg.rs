@@ -0,0 +1,163 @@
+use std::fmt::Write;
+
+use biosim_core::{hex_grid::{hex_square_to_uv, Grid, HEXEL_CORNERS}, render::{GridLines, Palette, GRID_LINES_FADE_END}, world::{get_index, Cell, WorldCoord}};
+
+use crate::{rasterizer::{mesh_size, to_srgba8}, world::{cell_outline, rect_outline, Region}};
+
+pub struct SvgGridLines {
+    pub color: [f32; 4],
+    /// In the same units as the world mesh
+    pub width: f32,
+}
+
+/// Cells in a state without a colour are left out of the svg
+pub struct SvgStyle {
+    pub alive: Option<[f32; 4]>,
+    pub dead: Option<[f32; 4]>,
+    pub grid_lines: Option<SvgGridLines>,
+}
+
+impl SvgStyle {
+    /// Draws cells in the palette's colours, dead ones only with `dead_cells`. Grid lines are as thick next to the cells
+    /// as the app draws them once they've fully faded in, and left out when they're turned off.
+    pub fn new(palette: &Palette, grid_lines: &GridLines, grid: Grid, dead_cells: bool) -> SvgStyle {
+        SvgStyle {
+            alive: Some(palette.cell_to_color(Cell::Alive)),
+            dead: dead_cells.then(|| palette.cell_to_color(Cell::Dead)),
+            grid_lines: (grid_lines.width > 0.0).then(|| SvgGridLines { color: grid_lines.color, width: grid_lines.width * grid.cell_size() / GRID_LINES_FADE_END }),
+        }
+    }
+
+    fn color(&self, cell: Cell) -> Option<[f32; 4]> {
+        match cell {
+            Cell::Alive => self.alive,
+            Cell::Dead => self.dead,
+        }
+    }
+}
+
+/// Draws the cells of `region` that `contains` accepts as an svg using the same units and geometry as the world mesh.
+/// Runs of neighbouring cells in the same state are merged into a single polygon, and all of the polygons for a state
+/// share one path.
+pub fn export_svg(cells: &[Cell], width: usize, grid: Grid, region: Region, contains: impl Fn(WorldCoord) -> bool, style: &SvgStyle) -> String {
+    let mesh_size = mesh_size(grid, width);
+    let to_svg = |(u, v): (f32, f32)| (u * mesh_size.x, (1.0 - v) * mesh_size.y);
+
+    let states = [Cell::Dead, Cell::Alive];
+    let mut paths = states.map(|_| String::new());
+    let mut add_run = |state: Cell, outline: &[(f32, f32)]| {
+        if style.color(state).is_some() {
+            let index = states.iter().position(|s| *s == state).unwrap();
+            write_polygon(&mut paths[index], outline.iter().copied().map(to_svg));
+        }
+    };
+    match grid {
+        Grid::Rect => for_each_rect_run(cells, width, region, &contains, |y, first_x, last_x, state| {
+            add_run(state, &rect_outline(width, first_x, last_x, y));
+        }),
+        Grid::Hex => for_each_hex_run(cells, width, region, &contains, |row, first_column, last_column, state| {
+            add_run(state, &hex_run_outline(width, row, first_column, last_column));
+        }),
+    }
+
+    let mut grid_path = String::new();
+    let (mut min, mut max) = ((f32::INFINITY, f32::INFINITY), (f32::NEG_INFINITY, f32::NEG_INFINITY));
+    for coord in region.coords().filter(|coord| contains(*coord)) {
+        let outline = cell_outline(grid, width, coord);
+        for (x, y) in outline.iter().copied().map(to_svg) {
+            (min, max) = ((min.0.min(x), min.1.min(y)), (max.0.max(x), max.1.max(y)));
+        }
+        if style.grid_lines.is_some() {
+            write_polygon(&mut grid_path, outline.into_iter().map(to_svg));
+        }
+    }
+
+    let mut svg = String::new();
+    let (width, height) = (max.0 - min.0, max.1 - min.1);
+    writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}" width="{}" height="{}">"#, number(min.0), number(min.1), number(width), number(height), number(width), number(height)).unwrap();
+    for (state, path) in states.iter().zip(&paths) {
+        if let (Some(color), false) = (style.color(*state), path.is_empty()) {
+            writeln!(svg, r#"<path {} d="{path}"/>"#, paint("fill", color)).unwrap();
+        }
+    }
+    if let (Some(grid_lines), false) = (&style.grid_lines, grid_path.is_empty()) {
+        writeln!(svg, r#"<path fill="none" {} stroke-width="{}" d="{grid_path}"/>"#, paint("stroke", grid_lines.color), number(grid_lines.width)).unwrap();
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Calls `add_run` with the row and first and last x of every run of cells in the same state that `contains` accepts
+fn for_each_rect_run(cells: &[Cell], width: usize, region: Region, contains: &impl Fn(WorldCoord) -> bool, mut add_run: impl FnMut(usize, usize, usize, Cell)) {
+    let state = |x: usize, y: usize| contains(WorldCoord { x, y }).then(|| cells[get_index(WorldCoord { x, y }, width)]);
+    for y in region.min.y..=region.max.y {
+        let mut first_x = region.min.x;
+        for x in region.min.x..=region.max.x {
+            let current = state(x, y);
+            if x == region.max.x || state(x + 1, y) != current {
+                if let Some(current) = current {
+                    add_run(y, first_x, x, current);
+                }
+                first_x = x + 1;
+            }
+        }
+    }
+}
+
+/// Calls `add_run` with the row and first and last column of every run of hexels in the same state. Runs follow the
+/// zigzag of neighbouring columns in a row, see `uv_to_hexel_coord`. Hexels `contains` doesn't accept end runs.
+fn for_each_hex_run(cells: &[Cell], width: usize, region: Region, contains: &impl Fn(WorldCoord) -> bool, mut add_run: impl FnMut(u32, u32, u32, Cell)) {
+    let in_region = |column: u32, row: u32| {
+        let coord = WorldCoord { x: (column / 2).wrapping_sub(row) as usize, y: (row * 2 + column % 2) as usize };
+        if region.contains(coord) && contains(coord) { Some(cells[get_index(coord, width)]) } else { None }
+    };
+
+    for row in region.min.y as u32 / 2..=region.max.y as u32 / 2 {
+        let mut run: Option<(u32, Cell)> = None;
+        for column in 2 * (region.min.x as u32 + row)..=2 * (region.max.x as u32 + row) + 2 {
+            let state = in_region(column, row);
+            match (run, state) {
+                (Some((_, run_state)), Some(state)) if run_state == state => continue,
+                (Some((first_column, run_state)), _) => add_run(row, first_column, column - 1, run_state),
+                (None, _) => {}
+            }
+            run = state.map(|state| (column, state));
+        }
+    }
+}
+
+/// Outline of a run of hexels in neighbouring columns, going along the bottoms of the hexels and back along their tops
+fn hex_run_outline(width: usize, row: u32, first_column: u32, last_column: u32) -> Vec<(f32, f32)> {
+    let corner = |column: u32, corner: usize| hex_square_to_uv(width, column, row, HEXEL_CORNERS[corner].0, HEXEL_CORNERS[corner].1);
+
+    let mut outline = vec![corner(first_column, 0)];
+    outline.extend((first_column..=last_column).flat_map(|column| [corner(column, 1), corner(column, 2)]));
+    outline.push(corner(last_column, 3));
+    outline.extend((first_column..=last_column).rev().flat_map(|column| [corner(column, 4), corner(column, 5)]));
+    outline
+}
+
+fn write_polygon(path: &mut String, mut points: impl Iterator<Item = (f32, f32)>) {
+    if let Some((x, y)) = points.next() {
+        write!(path, "M{} {}", number(x), number(y)).unwrap();
+    }
+    for (x, y) in points {
+        write!(path, "L{} {}", number(x), number(y)).unwrap();
+    }
+    path.push('Z');
+}
+
+/// Rounds to keep the file small, hexel corners fall on thirds so this is plenty
+fn number(value: f32) -> f32 {
+    (value * 1000.0).round() / 1000.0
+}
+
+/// Sets `attribute` to `color`, adding an opacity attribute only if it's needed
+fn paint(attribute: &str, color: [f32; 4]) -> String {
+    let [r, g, b, a] = to_srgba8(color);
+    let mut paint = format!(r##"{attribute}="#{r:02x}{g:02x}{b:02x}""##);
+    if a != 255 {
+        write!(paint, r#" {attribute}-opacity="{}""#, number(a as f32 / 255.0)).unwrap();
+    }
+    paint
+}
@@ -1,24 +1,142 @@
-use biosim_core::{world::{get_index, Cell, WorldCoord, WorldCursor}, WORLD_WIDTH};
+use bevy::math::Vec2;
+use biosim_core::{hex_grid::{hex_square_to_uv, hexel_column_row, world_space_to_uv, Grid, HEXEL_CORNERS}, statistics::GenerationStats, util::DOption, world::{get_index, Cell, WorldCoord, WorldCursor, WorldParams}};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
-pub fn new_random() -> Vec<Cell> {
-    let mut cells: Vec<Cell> = Vec::with_capacity(WORLD_WIDTH * WORLD_WIDTH);
-    for _ in 0..WORLD_WIDTH {
-        for _ in 0..WORLD_WIDTH {
-            cells.push(rand::random());
+/// An inclusive rectangle of world coordinates. On a hex grid this is drawn as a parallelogram.
+#[derive(Clone, Copy, Debug)]
+pub struct Region {
+    pub min: WorldCoord,
+    pub max: WorldCoord,
+}
+
+impl Region {
+    /// The smallest region containing every cell drawn between two corners of the mesh of a world `width` cells wide,
+    /// given as uvs with v pointing up. Returns `None` if the corners don't overlap the world at all.
+    pub fn covering(grid: Grid, width: usize, min_uv: (f32, f32), max_uv: (f32, f32)) -> Option<Region> {
+        let world_width = width as f32;
+        let corners = [(min_uv.0, min_uv.1), (max_uv.0, min_uv.1), (min_uv.0, max_uv.1), (max_uv.0, max_uv.1)].map(|(u, v)| match grid {
+            Grid::Rect => (u * world_width, v * world_width),
+            // Undo the column/row layout of `uv_to_hexel_coord` without rounding, so corners off the world don't wrap.
+            // The extra cell of margin covers the parts of hexels that poke out of their squares.
+            Grid::Hex => {
+                let column = u * world_width * grid.width_multiplier() / 2.0;
+                let row = 0.5 * v * world_width;
+                (column / 2.0 - row, 2.0 * row)
+            }
+        });
+
+        let min_x = corners.iter().map(|corner| corner.0).fold(f32::INFINITY, f32::min).floor() - 1.0;
+        let max_x = corners.iter().map(|corner| corner.0).fold(f32::NEG_INFINITY, f32::max).ceil() + 1.0;
+        let min_y = corners.iter().map(|corner| corner.1).fold(f32::INFINITY, f32::min).floor() - 1.0;
+        let max_y = corners.iter().map(|corner| corner.1).fold(f32::NEG_INFINITY, f32::max).ceil() + 1.0;
+        if max_x < 0.0 || max_y < 0.0 || min_x >= world_width || min_y >= world_width {
+            return None;
+        }
+
+        let clamp = |value: f32| value.clamp(0.0, world_width - 1.0) as usize;
+        Some(Region { min: WorldCoord { x: clamp(min_x), y: clamp(min_y) }, max: WorldCoord { x: clamp(max_x), y: clamp(max_y) } })
+    }
+
+    /// Every cell of a world `width` cells wide
+    pub fn whole(width: usize) -> Region {
+        Region { min: WorldCoord { x: 0, y: 0 }, max: WorldCoord { x: width - 1, y: width - 1 } }
+    }
+
+    /// The smallest region containing every cell within `radius` steps of `center` (see `Grid::distance`)
+    pub fn around(grid: Grid, width: usize, center: WorldCoord, radius: usize) -> Region {
+        // Hexel neighbours can be two rows apart
+        let y_radius = match grid {
+            Grid::Rect => radius,
+            Grid::Hex => 2 * radius,
+        };
+        Region {
+            min: WorldCoord { x: center.x.saturating_sub(radius), y: center.y.saturating_sub(y_radius) },
+            max: WorldCoord { x: (center.x + radius).min(width - 1), y: (center.y + y_radius).min(width - 1) },
+        }
+    }
+
+    /// The smallest region containing both regions
+    pub fn union(self, other: Region) -> Region {
+        Region {
+            min: WorldCoord { x: self.min.x.min(other.min.x), y: self.min.y.min(other.min.y) },
+            max: WorldCoord { x: self.max.x.max(other.max.x), y: self.max.y.max(other.max.y) },
+        }
+    }
+
+    /// Number of columns
+    pub fn width(&self) -> usize {
+        self.max.x - self.min.x + 1
+    }
+
+    pub fn contains(&self, coord: WorldCoord) -> bool {
+        (self.min.x..=self.max.x).contains(&coord.x) && (self.min.y..=self.max.y).contains(&coord.y)
+    }
+
+    pub fn coords(self) -> impl Iterator<Item = WorldCoord> {
+        (self.min.y..=self.max.y).flat_map(move |y| (self.min.x..=self.max.x).map(move |x| WorldCoord { x, y }))
+    }
+}
+
+/// The cell at `position` in world space on the mesh of a world `width` cells wide, if there is one
+pub fn coord_at_position(grid: Grid, width: usize, position: Vec2) -> Option<WorldCoord> {
+    let (u, v) = world_space_to_uv(grid, width, position.x, position.y);
+    match grid.coord_at(width, u, v) {
+        DOption(true, coord) => Some(coord),
+        DOption(false, _) => None,
+    }
+}
+
+/// Corners of the cell at `coord` as uvs with v pointing up, going counterclockwise
+pub fn cell_outline(grid: Grid, width: usize, coord: WorldCoord) -> Vec<(f32, f32)> {
+    match grid {
+        Grid::Rect => rect_outline(width, coord.x, coord.x, coord.y).to_vec(),
+        Grid::Hex => {
+            let (column, row) = hexel_column_row(coord);
+            HEXEL_CORNERS.iter().map(|(x, y)| hex_square_to_uv(width, column, row, *x, *y)).collect()
+        }
+    }
+}
+
+/// Corners of a run of squares in row `y`, as uvs with v pointing up
+pub fn rect_outline(width: usize, first_x: usize, last_x: usize, y: usize) -> [(f32, f32); 4] {
+    let world_width = width as f32;
+    let (left, right) = (first_x as f32 / world_width, (last_x + 1) as f32 / world_width);
+    let (bottom, top) = (y as f32 / world_width, (y + 1) as f32 / world_width);
+    [(left, bottom), (right, bottom), (right, top), (left, top)]
+}
+
+/// A world `width` cells wide where each cell is alive with a chance of `density`. The same `seed` always gives the
+/// same world, while no seed gives a different one every time.
+pub fn new_random(width: usize, density: f64, seed: Option<u64>) -> Vec<Cell> {
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut cells: Vec<Cell> = Vec::with_capacity(width * width);
+    for _ in 0..width {
+        for _ in 0..width {
+            cells.push(if rng.gen_bool(density) { Cell::Alive } else { Cell::Dead });
         }
     }
     cells
 }
 
-pub fn tick(cells: &Vec<Cell>) -> Vec<Cell> {
-    let mut new_cells : Vec<Cell> = vec![Cell::Dead; WORLD_WIDTH * WORLD_WIDTH];
+/// Runs a generation with the rule and boundary in `params`, returning the new cells along with their ages
+pub fn tick(cells: &Vec<Cell>, ages: &[u32], params: &WorldParams) -> (Vec<Cell>, Vec<u32>, GenerationStats) {
+    let width = params.width as usize;
+    let mut new_cells : Vec<Cell> = vec![Cell::Dead; width * width];
+    let mut new_ages = vec![0; width * width];
+    let mut stats = GenerationStats::default();
 
-    for x in 0..WORLD_WIDTH {
-        for y in 0..WORLD_WIDTH {
+    for x in 0..width {
+        for y in 0..width {
             let coord = WorldCoord { x, y };
-            let cursor = WorldCursor::new(cells.as_slice().try_into().unwrap(), coord);
-            new_cells[get_index(coord)] = cursor.get_new_state();
+            let cursor = WorldCursor::new(cells.as_slice().try_into().unwrap(), ages, width, params.boundary, params.grid, coord);
+            let new_state = cursor.get_new_state(params.rule);
+            stats.count_transition(cells[get_index(coord, width)], new_state);
+            new_ages[get_index(coord, width)] = cursor.get_new_age(new_state);
+            new_cells[get_index(coord, width)] = new_state;
         }
     }
-    new_cells
+    (new_cells, new_ages, stats)
 }
@@ -0,0 +1,483 @@
+use bytemuck::{Pod, Zeroable};
+use rand::{distributions::{Distribution, Standard}, Rng};
+
+use crate::hex_grid::Grid;
+use crate::util::DOption;
+use crate::world::{get_index, Boundary, Cell, WorldCoord, WorldOffset};
+
+/// Genes in a genome, which is also the most connections a brain can have
+pub const GENOME_LENGTH: usize = 16;
+
+/// Neurons between the sensors and the actions. They keep their value until the next step, so brains can remember.
+pub const INNER_NEURONS: usize = 4;
+
+/// 1.0 in the fixed point numbers brains work in. They're evaluated in integers so the CPU and the GPU agree to the
+/// bit, which floats wouldn't.
+pub const ONE: i32 = 1 << 12;
+
+/// Actions happen when their neuron is past half of `ONE`
+const THRESHOLD: i32 = ONE / 2;
+
+/// Creatures feel older up to this many steps, after which `Sensor::Age` stays at `ONE`
+pub const MATURE_AGE: u32 = 256;
+
+/// Marks a cell no creature is on in the occupancy, and a creature that isn't moving in its intent
+pub const NOBODY: u32 = u32::MAX;
+
+/// The neighbours of a square in the order creatures turn through them, going clockwise from the one above
+const RECT_DIRECTIONS: [WorldOffset; 8] = [
+    WorldOffset { x: 0, y: 1 },
+    WorldOffset { x: 1, y: 1 },
+    WorldOffset { x: 1, y: 0 },
+    WorldOffset { x: 1, y: -1 },
+    WorldOffset { x: 0, y: -1 },
+    WorldOffset { x: -1, y: -1 },
+    WorldOffset { x: -1, y: 0 },
+    WorldOffset { x: -1, y: 1 },
+];
+
+/// The neighbours of a hexel in the order creatures turn through them, which are the axial offsets (1, 0), (1, -1),
+/// (0, -1), (-1, 0), (-1, 1) and (0, 1) going around (see `hexel_axial`)
+const HEX_DIRECTIONS: [WorldOffset; 6] = [
+    WorldOffset { x: 0, y: 1 },
+    WorldOffset { x: -1, y: 2 },
+    WorldOffset { x: -1, y: 1 },
+    WorldOffset { x: 0, y: -1 },
+    WorldOffset { x: 1, y: -2 },
+    WorldOffset { x: 1, y: -1 },
+];
+
+/// What a creature knows about itself and its surroundings, each from 0 to `ONE`
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Sensor {
+    /// The share of the neighbouring cells that are alive
+    Density = 0,
+    /// How many steps the creature has lived, up to `MATURE_AGE`
+    Age = 1,
+    /// How far across the world the creature is, from left to right
+    X = 2,
+    /// How far up the world the creature is, from bottom to top
+    Y = 3,
+    /// Goes up and back down every `AgentParams::oscillator_period` steps
+    Oscillator = 4,
+    /// `ONE` when the cell ahead is taken or past a dead edge
+    Blocked = 5,
+}
+
+impl Sensor {
+    pub const COUNT: usize = 6;
+}
+
+/// What a creature can do in a step, which it does when the neuron of the action is past `THRESHOLD`
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    /// Steps into the cell ahead, if it's free and no other creature with a lower index wants it too
+    Move = 0,
+    /// Faces the neighbour before or after the one ahead, whichever of these two neurons is stronger. Turning happens
+    /// before moving.
+    TurnLeft = 1,
+    TurnRight = 2,
+    /// Brings the cell the creature was standing on to life, starting its age over if it was dead
+    Emit = 3,
+}
+
+impl Action {
+    pub const COUNT: usize = 4;
+}
+
+/// A connection from a sensor or inner neuron to an inner neuron or action, packed into bits so every `u32` is a valid
+/// gene:
+/// - bit 31: whether the source is an inner neuron rather than a sensor
+/// - bits 24 to 30: which source, modulo how many there are
+/// - bit 23: whether the sink is an action rather than an inner neuron
+/// - bits 16 to 22: which sink, modulo how many there are
+/// - bits 0 to 15: the weight as an `i16`, where 8192 is 1.0
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Gene(pub u32);
+
+unsafe impl Zeroable for Gene {}
+
+unsafe impl Pod for Gene {}
+
+impl Gene {
+    pub fn from_inner(self) -> bool {
+        self.0 >> 31 != 0
+    }
+
+    pub fn source(self) -> usize {
+        let source = (self.0 >> 24 & 0x7f) as usize;
+        if self.from_inner() { source % INNER_NEURONS } else { source % Sensor::COUNT }
+    }
+
+    pub fn to_action(self) -> bool {
+        self.0 >> 23 & 1 != 0
+    }
+
+    pub fn sink(self) -> usize {
+        let sink = (self.0 >> 16 & 0x7f) as usize;
+        if self.to_action() { sink % Action::COUNT } else { sink % INNER_NEURONS }
+    }
+
+    /// The weight in fixed point, from -4.0 to just under 4.0
+    pub fn weight(self) -> i32 {
+        (self.0 as u16 as i16) as i32 * ONE / 8192
+    }
+}
+
+/// Everything a creature inherits, which is the wiring of its brain
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Genome {
+    pub genes: [Gene; GENOME_LENGTH],
+}
+
+unsafe impl Zeroable for Genome {}
+
+unsafe impl Pod for Genome {}
+
+impl Distribution<Genome> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Genome {
+        Genome { genes: [(); GENOME_LENGTH].map(|_| Gene(rng.gen())) }
+    }
+}
+
+/// A creature standing on a cell of a world, which is the layout of the buffer a compute shader would step them in.
+/// No two creatures stand on the same cell.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Creature {
+    pub x: u32,
+    pub y: u32,
+    /// Which neighbour is ahead, as an index into the directions of the grid
+    pub facing: u32,
+    /// Steps lived
+    pub age: u32,
+    /// What the inner neurons came out as in the last step, in fixed point
+    pub inner: [i32; INNER_NEURONS],
+    pub genome: Genome,
+}
+
+unsafe impl Zeroable for Creature {}
+
+unsafe impl Pod for Creature {}
+
+impl Creature {
+    /// A newborn at `coord` facing the first direction
+    pub fn new(coord: WorldCoord, genome: Genome) -> Creature {
+        Creature { x: coord.x as u32, y: coord.y as u32, facing: 0, age: 0, inner: [0; INNER_NEURONS], genome }
+    }
+
+    pub fn coord(&self) -> WorldCoord {
+        WorldCoord { x: self.x as usize, y: self.y as usize }
+    }
+
+    /// Runs the brain on `sensors`, updating the inner neurons and returning how strongly each action fires. Genes
+    /// coming from inner neurons read what they were after the last step, so the order of the genes doesn't matter.
+    pub fn think(&mut self, sensors: [i32; Sensor::COUNT]) -> [i32; Action::COUNT] {
+        let mut inner = [0; INNER_NEURONS];
+        let mut actions = [0; Action::COUNT];
+        for i in 0..GENOME_LENGTH {
+            let gene = self.genome.genes[i];
+            let input = if gene.from_inner() { self.inner[gene.source()] } else { sensors[gene.source()] };
+            let signal = input * gene.weight() / ONE;
+            if gene.to_action() {
+                actions[gene.sink()] += signal;
+            } else {
+                inner[gene.sink()] += signal;
+            }
+        }
+        self.inner = inner.map(|value| value.clamp(-ONE, ONE));
+        actions.map(|value| value.clamp(-ONE, ONE))
+    }
+}
+
+/// What the creatures of a world need to know about it
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct AgentParams {
+    pub width: u32,
+    pub boundary: Boundary,
+    pub grid: Grid,
+    /// Steps for `Sensor::Oscillator` to go from 0 up to `ONE` and back
+    pub oscillator_period: u32,
+}
+
+unsafe impl Zeroable for AgentParams {}
+
+unsafe impl Pod for AgentParams {}
+
+/// What a creature does this step, which `decide` works out
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Intent {
+    /// The index of the cell it moves to, or `NOBODY`
+    pub target: u32,
+    /// 1 if it emits
+    pub emit: u32,
+}
+
+unsafe impl Zeroable for Intent {}
+
+unsafe impl Pod for Intent {}
+
+/// How many neighbours a cell has on `grid`, which is also how many ways a creature can face
+pub fn direction_count(grid: Grid) -> u32 {
+    match grid {
+        Grid::Rect => RECT_DIRECTIONS.len() as u32,
+        Grid::Hex => HEX_DIRECTIONS.len() as u32,
+    }
+}
+
+/// The neighbour of `coord` in `direction`, which is none past a dead edge
+pub fn neighbor(coord: WorldCoord, direction: u32, params: &AgentParams) -> DOption<WorldCoord> {
+    let offset = match params.grid {
+        Grid::Rect => RECT_DIRECTIONS[(direction % 8) as usize],
+        Grid::Hex => HEX_DIRECTIONS[(direction % 6) as usize],
+    };
+    match params.boundary {
+        Boundary::Wrap => DOption::some(coord.offset_wrapping(offset, params.width as usize)),
+        Boundary::Dead => coord.offset(offset, params.width as usize),
+    }
+}
+
+/// Random cells `spawn` tries before going through the cells in order
+const SPAWN_ATTEMPTS: u32 = 64;
+
+/// A newborn with a random genome facing a random way, on a random cell that `occupancy` has free. The cell is marked
+/// as taken by `index`, which should be where the creature goes in the slice it's stepped in. None if every cell is
+/// taken.
+pub fn spawn<R: Rng + ?Sized>(index: u32, occupancy: &mut [u32], params: &AgentParams, rng: &mut R) -> Option<Creature> {
+    let width = params.width as usize;
+    let mut tried = 0;
+    for _ in 0..SPAWN_ATTEMPTS {
+        tried = get_index(WorldCoord { x: rng.gen_range(0..width), y: rng.gen_range(0..width) }, width);
+        if occupancy[tried] == NOBODY {
+            break;
+        }
+    }
+    // A nearly full world can take forever to turn up a free cell at random, so settle for the next one along
+    let free = (0..occupancy.len()).map(|offset| (tried + offset) % occupancy.len()).find(|cell| occupancy[*cell] == NOBODY)?;
+    occupancy[free] = index;
+    let coord = WorldCoord { x: free % width, y: free / width };
+    Some(Creature { facing: rng.gen_range(0..direction_count(params.grid)), ..Creature::new(coord, rng.gen()) })
+}
+
+/// Fills `occupancy`, which has an entry per cell, with the index of the creature on each cell or `NOBODY`. `claims`
+/// should be filled with `NOBODY` once too, after that `step_creatures` keeps both up to date.
+pub fn fill_occupancy(creatures: &[Creature], occupancy: &mut [u32], width: usize) {
+    occupancy.fill(NOBODY);
+    for (i, creature) in creatures.iter().enumerate() {
+        occupancy[get_index(creature.coord(), width)] = i as u32;
+    }
+}
+
+/// What `creature` senses this step, going by the cells and the occupancy as they were at the start of it
+pub fn sense(creature: &Creature, cells: &[Cell], occupancy: &[u32], params: &AgentParams, step: u32) -> [i32; Sensor::COUNT] {
+    let coord = creature.coord();
+    let width = params.width as usize;
+    let directions = direction_count(params.grid);
+
+    let mut alive = 0;
+    for direction in 0..directions {
+        if let DOption(true, neighbor) = neighbor(coord, direction, params) {
+            if cells[get_index(neighbor, width)] == Cell::Alive {
+                alive += 1;
+            }
+        }
+    }
+    let blocked = match neighbor(coord, creature.facing, params) {
+        DOption(true, ahead) => occupancy[get_index(ahead, width)] != NOBODY,
+        DOption(false, _) => true,
+    };
+    let edge = (params.width as i32 - 1).max(1);
+    let period = params.oscillator_period.max(1);
+    let phase = (step % period) as i32;
+
+    let mut sensors = [0; Sensor::COUNT];
+    sensors[Sensor::Density as usize] = alive * ONE / directions as i32;
+    sensors[Sensor::Age as usize] = (creature.age.min(MATURE_AGE) as i32) * ONE / MATURE_AGE as i32;
+    sensors[Sensor::X as usize] = creature.x as i32 * ONE / edge;
+    sensors[Sensor::Y as usize] = creature.y as i32 * ONE / edge;
+    sensors[Sensor::Oscillator as usize] = ONE - (2 * phase - period as i32).abs() * ONE / period as i32;
+    sensors[Sensor::Blocked as usize] = if blocked { ONE } else { 0 };
+    sensors
+}
+
+/// The first phase of a step: `creature` senses, thinks and turns, then says where it wants to go. A creature only
+/// wants cells that were free at the start of the step, so creatures never swap places or follow each other in a chain.
+pub fn decide(creature: &mut Creature, cells: &[Cell], occupancy: &[u32], params: &AgentParams, step: u32) -> Intent {
+    let actions = creature.think(sense(creature, cells, occupancy, params, step));
+    let directions = direction_count(params.grid);
+    let turn = actions[Action::TurnRight as usize] - actions[Action::TurnLeft as usize];
+    if turn > THRESHOLD {
+        creature.facing = (creature.facing + 1) % directions;
+    } else if turn < -THRESHOLD {
+        creature.facing = (creature.facing + directions - 1) % directions;
+    }
+
+    let mut target = NOBODY;
+    if actions[Action::Move as usize] > THRESHOLD {
+        if let DOption(true, ahead) = neighbor(creature.coord(), creature.facing, params) {
+            let index = get_index(ahead, params.width as usize);
+            if occupancy[index] == NOBODY {
+                target = index as u32;
+            }
+        }
+    }
+    Intent { target, emit: (actions[Action::Emit as usize] > THRESHOLD) as u32 }
+}
+
+/// The second phase: the creature at `index` claims its target. Every claim on a cell keeps the lowest index, which a
+/// compute shader would do with an atomic min, so the winner doesn't depend on the order creatures run in.
+pub fn claim(index: u32, intent: Intent, claims: &mut [u32]) {
+    if intent.target != NOBODY {
+        let claim = &mut claims[intent.target as usize];
+        *claim = (*claim).min(index);
+    }
+}
+
+/// The third phase: the creature at `index` emits and moves if it won its target, and ages a step. Only the winner
+/// writes to its target and its old cell, which nobody else could have claimed, so creatures never write to the same
+/// place.
+#[allow(clippy::too_many_arguments)]
+pub fn act(index: u32, creature: &mut Creature, intent: Intent, claims: &[u32], occupancy: &mut [u32], cells: &mut [Cell], ages: &mut [u32], params: &AgentParams) {
+    let width = params.width as usize;
+    let here = get_index(creature.coord(), width);
+    if intent.emit != 0 && cells[here] != Cell::Alive {
+        (cells[here], ages[here]) = (Cell::Alive, 0);
+    }
+    if intent.target != NOBODY && claims[intent.target as usize] == index {
+        let target = intent.target as usize;
+        occupancy[here] = NOBODY;
+        occupancy[target] = index;
+        (creature.x, creature.y) = ((target % width) as u32, (target / width) as u32);
+    }
+    creature.age = creature.age.saturating_add(1);
+}
+
+/// The last phase: frees the claims of the creature, so `claims` is all `NOBODY` again for the next step
+pub fn release(intent: Intent, claims: &mut [u32]) {
+    if intent.target != NOBODY {
+        claims[intent.target as usize] = NOBODY;
+    }
+}
+
+/// Steps every creature on the CPU, running each phase for all of them before the next. `intents` has an entry per
+/// creature, and `occupancy` and `claims` one per cell, see `fill_occupancy`. A compute shader would run a dispatch
+/// per phase with an invocation per creature, and end up with exactly the same creatures and cells.
+#[allow(clippy::too_many_arguments)]
+pub fn step_creatures(creatures: &mut [Creature], intents: &mut [Intent], occupancy: &mut [u32], claims: &mut [u32], cells: &mut [Cell], ages: &mut [u32], params: &AgentParams, step: u32) {
+    let intents = &mut intents[..creatures.len()];
+    for (creature, intent) in creatures.iter_mut().zip(intents.iter_mut()) {
+        *intent = decide(creature, cells, occupancy, params, step);
+    }
+    for (i, intent) in intents.iter().enumerate() {
+        claim(i as u32, *intent, claims);
+    }
+    for (i, (creature, intent)) in creatures.iter_mut().zip(intents.iter()).enumerate() {
+        act(i as u32, creature, *intent, claims, occupancy, cells, ages, params);
+    }
+    for intent in intents.iter() {
+        release(*intent, claims);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::mock::StepRng;
+
+    use super::*;
+
+    fn gene(from_inner: bool, source: u32, to_action: bool, sink: u32, weight: i16) -> Gene {
+        Gene((from_inner as u32) << 31 | source << 24 | (to_action as u32) << 23 | sink << 16 | weight as u16 as u32)
+    }
+
+    fn params(width: u32) -> AgentParams {
+        AgentParams { width, boundary: Boundary::Dead, grid: Grid::Rect, oscillator_period: 32 }
+    }
+
+    /// A creature that moves ahead whenever it's right of the left edge
+    fn mover(coord: WorldCoord, facing: u32) -> Creature {
+        let genome = Genome { genes: [gene(false, Sensor::X as u32, true, Action::Move as u32, i16::MAX); GENOME_LENGTH] };
+        Creature { facing, ..Creature::new(coord, genome) }
+    }
+
+    #[test]
+    fn genes_decode_their_bits() {
+        let gene = Gene(0x8a85_2000);
+        assert!(gene.from_inner() && gene.to_action());
+        // 10 wraps around the 4 inner neurons and 5 around the 4 actions
+        assert_eq!((gene.source(), gene.sink(), gene.weight()), (2, 1, ONE));
+
+        let gene = Gene(0x0703_8000);
+        assert!(!gene.from_inner() && !gene.to_action());
+        // 7 wraps around the 6 sensors and 3 is an inner neuron
+        assert_eq!((gene.source(), gene.sink(), gene.weight()), (1, 3, -4 * ONE));
+        assert_eq!(Gene(0x0000_f000).weight(), -ONE / 2);
+    }
+
+    #[test]
+    fn thinking_clamps_neurons() {
+        let mut genes = [gene(false, Sensor::Density as u32, true, Action::Emit as u32, i16::MAX); GENOME_LENGTH];
+        genes[..GENOME_LENGTH / 2].fill(gene(false, Sensor::Density as u32, false, 0, i16::MIN));
+        let mut creature = Creature::new(WorldCoord { x: 0, y: 0 }, Genome { genes });
+        let mut sensors = [0; Sensor::COUNT];
+        sensors[Sensor::Density as usize] = ONE;
+
+        let actions = creature.think(sensors);
+        assert_eq!(actions[Action::Emit as usize], ONE);
+        assert_eq!(actions[Action::Move as usize], 0);
+        assert_eq!(creature.inner, [-ONE, 0, 0, 0]);
+    }
+
+    #[test]
+    fn spawning_fills_the_world_and_then_stops() {
+        // Always picks the first cell, so every creature after the first has to look for one
+        let mut rng = StepRng::new(0, 0);
+        let mut occupancy = [NOBODY; 9];
+        for index in 0..9 {
+            let creature = spawn(index, &mut occupancy, &params(3), &mut rng).unwrap();
+            assert_eq!(occupancy[get_index(creature.coord(), 3)], index);
+        }
+        assert!(spawn(9, &mut occupancy, &params(3), &mut rng).is_none());
+    }
+
+    #[test]
+    fn lowest_index_claims_a_cell() {
+        let mut claims = [NOBODY; 4];
+        let intent = Intent { target: 2, emit: 0 };
+        for order in [[0, 1], [1, 0]] {
+            for index in order {
+                claim(index, intent, &mut claims);
+            }
+            assert_eq!(claims[2], 0);
+            release(intent, &mut claims);
+            assert_eq!(claims, [NOBODY; 4]);
+        }
+    }
+
+    #[test]
+    fn lowest_index_moves_into_a_contested_cell() {
+        let params = params(5);
+        let (below, above, between) = (WorldCoord { x: 3, y: 1 }, WorldCoord { x: 3, y: 3 }, WorldCoord { x: 3, y: 2 });
+        // Facing up and down, towards each other
+        for (first, second) in [((below, 0), (above, 4)), ((above, 4), (below, 0))] {
+            let mut creatures = [mover(first.0, first.1), mover(second.0, second.1)];
+            let mut intents = [Intent::default(); 2];
+            let (mut occupancy, mut claims) = ([NOBODY; 25], [NOBODY; 25]);
+            let (mut cells, mut ages) = ([Cell::Dead; 25], [0; 25]);
+            fill_occupancy(&creatures, &mut occupancy, 5);
+
+            step_creatures(&mut creatures, &mut intents, &mut occupancy, &mut claims, &mut cells, &mut ages, &params, 0);
+            assert_eq!(creatures[0].coord(), between);
+            assert_eq!(creatures[1].coord(), second.0);
+            assert_eq!(occupancy[get_index(between, 5)], 0);
+            assert_eq!(occupancy[get_index(first.0, 5)], NOBODY);
+            assert_eq!(occupancy[get_index(second.0, 5)], 1);
+            assert_eq!(claims, [NOBODY; 25]);
+        }
+    }
+}
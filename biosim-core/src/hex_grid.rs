@@ -1,10 +1,112 @@
-use libm::floorf;
+use bytemuck::{Pod, Zeroable};
+use libm::{fabsf, floorf, sqrtf};
 
-use crate::{world::WorldCoord, WORLD_WIDTH, WORLD_WIDTH_MULTIPLER};
+use crate::{util::{within01, DOption}, world::{get_index, Cell, WorldCoord, WorldOffset}};
 
-pub fn uv_to_hexel_coord(u: f32, v: f32) -> WorldCoord {
-    let world_width = WORLD_WIDTH as f32;
-    let u = u * (WORLD_WIDTH_MULTIPLER / 2.0);
+/// How cells are laid out, which each world picks for itself. This decides both how the world is drawn and which
+/// cells are neighbours.
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Grid {
+    Rect = 0,
+    Hex = 1,
+}
+
+unsafe impl Zeroable for Grid {
+    fn zeroed() -> Self {
+        Grid::Rect
+    }
+}
+
+unsafe impl Pod for Grid {}
+
+impl Grid {
+    /// Whether the cell `offset` away is a neighbour, which are the 8 squares around a square or the 6 hexels sharing
+    /// an edge with a hexel (see `hexel_axial`). Neighbours are never more than 1 apart in x or 2 in y.
+    pub fn is_neighbor(self, offset: WorldOffset) -> bool {
+        match self {
+            Grid::Rect => offset.x.abs() <= 1 && offset.y.abs() <= 1 && !(offset.x == 0 && offset.y == 0),
+            Grid::Hex => matches!((offset.x, offset.y), (0, 1) | (0, -1) | (1, -1) | (-1, 1) | (-1, 2) | (1, -2)),
+        }
+    }
+
+    /// How much wider than tall the world mesh is
+    pub fn width_multiplier(self) -> f32 {
+        match self {
+            Grid::Rect => 1.0,
+            Grid::Hex => 6.0,
+        }
+    }
+
+    /// Finds the cell under `u`, `v` on the mesh of a world `width` cells wide. Note that mesh uvs point down, unlike
+    /// world coordinates.
+    pub fn cell_at(self, cells: &[Cell], width: usize, u: f32, v: f32) -> DOption<Cell> {
+        match self.coord_at(width, u, 1.0 - v) {
+            DOption(true, coord) => DOption::some(cells[get_index(coord, width)]),
+            DOption(false, _) => DOption::none(),
+        }
+    }
+
+    /// Finds the coordinate of the cell at `u`, `v` (with v pointing up) on the mesh of a world `width` cells wide
+    pub fn coord_at(self, width: usize, u: f32, v: f32) -> DOption<WorldCoord> {
+        let coord = match self {
+            Grid::Rect if !(within01(u) && within01(v)) => return DOption::none(),
+            Grid::Rect => uv_to_rect_grid_coord(width, u, v),
+            Grid::Hex => uv_to_hexel_coord(width, u, v),
+        };
+
+        if coord.x >= width || coord.y >= width {
+            DOption::none()
+        } else {
+            DOption::some(coord)
+        }
+    }
+
+    /// Height of a cell in units of the world mesh
+    pub fn cell_size(self) -> f32 {
+        match self {
+            Grid::Rect => 1.0,
+            Grid::Hex => 2.0,
+        }
+    }
+
+    /// How far `u`, `v` (with v pointing up) is from the nearest edge of the cell it's in, in units of the world mesh
+    pub fn distance_to_edge(self, width: usize, u: f32, v: f32) -> f32 {
+        let world_width = width as f32;
+        match self {
+            Grid::Rect => {
+                let x = u * world_width - floorf(u * world_width);
+                let y = v * world_width - floorf(v * world_width);
+                x.min(1.0 - x).min(y).min(1.0 - y)
+            }
+            Grid::Hex => {
+                let (column, row) = hexel_column_row(uv_to_hexel_coord(width, u, v));
+                let offset = if column % 2 == 0 { 0.0 } else { 0.5 };
+                // Squares are 2 units across, and hexels are symmetric around (1/3, 1/2) in them (see `HEXEL_CORNERS`)
+                let x = 2.0 * fabsf(u * (Grid::Hex.width_multiplier() / 2.0) * world_width - column as f32 - 1.0 / 3.0);
+                let y = 2.0 * fabsf(0.5 * v * world_width - offset - row as f32 - 0.5);
+                // The top edge, and the slanted one from (2/3, 1) to (4/3, 0)
+                (1.0 - y).min((4.0 - 3.0 * x - 2.0 * y) / sqrtf(13.0))
+            }
+        }
+    }
+
+    /// The number of steps between two cells, stepping between hexels that share an edge or squares that share a corner
+    pub fn distance(self, a: WorldCoord, b: WorldCoord) -> u32 {
+        match self {
+            Grid::Rect => a.x.abs_diff(b.x).max(a.y.abs_diff(b.y)) as u32,
+            Grid::Hex => {
+                let (a, b) = (hexel_axial(a), hexel_axial(b));
+                let (dq, dr) = (a.0 - b.0, a.1 - b.1);
+                (dq.unsigned_abs() + dr.unsigned_abs() + (dq + dr).unsigned_abs()) / 2
+            }
+        }
+    }
+}
+
+pub fn uv_to_hexel_coord(width: usize, u: f32, v: f32) -> WorldCoord {
+    let world_width = width as f32;
+    let u = u * (Grid::Hex.width_multiplier() / 2.0);
     let v = v;
     
     let mut column = floorf(u * world_width) as u32;
@@ -25,23 +127,55 @@ pub fn uv_to_hexel_coord(u: f32, v: f32) -> WorldCoord {
             row += 1;
         }
         if parameter_lower < 0.0 && in_even_column {
-            row -= 1;
+            row = row.wrapping_sub(1);
         }
     }
 
-    let hexel_x: u32 = (column / 2) - row;
-    let hexel_y: u32 = row * 2 + ((if column % 2 == 0 { 0 } else { 1 }) as u32);
+    // Coordinates outside the world wrap around to huge values rather than panicking on the CPU
+    let hexel_x: u32 = (column / 2).wrapping_sub(row);
+    let hexel_y: u32 = row.wrapping_mul(2) + ((if column % 2 == 0 { 0 } else { 1 }) as u32);
 
     WorldCoord { x: hexel_x as usize, y: hexel_y as usize }
 }
 
-pub fn uv_to_rect_grid_coord(u: f32, v: f32) -> WorldCoord {
-    let x = (u * WORLD_WIDTH as f32).clamp(0.0, WORLD_WIDTH as f32 - 1.0) as usize;
-    let y = (v * WORLD_WIDTH as f32).clamp(0.0, WORLD_WIDTH as f32 - 1.0) as usize;
+/// Corners of a hexel relative to the square its column and row start in (see `uv_to_hexel_coord`), going
+/// counterclockwise from the leftmost corner. Hexels in neighbouring columns of the same row share an edge.
+pub const HEXEL_CORNERS: [(f32, f32); 6] = [(-1.0 / 3.0, 0.5), (0.0, 0.0), (2.0 / 3.0, 0.0), (1.0, 0.5), (2.0 / 3.0, 1.0), (0.0, 1.0)];
+
+/// The column and row of squares a hexel starts in. This is the inverse of the end of `uv_to_hexel_coord`.
+pub fn hexel_column_row(coord: WorldCoord) -> (u32, u32) {
+    let row = coord.y as u32 / 2;
+    (2 * (coord.x as u32 + row) + coord.y as u32 % 2, row)
+}
+
+/// Axial coordinates of a hexel. The six neighbours of a hexel are at axial offsets (±1, 0), (0, ±1) and (±1, ∓1), which
+/// are (0, ±1), (±1, ∓1) and (∓1, ±2) in world coordinates.
+pub fn hexel_axial(coord: WorldCoord) -> (i32, i32) {
+    ((coord.x + coord.y) as i32, coord.x as i32)
+}
+
+/// Converts a point relative to the square at `column`, `row` back into the uvs `uv_to_hexel_coord` takes
+pub fn hex_square_to_uv(width: usize, column: u32, row: u32, x: f32, y: f32) -> (f32, f32) {
+    let world_width = width as f32;
+    let offset = if column % 2 == 0 { 0.0 } else { 0.5 };
+    ((column as f32 + x) / (world_width * Grid::Hex.width_multiplier() / 2.0), 2.0 * (row as f32 + y + offset) / world_width)
+}
+
+pub fn uv_to_rect_grid_coord(width: usize, u: f32, v: f32) -> WorldCoord {
+    let x = (u * width as f32).clamp(0.0, width as f32 - 1.0) as usize;
+    let y = (v * width as f32).clamp(0.0, width as f32 - 1.0) as usize;
     WorldCoord { x, y }
 }
 
-pub fn world_space_to_uv(x: f32, y: f32) -> (f32, f32) {
-    let world_width = WORLD_WIDTH as f32;
-    ((x + (world_width * WORLD_WIDTH_MULTIPLER * 0.5)) / (world_width * WORLD_WIDTH_MULTIPLER), (y + (world_width * 0.5)) / world_width)
+pub fn world_space_to_uv(grid: Grid, width: usize, x: f32, y: f32) -> (f32, f32) {
+    let world_width = width as f32;
+    let multiplier = grid.width_multiplier();
+    ((x + (world_width * multiplier * 0.5)) / (world_width * multiplier), (y + (world_width * 0.5)) / world_width)
+}
+
+/// The inverse of `world_space_to_uv`
+pub fn uv_to_world_space(grid: Grid, width: usize, u: f32, v: f32) -> (f32, f32) {
+    let world_width = width as f32;
+    let multiplier = grid.width_multiplier();
+    ((u - 0.5) * world_width * multiplier, (v - 0.5) * world_width)
 }
@@ -3,9 +3,7 @@
 pub mod world;
 pub mod util;
 pub mod hex_grid;
-
-pub const WORLD_WIDTH: usize = 512;
-#[cfg(feature = "rect_grid")]
-pub const WORLD_WIDTH_MULTIPLER: f32 = 1.0;
-#[cfg(not(feature = "rect_grid"))]
-pub const WORLD_WIDTH_MULTIPLER: f32 = 6.0;
+pub mod render;
+pub mod statistics;
+pub mod minimap;
+pub mod agents;
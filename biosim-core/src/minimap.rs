@@ -0,0 +1,57 @@
+use bytemuck::{Pod, Zeroable};
+
+use crate::world::{get_index, Cell, WorldCoord};
+
+/// The most blocks the minimap splits the world into along each side
+pub const MAX_MINIMAP_BLOCKS: u32 = 128;
+
+/// How the minimap splits the world into square blocks. This is also the layout of the buffer the minimap compute
+/// shader reads.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct MinimapParams {
+    pub world_width: u32,
+    /// Width of a block in cells
+    pub block_size: u32,
+    /// Number of blocks along each side
+    pub blocks: u32,
+}
+
+unsafe impl Zeroable for MinimapParams {}
+
+unsafe impl Pod for MinimapParams {}
+
+impl MinimapParams {
+    pub fn new(world_width: u32) -> MinimapParams {
+        let block_size = world_width.div_ceil(MAX_MINIMAP_BLOCKS);
+        MinimapParams { world_width, block_size, blocks: world_width.div_ceil(block_size) }
+    }
+
+    /// The block a cell falls in, as an index into the populations
+    pub fn block_index(&self, coord: WorldCoord) -> usize {
+        let block_size = self.block_size as usize;
+        get_index(WorldCoord { x: coord.x / block_size, y: coord.y / block_size }, self.blocks as usize)
+    }
+
+    /// How many cells of the block at `block_x`, `block_y` are alive. Blocks along the far edges can be cut short by the
+    /// edge of the world.
+    pub fn block_population(&self, cells: &[Cell], block_x: u32, block_y: u32) -> u32 {
+        let width = self.world_width as usize;
+        let (first_x, first_y) = ((block_x * self.block_size) as usize, (block_y * self.block_size) as usize);
+        let mut population = 0;
+        for y in first_y..first_y + self.block_size as usize {
+            for x in first_x..first_x + self.block_size as usize {
+                if x < width && y < width && cells[get_index(WorldCoord { x, y }, width)] == Cell::Alive {
+                    population += 1;
+                }
+            }
+        }
+        population
+    }
+
+    /// How many cells of the block at `block_x`, `block_y` are inside the world
+    pub fn block_area(&self, block_x: u32, block_y: u32) -> u32 {
+        let side = |block: u32| self.block_size.min(self.world_width - block * self.block_size);
+        side(block_x) * side(block_y)
+    }
+}
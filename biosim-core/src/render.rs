@@ -0,0 +1,168 @@
+use bytemuck::{Pod, Zeroable};
+use libm::{floorf, log2f};
+
+use crate::{hex_grid::Grid, util::{clamp01, DOption}, world::{get_index, Cell, WorldParams}};
+
+/// Colour of the parts of the world mesh that aren't covered by any cell
+pub const BACKGROUND_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.0];
+
+/// How many cell states a palette has colours for
+pub const MAX_STATES: usize = 8;
+/// How many evenly spaced colours make up a palette's gradient
+pub const GRADIENT_STOPS: usize = 4;
+
+/// Linear rgba colours for each cell state, along with a gradient for colouring cells by a number like their age
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Palette {
+    /// Indexed by the cell's state, so dead cells use the first colour and live cells the second
+    pub states: [[f32; 4]; MAX_STATES],
+    pub gradient: [[f32; 4]; GRADIENT_STOPS],
+}
+
+unsafe impl Zeroable for Palette {}
+
+unsafe impl Pod for Palette {}
+
+impl Default for Palette {
+    fn default() -> Self {
+        let (dead, alive) = ([1.0, 1.0, 1.0, 1.0], [0.0, 0.0, 0.0, 1.0]);
+        let mut states = [alive; MAX_STATES];
+        states[0] = dead;
+        Palette { states, gradient: [alive, [0.25, 0.25, 0.25, 1.0], [0.5, 0.5, 0.5, 1.0], [0.75, 0.75, 0.75, 1.0]] }
+    }
+}
+
+impl Palette {
+    pub fn cell_to_color(&self, cell: Cell) -> [f32; 4] {
+        self.states[cell as usize]
+    }
+
+    /// The colour of a cell that's been in its state for `age` generations. Ages are spread logarithmically over the
+    /// gradient, since the difference between young cells matters far more than between old ones.
+    pub fn age_to_color(&self, cell: Cell, age: u32) -> [f32; 4] {
+        match cell {
+            Cell::Alive => self.gradient_color(log2f(age.min(MAX_DRAWN_AGE) as f32 + 1.0) / log2f(MAX_DRAWN_AGE as f32 + 1.0)),
+            Cell::Dead => self.cell_to_color(cell),
+        }
+    }
+
+    /// The colour of a cell in `RenderMode::Delta`. A cell's age is 0 exactly when its state changed in the last
+    /// generation, so the ages are all it takes to tell births and deaths apart from the cells that stayed the same.
+    pub fn delta_to_color(&self, cell: Cell, age: u32) -> [f32; 4] {
+        match (cell, age) {
+            (Cell::Alive, 0) => BORN_COLOR,
+            (Cell::Dead, 0) => DIED_COLOR,
+            _ => self.cell_to_color(cell),
+        }
+    }
+
+    /// The colour `t` of the way along the gradient, where `t` is between 0 and 1
+    pub fn gradient_color(&self, t: f32) -> [f32; 4] {
+        let position = clamp01(t) * (GRADIENT_STOPS - 1) as f32;
+        let index = (floorf(position) as usize).min(GRADIENT_STOPS - 2);
+        let (from, to, t) = (self.gradient[index], self.gradient[index + 1], position - index as f32);
+        [
+            from[0] + (to[0] - from[0]) * t,
+            from[1] + (to[1] - from[1]) * t,
+            from[2] + (to[2] - from[2]) * t,
+            from[3] + (to[3] - from[3]) * t,
+        ]
+    }
+}
+
+/// Live cells this many generations old or older are drawn in the last colour of the gradient when colouring by age
+pub const MAX_DRAWN_AGE: u32 = 255;
+
+/// Linear rgba colours of the cells that changed in the last generation when drawing `RenderMode::Delta`
+pub const BORN_COLOR: [f32; 4] = [0.05, 0.6, 0.1, 1.0];
+pub const DIED_COLOR: [f32; 4] = [0.8, 0.05, 0.05, 1.0];
+
+/// What decides a cell's colour
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RenderMode {
+    /// The colour of its state in the palette
+    State = 0,
+    /// Live cells are coloured along the gradient by how long they've been alive, so still lifes stand out from
+    /// oscillators and chaos. Dead cells keep their state's colour.
+    Age = 1,
+    /// Cells born in the last generation are drawn in `BORN_COLOR` and those that died in `DIED_COLOR`, while the
+    /// rest keep their state's colour
+    Delta = 2,
+}
+
+unsafe impl Zeroable for RenderMode {
+    fn zeroed() -> Self {
+        RenderMode::State
+    }
+}
+
+unsafe impl Pod for RenderMode {}
+
+/// Grid lines start fading in once cells are this many pixels tall on screen...
+pub const GRID_LINES_FADE_START: f32 = 6.0;
+/// ...and are fully drawn once they're this tall
+pub const GRID_LINES_FADE_END: f32 = 16.0;
+
+/// Cell borders drawn over the cells once the camera is zoomed in far enough to make them out
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct GridLines {
+    /// Linear rgba, the alpha is multiplied with the fade
+    pub color: [f32; 4],
+    /// Width in pixels, 0 turns the lines off
+    pub width: f32,
+    /// How many pixels a unit of the world mesh covers on screen, which changes as the camera zooms
+    pub pixels_per_unit: f32,
+}
+
+unsafe impl Zeroable for GridLines {}
+
+unsafe impl Pod for GridLines {}
+
+impl Default for GridLines {
+    fn default() -> Self {
+        GridLines { color: [0.5, 0.5, 0.5, 1.0], width: 1.0, pixels_per_unit: 1.0 }
+    }
+}
+
+impl GridLines {
+    /// Mixes the line colour into `color` near the edges of the cell at `u`, `v` (with v pointing up)
+    pub fn apply(&self, color: [f32; 4], grid: Grid, width: usize, u: f32, v: f32) -> [f32; 4] {
+        let cell_pixels = grid.cell_size() * self.pixels_per_unit;
+        let fade = clamp01((cell_pixels - GRID_LINES_FADE_START) / (GRID_LINES_FADE_END - GRID_LINES_FADE_START));
+        if fade <= 0.0 || self.width <= 0.0 {
+            return color;
+        }
+
+        // Lines are centred on the edges, so each cell draws half of them. The extra half pixel smooths their edges.
+        let edge_pixels = grid.distance_to_edge(width, u, v) * self.pixels_per_unit;
+        let coverage = clamp01(0.5 * self.width + 0.5 - edge_pixels);
+        let alpha = fade * coverage * self.color[3];
+        [
+            color[0] + (self.color[0] - color[0]) * alpha,
+            color[1] + (self.color[1] - color[1]) * alpha,
+            color[2] + (self.color[2] - color[2]) * alpha,
+            color[3] + (1.0 - color[3]) * alpha,
+        ]
+    }
+}
+
+/// The colour the fragment shader outputs at `u`, `v` on the world mesh. This is shared with the CPU rasterizer so
+/// both draw exactly the same thing.
+pub fn fragment_color(cells: &[Cell], ages: &[u32], params: &WorldParams, u: f32, v: f32) -> [f32; 4] {
+    let (width, grid) = (params.width as usize, params.grid);
+    match grid.coord_at(width, u, 1.0 - v) {
+        DOption(true, coord) => {
+            let (cell, age) = (cells[get_index(coord, width)], ages[get_index(coord, width)]);
+            let color = match params.render_mode {
+                RenderMode::State => params.palette.cell_to_color(cell),
+                RenderMode::Age => params.palette.age_to_color(cell, age),
+                RenderMode::Delta => params.palette.delta_to_color(cell, age),
+            };
+            params.grid_lines.apply(color, grid, width, u, 1.0 - v)
+        }
+        DOption(false, _) => BACKGROUND_COLOR,
+    }
+}
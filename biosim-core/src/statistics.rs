@@ -0,0 +1,35 @@
+use bytemuck::{Pod, Zeroable};
+
+use crate::world::Cell;
+
+/// Summary of a single generation. This is also the layout of the buffer the compute shader counts into.
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct GenerationStats {
+    pub population: u32,
+    pub births: u32,
+    pub deaths: u32,
+}
+
+unsafe impl Zeroable for GenerationStats {}
+
+unsafe impl Pod for GenerationStats {}
+
+impl GenerationStats {
+    /// Stats for a world that wasn't ticked into existence, so nothing was born or died
+    pub fn initial(cells: &[Cell]) -> GenerationStats {
+        let population = cells.iter().filter(|cell| **cell == Cell::Alive).count() as u32;
+        GenerationStats { population, ..Default::default() }
+    }
+
+    pub fn count_transition(&mut self, old: Cell, new: Cell) {
+        match (old, new) {
+            (Cell::Dead, Cell::Alive) => self.births += 1,
+            (Cell::Alive, Cell::Dead) => self.deaths += 1,
+            _ => {}
+        }
+        if new == Cell::Alive {
+            self.population += 1;
+        }
+    }
+}
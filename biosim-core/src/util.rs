@@ -20,3 +20,14 @@ where T: Default
     }
 }
 
+/// `x` limited to between 0 and 1. Plain min and max are easier on rust-gpu than `clamp`, which asserts on its bounds.
+#[allow(clippy::manual_clamp)]
+pub fn clamp01(x: f32) -> f32 {
+    x.max(0.0).min(1.0)
+}
+
+/// Whether `x` is between 0 and 1, with plain comparisons instead of a range for the same reason as `clamp01`
+#[allow(clippy::manual_range_contains)]
+pub fn within01(x: f32) -> bool {
+    x >= 0.0 && x <= 1.0
+}
@@ -1,10 +1,8 @@
-use core::ops;
-
 use bytemuck::{Pod, Zeroable};
 use rand::{distributions::{Distribution, Standard}, Rng};
 
-use crate::WORLD_WIDTH;
-
+use crate::hex_grid::Grid;
+use crate::render::{GridLines, Palette, RenderMode};
 use crate::util::DOption;
 
 #[repr(u32)]
@@ -41,40 +39,116 @@ impl Distribution<Cell> for Standard {
 
 impl Copy for Cell {}
 
-#[derive(Copy, Clone, Default, Debug)]
+/// Settings of a world that the shaders need as well, which is why this is also the layout of a buffer they read
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct WorldParams {
+    pub width: u32,
+    pub palette: Palette,
+    pub grid_lines: GridLines,
+    pub render_mode: RenderMode,
+    pub rule: Rule,
+    pub boundary: Boundary,
+    pub grid: Grid,
+}
+
+unsafe impl Zeroable for WorldParams {}
+
+unsafe impl Pod for WorldParams {}
+
+/// A life-like rule, saying how many live neighbours it takes for a dead cell to be born and for a live cell to survive.
+/// Bit n of each mask is set if n neighbours do it, so Conway's Life (B3/S23) has bit 3 of `birth` and bits 2 and 3
+/// of `survival` set.
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Rule {
+    pub birth: u32,
+    pub survival: u32,
+}
+
+unsafe impl Zeroable for Rule {}
+
+unsafe impl Pod for Rule {}
+
+impl Rule {
+    pub const LIFE: Rule = Rule { birth: 1 << 3, survival: 1 << 2 | 1 << 3 };
+
+    /// Most neighbours a cell can have
+    pub const MAX_NEIGHBORS: u32 = 8;
+
+    pub fn next_state(&self, cell: Cell, living_neighbors: u32) -> Cell {
+        let mask = match cell {
+            Cell::Alive => self.survival,
+            Cell::Dead => self.birth,
+        };
+        if mask & (1 << living_neighbors) != 0 { Cell::Alive } else { Cell::Dead }
+    }
+}
+
+impl Default for Rule {
+    fn default() -> Self {
+        Rule::LIFE
+    }
+}
+
+/// What lies past the edges of the world
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Boundary {
+    /// Cells off the edge count as dead
+    Dead = 0,
+    /// The world wraps around like a torus, so cells on opposite edges are neighbours
+    Wrap = 1,
+}
+
+unsafe impl Zeroable for Boundary {
+    fn zeroed() -> Self {
+        Boundary::Dead
+    }
+}
+
+unsafe impl Pod for Boundary {}
+
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
 pub struct WorldCoord {
     pub x: usize,
     pub y: usize,
 }
 
+#[derive(Clone, Copy)]
 pub struct WorldOffset {
     pub x: i32,
     pub y: i32,
 }
 
-impl ops::Add<WorldOffset> for WorldCoord {
-    type Output = DOption<WorldCoord>;
-
-    fn add(self, rhs: WorldOffset) -> Self::Output {
-        let x = self.x as i32 + rhs.x;
-        let y = self.y as i32 + rhs.y;
-        if x < 0 || y < 0 || x as usize >= WORLD_WIDTH || y as usize >= WORLD_WIDTH {
+impl WorldCoord {
+    /// Moves by `offset`, or returns none if that leaves a world `width` cells wide
+    pub fn offset(self, offset: WorldOffset, width: usize) -> DOption<WorldCoord> {
+        let x = self.x as i32 + offset.x;
+        let y = self.y as i32 + offset.y;
+        if x < 0 || y < 0 || x as usize >= width || y as usize >= width {
             DOption::none()
         } else {
             DOption::some(WorldCoord { x: x as usize, y: y as usize })
         }
     }
-}
 
-impl WorldCoord {
-    pub fn add_clamped(&self, offset: WorldOffset) -> WorldCoord {
-        let x = (self.x as i32 + offset.x).clamp(0, WORLD_WIDTH as i32 - 1) as usize;
-        let y = (self.y as i32 + offset.y).clamp(0, WORLD_WIDTH as i32 - 1) as usize;
+    /// Moves by `offset`, coming back in on the opposite side when that leaves a world `width` cells wide
+    pub fn offset_wrapping(self, offset: WorldOffset, width: usize) -> WorldCoord {
+        let width = width as i32;
+        let x = ((self.x as i32 + offset.x) % width + width) % width;
+        let y = ((self.y as i32 + offset.y) % width + width) % width;
+        WorldCoord { x: x as usize, y: y as usize }
+    }
+
+    pub fn add_clamped(&self, offset: WorldOffset, width: usize) -> WorldCoord {
+        let x = (self.x as i32 + offset.x).clamp(0, width as i32 - 1) as usize;
+        let y = (self.y as i32 + offset.y).clamp(0, width as i32 - 1) as usize;
         WorldCoord { x, y }
     }
 
     pub fn min() -> WorldCoord { WorldCoord { x: 0, y: 0 }}
-    pub fn max() -> WorldCoord { WorldCoord { x: WORLD_WIDTH - 1, y: WORLD_WIDTH - 1 } }
+    pub fn max(width: usize) -> WorldCoord { WorldCoord { x: width - 1, y: width - 1 } }
 }
 
 impl WorldOffset {
@@ -86,32 +160,38 @@ impl WorldOffset {
 pub struct WorldCursor<'a> {
     coord: WorldCoord,
     cells: &'a [Cell],
+    /// How many generations each cell has been in its current state, see `next_age`
+    ages: &'a [u32],
+    width: usize,
+    boundary: Boundary,
+    /// Decides which cells are neighbours, see `Grid::is_neighbor`
+    grid: Grid,
 }
 
 impl<'a> WorldCursor<'a> {
-    pub fn new(cells: &'a [Cell], coord: WorldCoord) -> WorldCursor<'a> {
-        WorldCursor { coord, cells }
+    pub fn new(cells: &'a [Cell], ages: &'a [u32], width: usize, boundary: Boundary, grid: Grid, coord: WorldCoord) -> WorldCursor<'a> {
+        WorldCursor { coord, cells, ages, width, boundary, grid }
     }
 
-    pub fn get_new_state(&self) -> Cell {
-        match self.get_cell() {
-            Cell::Alive => {
-                match self.count_living_neighbors() {
-                    2..=3 => Cell::Alive,
-                    _ => Cell::Dead,
-                }
-            }
-            Cell::Dead => {
-                match self.count_living_neighbors() {
-                    3 => Cell::Alive,
-                    _ => Cell::Dead,
-                }
-            }
-        } 
+    /// How many generations the cell has been in its current state, which rules are free to take into account
+    pub fn get_age(&self) -> u32 {
+        self.ages[get_index(self.coord, self.width)]
+    }
+
+    /// The cell's age once it has changed into `new_state`
+    pub fn get_new_age(&self, new_state: Cell) -> u32 {
+        next_age(self.get_cell(), new_state, self.get_age())
+    }
+
+    pub fn get_new_state(&self, rule: Rule) -> Cell {
+        rule.next_state(self.get_cell(), self.count_living_neighbors())
     }
 
     fn get_cell_at_offset(&self, offset: WorldOffset) -> Cell {
-            match self.coord + offset {
+            if self.boundary == Boundary::Wrap {
+                return self.get_cell_at_coord(self.coord.offset_wrapping(offset, self.width));
+            }
+            match self.coord.offset(offset, self.width) {
                 DOption(true, coord) => self.get_cell_at_coord(coord),
                 DOption(false, _) => Cell::Dead
             }
@@ -119,7 +199,7 @@ impl<'a> WorldCursor<'a> {
     
     fn get_cell_at_coord(&self, coord: WorldCoord) -> Cell {
         self.cells[
-                get_index(coord)
+                get_index(coord, self.width)
             ]
     }
 
@@ -127,11 +207,12 @@ impl<'a> WorldCursor<'a> {
         self.get_cell_at_offset(WorldOffset::zero())
     }
 
-    fn count_living_neighbors(&self) -> i32 {
+    pub fn count_living_neighbors(&self) -> u32 {
         let mut num_living_neighbors = 0;
         for x in -1..=1 {
-            for y in -1..=1 {
-                if self.get_cell_at_offset(WorldOffset { x, y }) == Cell::Alive && !(x == 0 && y == 0) {
+            for y in -2..=2 {
+                let offset = WorldOffset { x, y };
+                if self.grid.is_neighbor(offset) && self.get_cell_at_offset(offset) == Cell::Alive {
                     num_living_neighbors += 1;
                 }
             }
@@ -140,7 +221,12 @@ impl<'a> WorldCursor<'a> {
     }
 }
 
-pub fn get_index(coord: WorldCoord) -> usize {
-    coord.y * WORLD_WIDTH + coord.x
+/// Cells age by one every generation they stay in the same state, and start over at 0 when it changes
+pub fn next_age(old: Cell, new: Cell, age: u32) -> u32 {
+    if old == new { age.saturating_add(1) } else { 0 }
+}
+
+pub fn get_index(coord: WorldCoord, width: usize) -> usize {
+    coord.y * width + coord.x
 }
 
@@ -1,8 +1,8 @@
 #![allow(unexpected_cfgs)]
 #![cfg_attr(target_arch = "spirv", no_std)]
 
-use biosim_core::{hex_grid::{uv_to_hexel_coord, uv_to_rect_grid_coord}, world::{get_index, Cell, WorldCoord, WorldCursor}, WORLD_WIDTH};
-use spirv_std::{glam::{vec4, UVec3, Vec2, Vec3, Vec4}, spirv};
+use biosim_core::{minimap::MinimapParams, render::fragment_color, statistics::GenerationStats, world::{get_index, Cell, WorldCoord, WorldCursor, WorldParams}};
+use spirv_std::{arch::atomic_i_add, glam::{UVec3, Vec2, Vec3, Vec4}, memory::{Scope, Semantics}, spirv};
 
 #[spirv(fragment)]
 pub fn fragment(
@@ -10,36 +10,11 @@ pub fn fragment(
     _: Vec3,
     uv: Vec2,
     #[spirv(storage_buffer, descriptor_set = 2, binding = 0)] cells: &[Cell], 
+    #[spirv(storage_buffer, descriptor_set = 2, binding = 1)] params: &WorldParams,
+    #[spirv(storage_buffer, descriptor_set = 2, binding = 2)] ages: &[u32],
     output: &mut Vec4
 ) {
-    if cfg!(feature = "rect_grid") {
-        rect_grid(uv, cells, output);
-    } else {
-        hex_grid(uv, cells, output);
-    }
-}
-
-fn rect_grid(uv: Vec2, cells: &[Cell], output: &mut Vec4) {
-  let coord = uv_to_rect_grid_coord(uv.x, 1.0 - uv.y);
-  *output = cell_to_color(cells[get_index(coord)]);
-}
-
-fn hex_grid(uv: Vec2, cells: &[Cell], output: &mut Vec4) {
-    let coord = uv_to_hexel_coord(uv.x, 1.0 - uv.y);
-    let WorldCoord { x: hexel_x, y: hexel_y } = coord.clone();
-
-    if hexel_x > WORLD_WIDTH || hexel_y > WORLD_WIDTH {
-        *output = vec4(0.0, 0.0, 0.0, 0.0);
-    } else {
-        *output = cell_to_color(cells[get_index(coord)]);
-    }
-}
-
-fn cell_to_color(cell: Cell) -> Vec4 {
-    match cell {
-        Cell::Alive => vec4(0.0, 0.0, 0.0, 1.0),
-        Cell::Dead => vec4(1.0, 1.0, 1.0, 1.0),
-    }
+    *output = Vec4::from_array(fragment_color(cells, ages, params, uv.x, uv.y));
 }
 
 #[spirv(compute(threads(32, 32)))]
@@ -47,21 +22,57 @@ pub fn main(
     #[spirv(global_invocation_id)] global_id: UVec3,
     #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] input: &[Cell],
     #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] output: &mut [Cell],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] stats: &mut GenerationStats,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] params: &WorldParams,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] input_ages: &[u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 5)] output_ages: &mut [u32],
 ) {
+    let width = params.width as usize;
     let coord = WorldCoord { x: global_id.x as usize, y: global_id.y as usize };
-    let idx = get_index(coord);
-    if idx < input.len() {
-        update_cell(input, output, coord);
+    // The last workgroups hang off the edge of worlds that aren't a multiple of their size
+    if coord.x < width && coord.y < width {
+        update_cell(input, output, input_ages, output_ages, stats, params, coord);
     }
 }
 
-fn update_cell(input: &[Cell], output: &mut [Cell], coord: WorldCoord) {
-    let cursor = WorldCursor::new(input, coord);
+/// Counts the live cells of each block of the minimap, so only the counts have to be read back
+#[spirv(compute(threads(8, 8)))]
+pub fn minimap(
+    #[spirv(global_invocation_id)] global_id: UVec3,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] cells: &[Cell],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] params: &MinimapParams,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] populations: &mut [u32],
+) {
+    if global_id.x < params.blocks && global_id.y < params.blocks {
+        populations[(global_id.y * params.blocks + global_id.x) as usize] = params.block_population(cells, global_id.x, global_id.y);
+    }
+}
+
+fn update_cell(input: &[Cell], output: &mut [Cell], input_ages: &[u32], output_ages: &mut [u32], stats: &mut GenerationStats, params: &WorldParams, coord: WorldCoord) {
+    let width = params.width as usize;
+    let cursor = WorldCursor::new(input, input_ages, width, params.boundary, params.grid, coord);
     
-    let new_state = cursor.get_new_state();
-    set_cell_at(output, coord, new_state);
+    let new_state = cursor.get_new_state(params.rule);
+    output_ages[get_index(coord, width)] = cursor.get_new_age(new_state);
+    set_cell_at(output, width, coord, new_state);
+    count_transition(stats, input[get_index(coord, width)], new_state);
+}
+
+/// Same as `GenerationStats::count_transition`, but safe to call from every invocation at once
+fn count_transition(stats: &mut GenerationStats, old: Cell, new: Cell) {
+    unsafe {
+        if old == Cell::Dead && new == Cell::Alive {
+            atomic_i_add::<u32, { Scope::Device as u32 }, { Semantics::NONE.bits() }>(&mut stats.births, 1);
+        }
+        if old == Cell::Alive && new == Cell::Dead {
+            atomic_i_add::<u32, { Scope::Device as u32 }, { Semantics::NONE.bits() }>(&mut stats.deaths, 1);
+        }
+        if new == Cell::Alive {
+            atomic_i_add::<u32, { Scope::Device as u32 }, { Semantics::NONE.bits() }>(&mut stats.population, 1);
+        }
+    }
 }
 
-fn set_cell_at(buf: &mut [Cell], coord: WorldCoord, cell: Cell) {
-    buf[get_index(coord)] = cell;
+fn set_cell_at(buf: &mut [Cell], width: usize, coord: WorldCoord, cell: Cell) {
+    buf[get_index(coord, width)] = cell;
 }
@@ -0,0 +1,470 @@
+//! Streams a world to spectators over TCP, so a simulation running in one process can be watched from another without
+//! running it again. Every message is a little endian `u32` length followed by that many bytes, the first of which says
+//! what the message is:
+//!
+//! - `HELLO`, sent when a viewer connects and whenever the grid or colours change: the magic bytes `BIOSIM`, the protocol version, the width of the world
+//!   as a `u32`, its grid as a byte and the srgba colours of dead and alive cells
+//! - `KEYFRAME`: the generation as a `u64`, then the whole world as the lengths of alternating runs of dead and live
+//!   cells in index order, starting with dead
+//! - `DELTA`: the generation as a `u64`, then the cells that changed since the last frame as pairs of the number of
+//!   unchanged cells before a run and the length of the run
+//!
+//! Lengths are LEB128 varints. Deltas only make sense on top of the frame before them, so viewers get a keyframe first,
+//! every `keyframe_every` generations after that, and whenever they fell behind and missed a delta.
+
+use std::{io::{self, Read, Write}, net::{Ipv4Addr, TcpListener, TcpStream}, ops::Range, sync::{mpsc::{self, Receiver, SyncSender, TrySendError}, Arc, Mutex}, thread};
+
+use biosim_core::{hex_grid::Grid, world::Cell};
+
+const MAGIC: &[u8; 6] = b"BIOSIM";
+const VERSION: u8 = 1;
+
+const HELLO: u8 = 0;
+const KEYFRAME: u8 = 1;
+const DELTA: u8 = 2;
+
+/// Messages are never larger than a keyframe of the largest world, which is well under this
+const MAX_MESSAGE_LEN: u32 = 1 << 28;
+
+/// The widest world a hello can announce, which is the app's `MAX_WORLD_SIZE`. Viewers allocate a world of this size
+/// before the first keyframe arrives.
+pub const MAX_WIDTH: u32 = 4096;
+
+/// How many messages can wait to be sent to a viewer before it counts as behind
+const VIEWER_BACKLOG: usize = 16;
+
+/// What a viewer needs to know about the world before the first frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hello {
+    /// At most `MAX_WIDTH`
+    pub width: u32,
+    pub grid: Grid,
+    /// srgba colours of dead and live cells
+    pub colors: [[u8; 4]; 2],
+}
+
+/// The cells of a generation, either all of them or what changed since the frame before
+#[derive(Clone, Debug, PartialEq)]
+pub enum Frame {
+    Keyframe(Vec<Cell>),
+    /// Ranges of cell indices that flipped between dead and alive
+    Delta(Vec<Range<usize>>),
+}
+
+impl Frame {
+    /// Every cell of `cells`
+    pub fn keyframe(cells: &[Cell]) -> Frame {
+        Frame::Keyframe(cells.to_vec())
+    }
+
+    /// The cells that differ between `previous` and `cells`, which have to be the same size
+    pub fn delta(previous: &[Cell], cells: &[Cell]) -> Frame {
+        let mut changes: Vec<Range<usize>> = Vec::new();
+        for (index, _) in previous.iter().zip(cells).enumerate().filter(|(_, (previous, cell))| previous != cell) {
+            match changes.last_mut() {
+                Some(run) if run.end == index => run.end += 1,
+                _ => changes.push(index..index + 1),
+            }
+        }
+        Frame::Delta(changes)
+    }
+
+    /// Brings `cells` up to this frame. Keyframes replace them, deltas flip the cells that changed.
+    pub fn apply(&self, cells: &mut Vec<Cell>) -> io::Result<()> {
+        match self {
+            Frame::Keyframe(keyframe) => cells.clone_from(keyframe),
+            Frame::Delta(changes) => {
+                // Deltas don't have to come from `read`, which only makes ones in order
+                if changes.iter().any(|run| run.start > run.end || run.end > cells.len()) {
+                    return Err(invalid_data("the delta changes cells outside of the world"));
+                }
+                for run in changes {
+                    for cell in &mut cells[run.clone()] {
+                        *cell = match cell {
+                            Cell::Dead => Cell::Alive,
+                            Cell::Alive => Cell::Dead,
+                        };
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    Hello(Hello),
+    Frame { generation: u64, frame: Frame },
+}
+
+impl Message {
+    /// The message with its length in front, ready to be written to a stream
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![0; 4];
+        match self {
+            Message::Hello(hello) => {
+                bytes.push(HELLO);
+                bytes.extend_from_slice(MAGIC);
+                bytes.push(VERSION);
+                bytes.extend_from_slice(&hello.width.to_le_bytes());
+                bytes.push(hello.grid as u8);
+                bytes.extend(hello.colors.iter().flatten());
+            }
+            Message::Frame { generation, frame: Frame::Keyframe(cells) } => {
+                bytes.push(KEYFRAME);
+                bytes.extend_from_slice(&generation.to_le_bytes());
+                let mut state = Cell::Dead;
+                let mut run = 0;
+                for cell in cells {
+                    if *cell != state {
+                        write_varint(&mut bytes, run);
+                        (state, run) = (*cell, 0);
+                    }
+                    run += 1;
+                }
+                write_varint(&mut bytes, run);
+            }
+            Message::Frame { generation, frame: Frame::Delta(changes) } => {
+                bytes.push(DELTA);
+                bytes.extend_from_slice(&generation.to_le_bytes());
+                let mut end = 0;
+                for run in changes {
+                    write_varint(&mut bytes, (run.start - end) as u64);
+                    write_varint(&mut bytes, run.len() as u64);
+                    end = run.end;
+                }
+            }
+        }
+        let len = (bytes.len() - 4) as u32;
+        bytes[..4].copy_from_slice(&len.to_le_bytes());
+        bytes
+    }
+
+    /// Reads the next message, waiting for it to arrive. Keyframes are checked against the size of the world in
+    /// `hello`, and can't be read before it.
+    pub fn read(reader: &mut impl Read, hello: Option<&Hello>) -> io::Result<Message> {
+        let mut len = [0; 4];
+        reader.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len);
+        if len == 0 || len > MAX_MESSAGE_LEN {
+            return Err(invalid_data(format!("a message can't be {len} bytes long")));
+        }
+        let mut bytes = vec![0; len as usize];
+        reader.read_exact(&mut bytes)?;
+        let (tag, mut bytes) = (bytes[0], Bytes(&bytes[1..]));
+
+        match tag {
+            HELLO => {
+                if bytes.take(MAGIC.len())? != MAGIC {
+                    return Err(invalid_data("this isn't a biosim stream"));
+                }
+                let version = bytes.take(1)?[0];
+                if version != VERSION {
+                    return Err(invalid_data(format!("the stream is version {version}, but this reads version {VERSION}")));
+                }
+                let width = u32::from_le_bytes(bytes.array()?);
+                if !(1..=MAX_WIDTH).contains(&width) {
+                    return Err(invalid_data(format!("a world can't be {width} cells wide, at most {MAX_WIDTH}")));
+                }
+                let grid = match bytes.take(1)?[0] {
+                    0 => Grid::Rect,
+                    1 => Grid::Hex,
+                    grid => return Err(invalid_data(format!("there's no grid {grid}"))),
+                };
+                let colors = [bytes.array()?, bytes.array()?];
+                Ok(Message::Hello(Hello { width, grid, colors }))
+            }
+            tag @ (KEYFRAME | DELTA) => {
+                let generation = u64::from_le_bytes(bytes.array()?);
+                let frame = if tag == KEYFRAME {
+                    let Some(hello) = hello else {
+                        return Err(invalid_data("a keyframe came before the hello"));
+                    };
+                    let size = hello.width as usize * hello.width as usize;
+                    let mut cells = Vec::with_capacity(size);
+                    let mut state = Cell::Dead;
+                    while !bytes.0.is_empty() {
+                        let end = bytes.varint().map(|run| cells.len().checked_add(run as usize))?;
+                        let Some(end) = end.filter(|end| *end <= size) else {
+                            return Err(invalid_data("the keyframe has more cells than the world"));
+                        };
+                        cells.resize(end, state);
+                        state = if state == Cell::Dead { Cell::Alive } else { Cell::Dead };
+                    }
+                    if cells.len() != size {
+                        return Err(invalid_data("the keyframe has fewer cells than the world"));
+                    }
+                    Frame::Keyframe(cells)
+                } else {
+                    let mut changes = Vec::new();
+                    let mut end: usize = 0;
+                    while !bytes.0.is_empty() {
+                        let too_far = || invalid_data("the delta goes past the last index there can be");
+                        let start = end.checked_add(bytes.varint()? as usize).ok_or_else(too_far)?;
+                        end = start.checked_add(bytes.varint()? as usize).ok_or_else(too_far)?;
+                        changes.push(start..end);
+                    }
+                    Frame::Delta(changes)
+                };
+                Ok(Message::Frame { generation, frame })
+            }
+            tag => Err(invalid_data(format!("there's no message {tag}"))),
+        }
+    }
+}
+
+/// What's left of a message being read
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(invalid_data("the message ends early"));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().expect("took exactly N bytes"))
+    }
+
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid_data("a number in the message is too long"))
+    }
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// A spectator connected to a `Broadcaster`, whose messages are written on a thread of its own so a slow viewer
+/// doesn't hold up the simulation
+struct Viewer {
+    messages: SyncSender<Arc<[u8]>>,
+    /// Set for new viewers and ones that missed a frame, which can't follow deltas until they get a keyframe
+    needs_keyframe: bool,
+}
+
+/// Sends the generations of a world to every viewer connected to a port of localhost, see the top of this file for
+/// the protocol
+pub struct Broadcaster {
+    viewers: Arc<Mutex<Vec<Viewer>>>,
+    /// The encoded hello, which new viewers are greeted with
+    hello: Arc<Mutex<Arc<[u8]>>>,
+    keyframe_every: u64,
+    /// The generation and cells of the last frame, which the next delta is taken against
+    previous: Option<(u64, Vec<Cell>)>,
+    last_keyframe: u64,
+}
+
+impl Broadcaster {
+    /// Starts taking viewers on `port`, greeting each with `hello`. A keyframe is sent at least every
+    /// `keyframe_every` generations.
+    pub fn listen(port: u16, hello: Hello, keyframe_every: u64) -> io::Result<Broadcaster> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        let viewers = Arc::new(Mutex::new(Vec::new()));
+        let hello: Arc<Mutex<Arc<[u8]>>> = Arc::new(Mutex::new(Message::Hello(hello).encode().into()));
+        let (accepted, greeting) = (viewers.clone(), hello.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (messages, receiver) = mpsc::sync_channel(VIEWER_BACKLOG);
+                // Holding on to the viewers keeps the hello from changing before this one is among them
+                let mut viewers = accepted.lock().expect("broadcasting doesn't panic");
+                // The hello goes first, and can't fill up a new channel
+                let _ = messages.try_send(greeting.lock().expect("broadcasting doesn't panic").clone());
+                thread::spawn(move || send_messages(stream, receiver));
+                viewers.push(Viewer { messages, needs_keyframe: true });
+            }
+        });
+        Ok(Broadcaster { viewers, hello, keyframe_every, previous: None, last_keyframe: 0 })
+    }
+
+    /// Whether anyone is watching, otherwise there's no need to read the cells for `broadcast`
+    pub fn has_viewers(&self) -> bool {
+        !self.viewers.lock().expect("accepting viewers doesn't panic").is_empty()
+    }
+
+    /// Tells every viewer the grid or colours changed, along with viewers that connect from now on. Viewers that are
+    /// too far behind to take the news are dropped.
+    pub fn set_hello(&mut self, hello: Hello) {
+        let mut viewers = self.viewers.lock().expect("accepting viewers doesn't panic");
+        let encoded: Arc<[u8]> = Message::Hello(hello).encode().into();
+        *self.hello.lock().expect("accepting viewers doesn't panic") = encoded.clone();
+        viewers.retain(|viewer| viewer.messages.try_send(encoded.clone()).is_ok());
+    }
+
+    /// Sends `cells`, the world at `generation`, to every viewer. Frames can skip generations, but have to be sent in
+    /// order.
+    pub fn broadcast(&mut self, generation: u64, cells: &[Cell]) {
+        let mut viewers = self.viewers.lock().expect("accepting viewers doesn't panic");
+        if viewers.is_empty() {
+            // Nobody would see a delta against these
+            self.previous = None;
+            return;
+        }
+
+        let keyframe_due = match &self.previous {
+            Some((previous_generation, previous)) => {
+                previous.len() != cells.len() || generation < *previous_generation || generation >= self.last_keyframe + self.keyframe_every
+            }
+            None => true,
+        };
+        if keyframe_due {
+            self.last_keyframe = generation;
+            for viewer in viewers.iter_mut() {
+                viewer.needs_keyframe = true;
+            }
+        }
+        let encode = |frame: Frame| -> Arc<[u8]> { Message::Frame { generation, frame }.encode().into() };
+        let keyframe = viewers.iter().any(|viewer| viewer.needs_keyframe).then(|| encode(Frame::keyframe(cells)));
+        let delta = match &self.previous {
+            Some((_, previous)) if viewers.iter().any(|viewer| !viewer.needs_keyframe) => Some(encode(Frame::delta(previous, cells))),
+            _ => None,
+        };
+
+        viewers.retain_mut(|viewer| {
+            let message = if viewer.needs_keyframe { &keyframe } else { &delta };
+            let message = message.clone().expect("encoded for every viewer that needs it");
+            match viewer.messages.try_send(message) {
+                Ok(()) => {
+                    viewer.needs_keyframe = false;
+                    true
+                }
+                // It missed this frame, so the next delta wouldn't apply
+                Err(TrySendError::Full(_)) => {
+                    viewer.needs_keyframe = true;
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+        match &mut self.previous {
+            Some((previous_generation, previous)) if previous.len() == cells.len() => {
+                *previous_generation = generation;
+                previous.copy_from_slice(cells);
+            }
+            _ => self.previous = Some((generation, cells.to_vec())),
+        }
+    }
+}
+
+/// Writes the messages for a viewer until it disconnects, which drops `messages` so the broadcaster forgets it
+fn send_messages(mut stream: TcpStream, messages: Receiver<Arc<[u8]>>) {
+    for message in messages {
+        if stream.write_all(&message).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO_4: Hello = Hello { width: 4, grid: Grid::Hex, colors: [[255, 255, 255, 255], [0, 0, 0, 255]] };
+
+    fn cells(pattern: &str) -> Vec<Cell> {
+        pattern.chars().map(|c| if c == 'o' { Cell::Alive } else { Cell::Dead }).collect()
+    }
+
+    fn read(bytes: &[u8]) -> io::Result<Message> {
+        Message::read(&mut &bytes[..], Some(&HELLO_4))
+    }
+
+    /// A message with `body` after its tag and the length in front
+    fn message(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut bytes = ((body.len() + 1) as u32).to_le_bytes().to_vec();
+        bytes.push(tag);
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    fn varints(values: &[u64]) -> Vec<u8> {
+        let mut bytes = 7u64.to_le_bytes().to_vec();
+        for value in values {
+            write_varint(&mut bytes, *value);
+        }
+        bytes
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let hello = Message::Hello(HELLO_4);
+        assert_eq!(Message::read(&mut &hello.encode()[..], None).unwrap(), hello);
+
+        for pattern in ["................", "oooooooooooooooo", "o..oo...ooo....o"] {
+            let keyframe = Message::Frame { generation: 3, frame: Frame::keyframe(&cells(pattern)) };
+            assert_eq!(read(&keyframe.encode()).unwrap(), keyframe);
+        }
+
+        let delta = Message::Frame { generation: u64::MAX, frame: Frame::Delta(vec![0..1, 200..330, 100_000..100_001]) };
+        assert_eq!(read(&delta.encode()).unwrap(), delta);
+    }
+
+    #[test]
+    fn deltas_reproduce_the_new_cells() {
+        let (previous, next) = (cells("o..oo...ooo....o"), cells(".o.o.oo.o.oooooo"));
+        let delta = Frame::delta(&previous, &next);
+        assert_eq!(delta, Frame::Delta(vec![0..2, 4..7, 9..10, 11..15]));
+
+        let mut viewed = previous.clone();
+        delta.apply(&mut viewed).unwrap();
+        assert_eq!(viewed, next);
+        Frame::delta(&next, &next).apply(&mut viewed).unwrap();
+        assert_eq!(viewed, next);
+    }
+
+    #[test]
+    fn truncated_messages_are_rejected() {
+        let keyframe = Message::Frame { generation: 1, frame: Frame::keyframe(&cells("o..oo...ooo....o")) }.encode();
+        assert!(read(&keyframe[..keyframe.len() - 1]).is_err());
+        // A complete message whose cells stop early
+        assert!(read(&message(KEYFRAME, &varints(&[1, 2]))).is_err());
+        assert!(read(&message(DELTA, &varints(&[1]))).is_err());
+        assert!(read(&message(DELTA, &[0x80])).is_err());
+        assert!(read(&message(HELLO, MAGIC)).is_err());
+    }
+
+    #[test]
+    fn oversized_messages_are_rejected() {
+        assert!(read(&(MAX_MESSAGE_LEN + 1).to_le_bytes()).is_err());
+        assert!(read(&message(KEYFRAME, &varints(&[10, 7]))).is_err());
+        for width in [0, MAX_WIDTH + 1, u32::MAX] {
+            let hello = Message::Hello(Hello { width, ..HELLO_4 }).encode();
+            assert!(Message::read(&mut &hello[..], None).is_err());
+        }
+        // A varint can't be longer than 10 bytes
+        assert!(read(&message(DELTA, &[&7u64.to_le_bytes()[..], &[0xff; 11]].concat())).is_err());
+    }
+
+    #[test]
+    fn overflowing_messages_are_rejected() {
+        assert!(read(&message(KEYFRAME, &varints(&[1, u64::MAX]))).is_err());
+        assert!(read(&message(DELTA, &varints(&[u64::MAX, 1]))).is_err());
+        assert!(read(&message(DELTA, &varints(&[1, 1, u64::MAX, 0]))).is_err());
+
+        // Only the last run used to be checked against the world
+        let mut viewed = cells("................");
+        assert!(Frame::Delta(vec![10..20, 15..16]).apply(&mut viewed).is_err());
+        assert!(Frame::Delta(vec![Range { start: 3, end: 2 }]).apply(&mut viewed).is_err());
+    }
+}
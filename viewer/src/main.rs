@@ -0,0 +1,189 @@
+use std::{io, net::TcpStream, sync::{mpsc::{self, Receiver, Sender}, Mutex}, thread};
+
+use bevy::{prelude::*, render::{render_asset::RenderAssetUsages, render_resource::{Extent3d, TextureDimension, TextureFormat}, texture::ImageSampler}, window::PrimaryWindow};
+use biosim_core::{hex_grid::Grid, render::BACKGROUND_COLOR, util::DOption, world::{get_index, Cell}};
+use biosim_stream::{Frame, Hello, Message};
+use clap::Parser;
+
+/// The image is scaled up to about this many pixels across, so small worlds don't look blurry when the window is
+/// larger than them
+const IMAGE_SIZE: usize = 1024;
+
+/// Watches a world streamed by `biosim-rust --stream-port <port>`, which can be running in another process or in a
+/// headless run, without simulating it again
+#[derive(Parser, Debug)]
+#[command(name = "biosim-viewer", version, about)]
+struct Cli {
+    /// Where the app streams, `127.0.0.1:<stream.port>`
+    #[arg(default_value = "127.0.0.1:7879")]
+    address: String,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let stream = TcpStream::connect(&cli.address).unwrap_or_else(|e| {
+        eprintln!("error: couldn't connect to {}: {e}", cli.address);
+        std::process::exit(1);
+    });
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || receive_messages(stream, sender));
+
+    let window = Window { title: format!("biosim viewer - {}", cli.address), ..default() };
+    App::new()
+        .add_plugins(DefaultPlugins.set(WindowPlugin { primary_window: Some(window), ..default() }))
+        .insert_resource(ClearColor(linear_color(BACKGROUND_COLOR)))
+        .insert_resource(View { messages: Mutex::new(receiver), hello: None, cells: Vec::new(), generation: None, pixels: Vec::new(), status: "Waiting for the first frame".to_string() })
+        .add_systems(Startup, setup)
+        .add_systems(Update, (show_messages, fit_to_window, update_status_text).chain())
+        .run();
+}
+
+/// Reads messages until the stream ends, which is passed on as an error too
+fn receive_messages(mut stream: TcpStream, messages: Sender<io::Result<Message>>) {
+    let mut hello = None;
+    loop {
+        let message = Message::read(&mut stream, hello.as_ref());
+        if let Ok(Message::Hello(new_hello)) = &message {
+            hello = Some(*new_hello);
+        }
+        let failed = message.is_err();
+        if messages.send(message).is_err() || failed {
+            return;
+        }
+    }
+}
+
+fn linear_color([r, g, b, a]: [f32; 4]) -> Color {
+    Color::rgba_linear(r, g, b, a)
+}
+
+/// The world as it was last streamed
+#[derive(Resource)]
+struct View {
+    messages: Mutex<Receiver<io::Result<Message>>>,
+    hello: Option<Hello>,
+    cells: Vec<Cell>,
+    generation: Option<u64>,
+    /// The index of the cell each pixel of the image shows, if any, which only changes with the hello
+    pixels: Vec<Option<usize>>,
+    status: String,
+}
+
+#[derive(Component)]
+struct WorldSprite;
+
+#[derive(Component)]
+struct StatusText;
+
+fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    commands.spawn(Camera2dBundle::default());
+    let image = images.add(Image::default());
+    commands.spawn((SpriteBundle { texture: image, ..default() }, WorldSprite));
+    let style = Style { position_type: PositionType::Absolute, left: Val::Px(8.0), top: Val::Px(8.0), ..default() };
+    commands.spawn((TextBundle::from_section("", TextStyle { font_size: 20.0, ..default() }).with_style(style), StatusText));
+}
+
+/// How large the image of a world `width` cells wide is, and which cell each of its pixels shows. Hex worlds are laid
+/// out like the app draws them, in `width / 2` rows of hexels.
+fn layout(width: usize, grid: Grid) -> (UVec2, Vec<Option<usize>>) {
+    let rows = match grid {
+        Grid::Rect => width,
+        Grid::Hex => (width / 2).max(1),
+    };
+    let scale = (IMAGE_SIZE / (rows as f32 * grid.width_multiplier()) as usize).max(1);
+    let size = UVec2::new((rows as f32 * grid.width_multiplier()) as u32 * scale as u32, (rows * scale) as u32);
+    let pixels = (0..size.y).flat_map(|row| (0..size.x).map(move |column| (column, row))).map(|(column, row)| {
+        // Image rows go down but uvs go up
+        let (u, v) = ((column as f32 + 0.5) / size.x as f32, 1.0 - (row as f32 + 0.5) / size.y as f32);
+        match grid.coord_at(width, u, v) {
+            DOption(true, coord) => Some(get_index(coord, width)),
+            DOption(false, _) => None,
+        }
+    }).collect();
+    (size, pixels)
+}
+
+/// Applies every message that came in since the last frame, and redraws the image if the world changed
+fn show_messages(mut view: ResMut<View>, mut images: ResMut<Assets<Image>>, sprites: Query<&Handle<Image>, With<WorldSprite>>) {
+    let messages: Vec<io::Result<Message>> = view.messages.lock().expect("receiving doesn't panic").try_iter().collect();
+    if messages.is_empty() {
+        return;
+    }
+    let Some(image) = sprites.get_single().ok().and_then(|handle| images.get_mut(handle)) else {
+        return;
+    };
+
+    for message in messages {
+        match message {
+            Ok(Message::Hello(hello)) => {
+                let width = hello.width as usize;
+                let (size, pixels) = layout(width, hello.grid);
+                if view.cells.len() != width * width {
+                    view.cells = vec![Cell::Dead; width * width];
+                }
+                view.pixels = pixels;
+                view.hello = Some(hello);
+                let background = linear_color(BACKGROUND_COLOR).as_rgba_u8();
+                *image = Image::new_fill(Extent3d { width: size.x, height: size.y, depth_or_array_layers: 1 }, TextureDimension::D2, &background, TextureFormat::Rgba8UnormSrgb, RenderAssetUsages::default());
+                image.sampler = ImageSampler::nearest();
+            }
+            Ok(Message::Frame { generation, frame }) => {
+                let view = &mut *view;
+                if let Err(e) = frame.apply(&mut view.cells) {
+                    view.status = format!("The stream is broken: {e}");
+                    return;
+                }
+                // Deltas mean nothing until the first keyframe
+                if view.generation.is_none() && matches!(frame, Frame::Keyframe(_)) {
+                    view.status.clear();
+                }
+                if view.generation.is_some() || matches!(frame, Frame::Keyframe(_)) {
+                    view.generation = Some(generation);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => view.status = "The app stopped streaming".to_string(),
+            Err(e) => view.status = format!("The stream is broken: {e}"),
+        }
+    }
+
+    let (Some(hello), Some(_)) = (view.hello, view.generation) else {
+        return;
+    };
+    for (pixel, cell) in image.data.chunks_exact_mut(4).zip(&view.pixels) {
+        if let Some(index) = cell {
+            pixel.copy_from_slice(&hello.colors[view.cells[*index] as usize]);
+        }
+    }
+}
+
+/// Scales the world to the largest size that fits in the window
+fn fit_to_window(windows: Query<&Window, With<PrimaryWindow>>, images: Res<Assets<Image>>, mut sprites: Query<(&Handle<Image>, &mut Sprite), With<WorldSprite>>) {
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
+    for (handle, mut sprite) in &mut sprites {
+        let Some(image) = images.get(handle) else {
+            continue;
+        };
+        let size = image.size_f32();
+        let scale = (window.width() / size.x).min(window.height() / size.y);
+        let fitted = Some(size * scale);
+        if sprite.custom_size != fitted {
+            sprite.custom_size = fitted;
+        }
+    }
+}
+
+fn update_status_text(view: Res<View>, mut texts: Query<&mut Text, With<StatusText>>) {
+    if !view.is_changed() {
+        return;
+    }
+    let status = match view.generation {
+        Some(generation) if view.status.is_empty() => format!("Generation {generation}"),
+        Some(generation) => format!("Generation {generation}\n{}", view.status),
+        None => view.status.clone(),
+    };
+    for mut text in &mut texts {
+        text.sections[0].value.clone_from(&status);
+    }
+}